@@ -0,0 +1,71 @@
+// notify_supply throughput with many concurrent tasks on one SXServiceClient.
+// Runs MockSynerexServer in process, so no external server is needed:
+//
+//   cargo bench --bench notify_supply
+//
+// A supply subscription is kept open during measurement to show that streams
+// don't block unary calls.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use synerex_api::api;
+use sxutil::testing::{MockMethod, MockSynerexServer};
+
+// messages sent by each task
+const MESSAGES: u64 = 500;
+const CONCURRENCY: [u64; 5] = [1, 8, 32, 128, 512];
+const CHANNEL_TYPE: u32 = 1;
+
+fn supply_opts() -> sxutil::SupplyOpts {
+    sxutil::SupplyOpts {
+        id: 0,
+        target: 0,
+        name: String::from("bench"),
+        json: String::from("{}"),
+        cdata: api::Content { entity: vec![0; 64] },
+    }
+}
+
+async fn run(client: Arc<sxutil::SXServiceClient>, tasks: u64) -> Duration {
+    let started = Instant::now();
+    let workers: Vec<_> = (0..tasks).map(|_| {
+        let client = Arc::clone(&client);
+        tokio::spawn(async move {
+            for _ in 0..MESSAGES {
+                client.notify_supply(supply_opts()).await.expect("notify_supply");
+            }
+        })
+    }).collect();
+    for worker in workers {
+        worker.await.unwrap();
+    }
+    started.elapsed()
+}
+
+#[tokio::main]
+async fn main() {
+    let server = MockSynerexServer::start().await.expect("start mock server");
+    let sxclient = server.connect().await.expect("connect mock server");
+    let client = Arc::new(sxutil::new_sx_service_client(sxclient, CHANNEL_TYPE, String::from("{}")).await);
+
+    // subscription open during measurement
+    let spcb = sxutil::supply_handler(|_, _| async {});
+    let subscriber = {
+        let client = Arc::clone(&client);
+        tokio::spawn(async move { client.subscribe_supply(&spcb).await })
+    };
+
+    run(Arc::clone(&client), 8).await; // warm up
+    server.clear_requests();
+    println!("{:>8} {:>10} {:>12} {:>14}", "tasks", "messages", "elapsed", "msgs/sec");
+    for tasks in CONCURRENCY {
+        let elapsed = run(Arc::clone(&client), tasks).await;
+        let messages = tasks * MESSAGES;
+        assert_eq!(server.requests_of(MockMethod::NotifySupply).len() as u64, messages);
+        server.clear_requests(); // mock server records every request
+        println!("{:>8} {:>10} {:>12.3?} {:>14.0}", tasks, messages, elapsed, messages as f64 / elapsed.as_secs_f64());
+    }
+    subscriber.abort();
+    server.shutdown().await;
+}
@@ -0,0 +1,93 @@
+// synerex-server is Synerex server on SynerexBroker (flags are the same as Go version's synerex-server)
+//
+//   synerex-server -nodesrv 127.0.0.1:9990 -servaddr 127.0.0.1 -port 10000 -name SynerexServer
+//
+// with empty nodesrv (-nodesrv "") it runs without node server.
+
+#[macro_use]
+extern crate log;
+
+use std::net::SocketAddr;
+use std::process::exit;
+
+use sxutil::SynerexBroker;
+
+struct Args {
+    nodesrv: String,
+    servaddr: String,
+    port: u16,
+    name: String,
+}
+
+const USAGE: &str = "usage: synerex-server [-nodesrv host:port] [-servaddr host] [-port port] [-name name]";
+
+// accepts "-flag value", "--flag value" and "-flag=value"
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        nodesrv: String::from("127.0.0.1:9990"),
+        servaddr: String::from("127.0.0.1"),
+        port: 10000,
+        name: String::from("SynerexServer"),
+    };
+    let mut argv = std::env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        let flag = arg.trim_start_matches('-');
+        let (flag, value) = match flag.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), value.to_string()),
+            None if flag == "h" || flag == "help" => return Err(String::from(USAGE)),
+            None => (flag.to_string(), argv.next().ok_or_else(|| format!("flag needs an argument: {}", arg))?),
+        };
+        match flag.as_str() {
+            "nodesrv" => args.nodesrv = value,
+            "servaddr" => args.servaddr = value,
+            "port" => args.port = value.parse().map_err(|_| format!("invalid port: {}", value))?,
+            "name" => args.name = value,
+            _ => return Err(format!("unknown flag: {}\n{}", arg, USAGE)),
+        }
+    }
+    Ok(args)
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(2);
+        },
+    };
+
+    let broker = SynerexBroker::new();
+    let keepalive = if args.nodesrv.is_empty() {
+        None
+    } else {
+        let server_info = format!("{}:{}", args.servaddr, args.port);
+        match broker.register_node(args.nodesrv.clone(), args.name.clone(), server_info).await {
+            Ok(keepalive) => Some(keepalive),
+            Err(err) => {
+                error!("Can't register to node server {}: {}", args.nodesrv, err);
+                exit(1);
+            },
+        }
+    };
+
+    let addr = SocketAddr::from(([0, 0, 0, 0], args.port));
+    let shutdown = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            error!("Can't wait for Ctrl-C: {}", err);
+        }
+    };
+    let served = broker.serve_with_shutdown(addr, shutdown).await;
+
+    if let Some(keepalive) = keepalive {
+        if let Err(err) = keepalive.stop().await {
+            error!("Can't unregister from node server: {}", err);
+        }
+    }
+    if let Err(err) = served {
+        error!("Serve error: {}", err);
+        exit(1);
+    }
+}
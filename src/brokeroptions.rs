@@ -0,0 +1,35 @@
+use core::time::Duration;
+
+// messages buffered per subscription stream
+const DEFAULT_STREAM_BUFFER: usize = 1024;
+// demand / supply ids remembered for proposals and selections
+const DEFAULT_ROUTE_CAPACITY: usize = 100_000;
+// closed mbus ids remembered for MbusState reporting
+const DEFAULT_CLOSED_MBUS_CAPACITY: usize = 10_000;
+const DEFAULT_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+// BrokerOptions configures SynerexBroker
+#[derive(Debug, Clone)]
+pub struct BrokerOptions {
+    // messages buffered per subscription stream.
+    // messages to a full stream (slow subscriber) are dropped and counted in BrokerStats.
+    pub stream_buffer: usize,
+    // number of latest demand / supply ids whose sender is remembered
+    // to deliver proposals and selections only to the sender
+    pub route_capacity: usize,
+    // number of closed mbus ids reported as CLOSED (older ones are INVALID)
+    pub closed_mbus_capacity: usize,
+    // interval to add message count to keepalive of node server
+    pub report_interval: Duration,
+}
+
+impl Default for BrokerOptions {
+    fn default() -> Self {
+        BrokerOptions {
+            stream_buffer: DEFAULT_STREAM_BUFFER,
+            route_capacity: DEFAULT_ROUTE_CAPACITY,
+            closed_mbus_capacity: DEFAULT_CLOSED_MBUS_CAPACITY,
+            report_interval: DEFAULT_REPORT_INTERVAL,
+        }
+    }
+}
@@ -0,0 +1,457 @@
+use std::collections::{HashMap, VecDeque};
+
+use tonic::Status;
+
+use synerex_api::api::{self, gateway_msg::MsgOneof, mbus_opt::MbusType, mbus_state::MbusStatus};
+
+use crate::BrokerOptions;
+use crate::serving::{Delivery, Subscribers, SubscriptionStream};
+
+// node id embedded in snowflake id (same bits as node of Go version's snowflake)
+pub(crate) fn node_of(id: u64) -> i32 {
+    ((id >> 12) & 0x3ff) as i32
+}
+
+// RecentIds remembers values of the latest ids up to capacity
+struct RecentIds<V> {
+    map: HashMap<u64, V>,
+    order: VecDeque<u64>,
+    capacity: usize,
+}
+
+impl<V> RecentIds<V> {
+    fn new(capacity: usize) -> Self {
+        RecentIds { map: HashMap::new(), order: VecDeque::new(), capacity: capacity.max(1) }
+    }
+
+    fn insert(&mut self, id: u64, value: V) {
+        if self.map.insert(id, value).is_none() {
+            self.order.push_back(id);
+        }
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.map.remove(&oldest);
+            }
+        }
+    }
+
+    fn get(&self, id: u64) -> Option<&V> {
+        self.map.get(&id)
+    }
+
+    fn contains(&self, id: u64) -> bool {
+        self.map.contains_key(&id)
+    }
+}
+
+// BrokerState is routing state of SynerexBroker.
+// All operations are non-blocking (full streams drop messages), so it is used under a plain mutex.
+pub(crate) struct BrokerState {
+    demands: Subscribers<api::Demand>,
+    supplies: Subscribers<api::Supply>,
+    mbus_msgs: Subscribers<api::MbusMsg>,
+    gateways: Subscribers<api::GatewayMsg>,
+    gateway_infos: HashMap<u64, api::GatewayInfo>,
+    // sender of demand / supply id (to route proposals and selections)
+    senders: RecentIds<u64>,
+    mbuses: HashMap<u64, api::MbusOpt>,
+    closed_mbuses: RecentIds<()>,
+    // src_synerex_id of messages to gateways (node id of broker after registration)
+    pub(crate) synerex_id: Option<u64>,
+    pub(crate) delivered: u64,
+    pub(crate) dropped: u64,
+}
+
+impl BrokerState {
+    pub(crate) fn new(opts: &BrokerOptions) -> Self {
+        BrokerState {
+            demands: Subscribers::new(opts.stream_buffer),
+            supplies: Subscribers::new(opts.stream_buffer),
+            mbus_msgs: Subscribers::new(opts.stream_buffer),
+            gateways: Subscribers::new(opts.stream_buffer),
+            gateway_infos: HashMap::new(),
+            senders: RecentIds::new(opts.route_capacity),
+            mbuses: HashMap::new(),
+            closed_mbuses: RecentIds::new(opts.closed_mbus_capacity),
+            synerex_id: None,
+            delivered: 0,
+            dropped: 0,
+        }
+    }
+
+    fn count(&mut self, delivery: Delivery) {
+        self.delivered += delivery.delivered as u64;
+        self.dropped += delivery.dropped as u64;
+        if delivery.dropped > 0 {
+            warn!("SynerexBroker: dropped message for {} slow subscribers", delivery.dropped);
+        }
+    }
+
+    // sender of demand / supply when known
+    fn sender_of(&self, id: u64) -> Option<u64> {
+        self.senders.get(id).copied()
+    }
+
+    pub(crate) fn subscribe_demand(&mut self, ch: &api::Channel) -> SubscriptionStream<api::Demand> {
+        self.demands.subscribe(ch.client_id, ch.channel_type as u64)
+    }
+
+    pub(crate) fn subscribe_supply(&mut self, ch: &api::Channel) -> SubscriptionStream<api::Supply> {
+        self.supplies.subscribe(ch.client_id, ch.channel_type as u64)
+    }
+
+    // NotifyDemand / ProposeDemand: proposal to known supply goes only to its sender
+    pub(crate) fn send_demand(&mut self, dm: &api::Demand, propose: bool) {
+        self.senders.insert(dm.id, dm.sender_id);
+        let target = if propose { self.sender_of(dm.target_id) } else { None };
+        let delivery = self.demands.send(dm.channel_type as u64, |cid| target.is_none() || target == Some(cid), dm);
+        self.count(delivery);
+        self.forward_to_gateways(dm.channel_type, api::MsgType::Demand, MsgOneof::Demand(dm.clone()));
+    }
+
+    // NotifySupply / ProposeSupply: proposal to known demand goes only to its sender
+    pub(crate) fn send_supply(&mut self, sp: &api::Supply, propose: bool) {
+        self.senders.insert(sp.id, sp.sender_id);
+        let target = if propose { self.sender_of(sp.target_id) } else { None };
+        let delivery = self.supplies.send(sp.channel_type as u64, |cid| target.is_none() || target == Some(cid), sp);
+        self.count(delivery);
+        self.forward_to_gateways(sp.channel_type, api::MsgType::Supply, MsgOneof::Supply(sp.clone()));
+    }
+
+    // selection of supply is delivered to its sender as Demand with private mbus between them
+    // (None: sender of the target is unknown)
+    pub(crate) fn select_supply(&mut self, mut dm: api::Demand, mbus_id: u64) -> Option<u64> {
+        let owner = self.sender_of(dm.target_id)?;
+        self.open_private_mbus(mbus_id, vec![dm.sender_id, owner]);
+        dm.mbus_id = mbus_id;
+        let delivery = self.demands.send(dm.channel_type as u64, |cid| cid == owner, &dm);
+        self.count(delivery);
+        Some(mbus_id)
+    }
+
+    // selection of demand is delivered to its sender as Supply with private mbus between them
+    pub(crate) fn select_demand(&mut self, mut sp: api::Supply, mbus_id: u64) -> Option<u64> {
+        let owner = self.sender_of(sp.target_id)?;
+        self.open_private_mbus(mbus_id, vec![sp.sender_id, owner]);
+        sp.mbus_id = mbus_id;
+        let delivery = self.supplies.send(sp.channel_type as u64, |cid| cid == owner, &sp);
+        self.count(delivery);
+        Some(mbus_id)
+    }
+
+    fn open_private_mbus(&mut self, mbus_id: u64, mut subscribers: Vec<u64>) {
+        subscribers.dedup();
+        self.mbuses.insert(mbus_id, api::MbusOpt { mbus_type: MbusType::Private as i32, subscribers });
+    }
+
+    // Confirm joins sender to the private mbus of selection (false when mbus is not open)
+    pub(crate) fn confirm(&mut self, tg: &api::Target) -> bool {
+        let mbus_id = if self.mbuses.contains_key(&tg.mbus_id) { tg.mbus_id } else { tg.target_id };
+        match self.mbuses.get_mut(&mbus_id) {
+            Some(opt) => {
+                if opt.mbus_type == MbusType::Private as i32 && !opt.subscribers.contains(&tg.sender_id) {
+                    opt.subscribers.push(tg.sender_id);
+                }
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub(crate) fn create_mbus(&mut self, mbus_id: u64, opt: api::MbusOpt) {
+        self.mbuses.insert(mbus_id, opt);
+    }
+
+    // closing mbus ends its subscriptions normally
+    pub(crate) fn close_mbus(&mut self, mbus_id: u64) -> bool {
+        if self.mbuses.remove(&mbus_id).is_none() {
+            return false;
+        }
+        self.closed_mbuses.insert(mbus_id, ());
+        self.mbus_msgs.close(|_, key| key == mbus_id);
+        true
+    }
+
+    fn allowed(opt: &api::MbusOpt, client_id: u64) -> bool {
+        opt.mbus_type != MbusType::Private as i32 || opt.subscribers.contains(&client_id)
+    }
+
+    // private mbus can be subscribed only by its subscribers
+    pub(crate) fn subscribe_mbus(&mut self, mb: &api::Mbus) -> Result<SubscriptionStream<api::MbusMsg>, Box<Status>> {
+        let opt = self.mbuses.get(&mb.mbus_id)
+            .ok_or_else(|| Status::not_found(format!("no such mbus {}", mb.mbus_id)))?;
+        if !Self::allowed(opt, mb.client_id) {
+            return Err(Box::new(Status::permission_denied(format!("mbus {} is private", mb.mbus_id))));
+        }
+        Ok(self.mbus_msgs.subscribe(mb.client_id, mb.mbus_id))
+    }
+
+    // SendMbusMsg delivers msg to subscribers except sender (only to target_id if given)
+    pub(crate) fn send_mbus_msg(&mut self, msg: &api::MbusMsg) -> Result<(), String> {
+        let opt = self.mbuses.get(&msg.mbus_id).ok_or_else(|| format!("no such mbus {}", msg.mbus_id))?;
+        if !Self::allowed(opt, msg.sender_id) {
+            return Err(format!("{} is not a subscriber of mbus {}", msg.sender_id, msg.mbus_id));
+        }
+        let (sender, target) = (msg.sender_id, msg.target_id);
+        let delivery = self.mbus_msgs.send(msg.mbus_id, |cid| cid != sender && (target == 0 || cid == target), msg);
+        self.count(delivery);
+        Ok(())
+    }
+
+    pub(crate) fn mbus_state(&mut self, mbus_id: u64) -> api::MbusState {
+        let subscribers = self.mbus_msgs.clients(mbus_id);
+        let status = if self.mbuses.contains_key(&mbus_id) {
+            if subscribers.is_empty() { MbusStatus::Intialized } else { MbusStatus::Subscribers }
+        } else if self.closed_mbuses.contains(mbus_id) {
+            MbusStatus::Closed
+        } else {
+            MbusStatus::Invalid
+        };
+        api::MbusState { mbus_id, status: status as i32, subscribers }
+    }
+
+    pub(crate) fn subscribe_gateway(&mut self, gi: api::GatewayInfo) -> SubscriptionStream<api::GatewayMsg> {
+        let stream = self.gateways.subscribe(gi.client_id, 0);
+        let open = self.gateways.clients(0);
+        self.gateway_infos.retain(|cid, _| open.contains(cid));
+        self.gateway_infos.insert(gi.client_id, gi);
+        stream
+    }
+
+    // forwards to gateways of channel (gateway without channels receives all), except write only gateways
+    fn forward_to_gateways(&mut self, channel_type: u32, msg_type: api::MsgType, msg: MsgOneof) {
+        if self.gateway_infos.is_empty() {
+            return;
+        }
+        let msg = api::GatewayMsg { src_synerex_id: self.synerex_id.unwrap_or_default(), msg_type: msg_type as i32, msg_oneof: Some(msg) };
+        let infos = &self.gateway_infos;
+        let delivery = self.gateways.send(0, |cid| match infos.get(&cid) {
+            Some(gi) => gi.gateway_type != api::GatewayType::WriteOnly as i32
+                && (gi.channels.is_empty() || gi.channels.contains(&channel_type)),
+            None => false,
+        }, &msg);
+        self.count(delivery);
+    }
+
+    // message from gateway is delivered to local subscribers only (not forwarded to gateways again)
+    pub(crate) fn deliver_from_gateway(&mut self, msg: api::GatewayMsg) {
+        if self.synerex_id == Some(msg.src_synerex_id) {
+            debug!("SynerexBroker: drop looped gateway message {:?}", msg);
+            return;
+        }
+        let delivery = match msg.msg_oneof {
+            Some(MsgOneof::Demand(dm)) => {
+                self.senders.insert(dm.id, dm.sender_id);
+                let target = self.sender_of(dm.target_id);
+                self.demands.send(dm.channel_type as u64, |cid| target.is_none() || target == Some(cid), &dm)
+            },
+            Some(MsgOneof::Supply(sp)) => {
+                self.senders.insert(sp.id, sp.sender_id);
+                let target = self.sender_of(sp.target_id);
+                self.supplies.send(sp.channel_type as u64, |cid| target.is_none() || target == Some(cid), &sp)
+            },
+            Some(MsgOneof::MbusMsg(mm)) if self.mbuses.contains_key(&mm.mbus_id) => {
+                let (sender, target) = (mm.sender_id, mm.target_id);
+                self.mbus_msgs.send(mm.mbus_id, |cid| cid != sender && (target == 0 || cid == target), &mm)
+            },
+            other => {
+                debug!("SynerexBroker: ignore gateway message {:?}", other);
+                Delivery::default()
+            },
+        };
+        self.count(delivery);
+    }
+
+    pub(crate) fn close_demand_channel(&mut self, ch: &api::Channel) -> usize {
+        self.demands.close(|cid, key| cid == ch.client_id && key == ch.channel_type as u64)
+    }
+
+    pub(crate) fn close_supply_channel(&mut self, ch: &api::Channel) -> usize {
+        self.supplies.close(|cid, key| cid == ch.client_id && key == ch.channel_type as u64)
+    }
+
+    // ends all streams of clients accepted by `closing`
+    pub(crate) fn close_clients<F: Fn(u64) -> bool>(&mut self, closing: F) -> usize {
+        self.gateway_infos.retain(|cid, _| !closing(*cid));
+        self.demands.close(|cid, _| closing(cid))
+            + self.supplies.close(|cid, _| closing(cid))
+            + self.mbus_msgs.close(|cid, _| closing(cid))
+            + self.gateways.close(|cid, _| closing(cid))
+    }
+
+    // (demand, supply, mbus, gateway) subscribers and open mbuses
+    pub(crate) fn counts(&mut self) -> (usize, usize, usize, usize, usize) {
+        (self.demands.len(), self.supplies.len(), self.mbus_msgs.len(), self.gateways.len(), self.mbuses.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{FutureExt, Stream, StreamExt};
+
+    // client ids generated by provider nodes 12 and 13
+    const A: u64 = 12 << 12 | 1;
+    const B: u64 = 12 << 12 | 2;
+    const C: u64 = 13 << 12 | 1;
+
+    fn channel(client_id: u64, channel_type: u32) -> api::Channel {
+        api::Channel { client_id, channel_type, ..Default::default() }
+    }
+
+    fn demand(id: u64, sender_id: u64, target_id: u64) -> api::Demand {
+        api::Demand { id, sender_id, target_id, channel_type: 1, ..Default::default() }
+    }
+
+    fn supply(id: u64, sender_id: u64, target_id: u64) -> api::Supply {
+        api::Supply { id, sender_id, target_id, channel_type: 1, ..Default::default() }
+    }
+
+    fn mbus(mbus_id: u64, client_id: u64) -> api::Mbus {
+        api::Mbus { mbus_id, client_id, ..Default::default() }
+    }
+
+    fn mbus_msg(mbus_id: u64, sender_id: u64) -> api::MbusMsg {
+        api::MbusMsg { mbus_id, sender_id, ..Default::default() }
+    }
+
+    // message already put to stream (None: nothing to read, Some(None): stream ended)
+    fn ready<T, S: Stream<Item = Result<T, Status>> + Unpin>(stream: &mut S) -> Option<Option<T>> {
+        stream.next().now_or_never().map(|msg| msg.map(Result::unwrap))
+    }
+
+    #[test]
+    fn node_of_snowflake_id() {
+        assert_eq!(node_of(A), 12);
+        assert_eq!(node_of(C), 13);
+        assert_eq!(node_of(1023 << 12 | 4095 | 1 << 22), 1023);
+    }
+
+    #[test]
+    fn proposal_goes_to_sender_of_target() {
+        let mut state = BrokerState::new(&BrokerOptions::default());
+        let mut streams: Vec<_> = [A, B, C].iter().map(|&cid| state.subscribe_supply(&channel(cid, 1))).collect();
+        let mut other_channel = state.subscribe_supply(&channel(A, 2));
+
+        state.send_demand(&demand(100, A, 0), false);
+        state.send_supply(&supply(200, B, 100), true);
+        assert_eq!(ready(&mut streams[0]).unwrap().unwrap().id, 200);
+        assert!(ready(&mut streams[1]).is_none());
+        assert!(ready(&mut streams[2]).is_none());
+
+        // notification and proposal to unknown target go to all subscribers of the channel
+        state.send_supply(&supply(201, B, 0), false);
+        state.send_supply(&supply(202, B, 999), true);
+        for stream in streams.iter_mut() {
+            assert_eq!(ready(stream).unwrap().unwrap().id, 201);
+            assert_eq!(ready(stream).unwrap().unwrap().id, 202);
+        }
+        assert!(ready(&mut other_channel).is_none());
+        assert_eq!(state.delivered, 7);
+    }
+
+    #[test]
+    fn full_stream_drops_messages() {
+        let mut state = BrokerState::new(&BrokerOptions { stream_buffer: 1, ..Default::default() });
+        let mut stream = state.subscribe_demand(&channel(A, 1));
+        state.send_demand(&demand(100, B, 0), false);
+        state.send_demand(&demand(101, B, 0), false);
+        assert_eq!((state.delivered, state.dropped), (1, 1));
+        assert_eq!(ready(&mut stream).unwrap().unwrap().id, 100);
+        assert!(ready(&mut stream).is_none());
+    }
+
+    #[test]
+    fn selection_opens_private_mbus() {
+        let mut state = BrokerState::new(&BrokerOptions::default());
+        let mut demands: Vec<_> = [A, B].iter().map(|&cid| state.subscribe_demand(&channel(cid, 1))).collect();
+        state.send_supply(&supply(200, B, 0), false);
+
+        assert_eq!(state.select_supply(demand(300, A, 999), 500), None); // unknown supply
+        assert_eq!(state.select_supply(demand(300, A, 200), 500), Some(500));
+        assert!(ready(&mut demands[0]).is_none());
+        let selection = ready(&mut demands[1]).unwrap().unwrap();
+        assert_eq!((selection.id, selection.mbus_id), (300, 500));
+
+        // only parties of the selection (and confirmed senders) can use the mbus
+        let mut streams: Vec<_> = [A, B].iter().map(|&cid| state.subscribe_mbus(&mbus(500, cid)).unwrap()).collect();
+        assert_eq!(state.subscribe_mbus(&mbus(500, C)).err().unwrap().code(), tonic::Code::PermissionDenied);
+        assert_eq!(state.subscribe_mbus(&mbus(501, A)).err().unwrap().code(), tonic::Code::NotFound);
+        assert!(state.send_mbus_msg(&mbus_msg(500, C)).is_err());
+
+        state.send_mbus_msg(&mbus_msg(500, A)).unwrap();
+        assert!(ready(&mut streams[0]).is_none()); // not to sender
+        assert_eq!(ready(&mut streams[1]).unwrap().unwrap().sender_id, A);
+
+        assert!(state.confirm(&api::Target { sender_id: C, mbus_id: 500, ..Default::default() }));
+        assert!(state.subscribe_mbus(&mbus(500, C)).is_ok());
+        assert!(!state.confirm(&api::Target { sender_id: C, mbus_id: 501, target_id: 501, ..Default::default() }));
+    }
+
+    #[test]
+    fn mbus_state_closed_and_invalid() {
+        let mut state = BrokerState::new(&BrokerOptions { closed_mbus_capacity: 1, ..Default::default() });
+        let status = |state: &mut BrokerState, mbus_id| MbusStatus::try_from(state.mbus_state(mbus_id).status).unwrap();
+        state.create_mbus(7, api::MbusOpt::default());
+        state.create_mbus(8, api::MbusOpt::default());
+        assert_eq!(status(&mut state, 7), MbusStatus::Intialized);
+
+        let mut stream = state.subscribe_mbus(&mbus(7, A)).unwrap();
+        assert_eq!(status(&mut state, 7), MbusStatus::Subscribers);
+        assert_eq!(state.mbus_state(7).subscribers, vec![A]);
+
+        assert!(state.close_mbus(7));
+        assert!(!state.close_mbus(7));
+        assert_eq!(ready(&mut stream), Some(None));
+        assert_eq!(status(&mut state, 7), MbusStatus::Closed);
+        assert_eq!(status(&mut state, 9), MbusStatus::Invalid);
+        assert!(state.send_mbus_msg(&mbus_msg(7, A)).is_err());
+
+        // older closed mbuses are forgotten
+        assert!(state.close_mbus(8));
+        assert_eq!(status(&mut state, 8), MbusStatus::Closed);
+        assert_eq!(status(&mut state, 7), MbusStatus::Invalid);
+    }
+
+    #[test]
+    fn close_channel_ends_only_its_stream() {
+        let mut state = BrokerState::new(&BrokerOptions::default());
+        let mut closing = state.subscribe_demand(&channel(A, 1));
+        let mut other_channel = state.subscribe_demand(&channel(A, 2));
+        let mut other_client = state.subscribe_demand(&channel(B, 1));
+        let mut supplies = state.subscribe_supply(&channel(A, 1));
+
+        assert_eq!(state.close_demand_channel(&channel(A, 1)), 1);
+        assert_eq!(state.close_demand_channel(&channel(A, 1)), 0);
+        assert_eq!(ready(&mut closing), Some(None));
+        assert!(ready(&mut other_channel).is_none());
+        assert!(ready(&mut other_client).is_none());
+        assert!(ready(&mut supplies).is_none());
+
+        assert_eq!(state.close_supply_channel(&channel(A, 1)), 1);
+        assert_eq!(ready(&mut supplies), Some(None));
+        assert_eq!(state.counts(), (2, 0, 0, 0, 0));
+    }
+
+    #[test]
+    fn provider_disconnect_closes_streams_of_node() {
+        let mut state = BrokerState::new(&BrokerOptions::default());
+        state.create_mbus(7, api::MbusOpt::default());
+        let mut closing = [
+            state.subscribe_demand(&channel(A, 1)),
+            state.subscribe_demand(&channel(B, 2)),
+        ];
+        let mut closing_supply = state.subscribe_supply(&channel(B, 1));
+        let mut closing_mbus = state.subscribe_mbus(&mbus(7, A)).unwrap();
+        let mut other_node = state.subscribe_demand(&channel(C, 1));
+
+        assert_eq!(state.close_clients(|cid| node_of(cid) == 12), 4);
+        for stream in closing.iter_mut() {
+            assert_eq!(ready(stream), Some(None));
+        }
+        assert_eq!(ready(&mut closing_supply), Some(None));
+        assert_eq!(ready(&mut closing_mbus), Some(None));
+        assert!(ready(&mut other_node).is_none());
+        assert_eq!(state.counts(), (1, 0, 0, 0, 1));
+    }
+}
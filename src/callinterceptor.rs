@@ -0,0 +1,319 @@
+use std::fmt;
+use std::str::FromStr;
+use std::future::Future;
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use tokio::task::JoinHandle;
+use tonic::metadata::{AsciiMetadataKey, AsciiMetadataValue};
+use tonic::service::{interceptor::InterceptedService, Interceptor};
+use tonic::transport::Channel;
+use tonic::{Request, Status};
+
+use crate::SxutilError;
+
+// SxChannel is the channel of synerex server / node server clients (every call passes CallInterceptor)
+pub type SxChannel = InterceptedService<Channel, CallInterceptor>;
+
+type TokenRefresher = Arc<dyn Fn() -> Result<String, Status> + Send + Sync>;
+type CallHook = Arc<dyn Fn(Request<()>) -> Result<Request<()>, Status> + Send + Sync>;
+
+// SharedToken is a token replaced by an (async) refresh task and read by every call without blocking
+#[derive(Clone)]
+pub struct SharedToken(Arc<ArcSwap<String>>);
+
+impl SharedToken {
+    pub fn new(token: String) -> Self {
+        SharedToken(Arc::new(ArcSwap::from_pointee(token)))
+    }
+
+    pub fn set(&self, token: String) {
+        self.0.store(Arc::new(token));
+    }
+
+    pub fn get(&self) -> Arc<String> {
+        self.0.load_full()
+    }
+
+    // SpawnRefresh replaces the token by refresh() every interval until the task is aborted
+    // (on error the current token is kept and refresh is tried again after interval)
+    pub fn spawn_refresh<F, Fut>(&self, interval: core::time::Duration, refresh: F) -> JoinHandle<()>
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<String, Status>> + Send + 'static,
+    {
+        let token = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                match refresh().await {
+                    Ok(refreshed) => token.set(refreshed),
+                    Err(status) => error!("sxutil: token refresh failed {}", status),
+                }
+            }
+        })
+    }
+}
+
+impl fmt::Debug for SharedToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SharedToken(..)") // don't print the token
+    }
+}
+
+// TokenSource gives bearer token for "authorization" header
+#[derive(Clone)]
+pub enum TokenSource {
+    Static(String),
+    // called on every call (cache the token inside, it must not block)
+    Refresher(TokenRefresher),
+    // current token of SharedToken (refreshed asynchronously by its owner)
+    Shared(SharedToken),
+}
+
+impl fmt::Debug for TokenSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenSource::Static(_) => write!(f, "Static(..)"), // don't print the token
+            TokenSource::Refresher(_) => write!(f, "Refresher(..)"),
+            TokenSource::Shared(_) => write!(f, "Shared(..)"),
+        }
+    }
+}
+
+impl PartialEq for TokenSource {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (TokenSource::Static(a), TokenSource::Static(b)) => a == b,
+            (TokenSource::Refresher(a), TokenSource::Refresher(b)) => Arc::ptr_eq(a, b),
+            (TokenSource::Shared(a), TokenSource::Shared(b)) => Arc::ptr_eq(&a.0, &b.0),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for TokenSource {}
+
+// CallInterceptor adds auth token and metadata to every call (including streaming subscriptions)
+#[derive(Clone, Default)]
+pub struct CallInterceptor {
+    token: Option<TokenSource>,
+    metadata: Vec<(AsciiMetadataKey, AsciiMetadataValue)>,
+    hook: Option<CallHook>,
+}
+
+impl CallInterceptor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // WithToken sends "authorization: Bearer <token>"
+    pub fn with_token(mut self, token: String) -> Self {
+        self.token = Some(TokenSource::Static(token));
+        self
+    }
+
+    // WithTokenRefresher asks refresher for the token on every call.
+    // refresher is sync and must not block, use with_shared_token for async refresh.
+    pub fn with_token_refresher<F>(mut self, refresher: F) -> Self
+    where
+        F: Fn() -> Result<String, Status> + Send + Sync + 'static,
+    {
+        self.token = Some(TokenSource::Refresher(Arc::new(refresher)));
+        self
+    }
+
+    // WithSharedToken sends current token of shared (e.g. updated by shared.spawn_refresh)
+    pub fn with_shared_token(mut self, shared: SharedToken) -> Self {
+        self.token = Some(TokenSource::Shared(shared));
+        self
+    }
+
+    // WithMetadata adds ASCII metadata (e.g. tenant header) to every call
+    pub fn with_metadata(mut self, key: &str, value: &str) -> Result<Self, SxutilError> {
+        let key = AsciiMetadataKey::from_str(key)
+            .map_err(|_| SxutilError::Config(format!("invalid metadata key: {}", key)))?;
+        let value = AsciiMetadataValue::try_from(value)
+            .map_err(|_| SxutilError::Config(format!("invalid metadata value for {}", key)))?;
+        self.metadata.retain(|(k, _)| *k != key);
+        self.metadata.push((key, value));
+        Ok(self)
+    }
+
+    // WithHook runs hook after token and metadata are added (returning Err cancels the call)
+    pub fn with_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(Request<()>) -> Result<Request<()>, Status> + Send + Sync + 'static,
+    {
+        self.hook = Some(Arc::new(hook));
+        self
+    }
+
+    pub fn token(&self) -> Option<&TokenSource> {
+        self.token.as_ref()
+    }
+}
+
+impl Interceptor for CallInterceptor {
+    fn call(&mut self, mut req: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(token) = &self.token {
+            let token = match token {
+                TokenSource::Static(token) => token.clone(),
+                TokenSource::Refresher(refresher) => refresher()?,
+                TokenSource::Shared(shared) => shared.get().to_string(),
+            };
+            let bearer = AsciiMetadataValue::try_from(format!("Bearer {}", token))
+                .map_err(|_| Status::unauthenticated("sxutil: invalid auth token"))?;
+            req.metadata_mut().insert("authorization", bearer);
+        }
+        for (key, value) in &self.metadata {
+            req.metadata_mut().insert(key.clone(), value.clone());
+        }
+        match &self.hook {
+            Some(hook) => hook(req),
+            None => Ok(req),
+        }
+    }
+}
+
+impl fmt::Debug for CallInterceptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CallInterceptor")
+            .field("token", &self.token)
+            .field("metadata", &self.metadata.iter().map(|(key, _)| key.as_str()).collect::<Vec<_>>())
+            .field("hook", &self.hook.is_some())
+            .finish()
+    }
+}
+
+impl PartialEq for CallInterceptor {
+    fn eq(&self, other: &Self) -> bool {
+        let same_hook = match (&self.hook, &other.hook) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        self.token == other.token && self.metadata == other.metadata && same_hook
+    }
+}
+
+impl Eq for CallInterceptor {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::time::Duration;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use futures::StreamExt;
+    use tonic::metadata::MetadataMap;
+    use crate::testing::{MockMethod, MockNodeMethod, MockNodeServer, MockSynerexServer};
+    use crate::{ConnectOptions, SupplyOpts, SxutilConfig, SxutilNode};
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    fn supply() -> SupplyOpts {
+        SupplyOpts { id: 0, target: 0, name: String::from("taxi"), json: String::new(), cdata: Default::default() }
+    }
+
+    fn header<'a>(metadata: &'a MetadataMap, key: &str) -> &'a str {
+        metadata.get(key).and_then(|value| value.to_str().ok()).unwrap_or_default()
+    }
+
+    // metadata of a unary call and a streaming subscription sent with interceptor
+    async fn sent_metadata(interceptor: CallInterceptor) -> (MetadataMap, MetadataMap) {
+        let server = MockSynerexServer::start_in_memory().await;
+        let opts = ConnectOptions::default().with_interceptor(interceptor);
+        let clt = SxutilNode::new().new_sx_service_client(server.connect_with(&opts).await.unwrap(), 1, String::new()).await;
+        clt.notify_supply(supply()).await.unwrap();
+        let mut demands = std::pin::pin!(clt.demand_stream());
+        let _ = tokio::time::timeout(Duration::from_millis(50), demands.next()).await; // subscribes
+        server.wait_for_requests(MockMethod::SubscribeDemand, 1, WAIT).await;
+        (server.metadata_of(MockMethod::NotifySupply).remove(0), server.metadata_of(MockMethod::SubscribeDemand).remove(0))
+    }
+
+    #[tokio::test]
+    async fn static_token_and_metadata_reach_server() {
+        let interceptor = CallInterceptor::new()
+            .with_token(String::from("secret"))
+            .with_metadata("x-tenant", "tenant-a").unwrap();
+        let (unary, streaming) = sent_metadata(interceptor).await;
+        for metadata in [&unary, &streaming] {
+            assert_eq!(header(metadata, "authorization"), "Bearer secret");
+            assert_eq!(header(metadata, "x-tenant"), "tenant-a");
+        }
+    }
+
+    #[tokio::test]
+    #[allow(clippy::result_large_err)] // refresher returns tonic::Status like Interceptor
+    async fn refresher_is_asked_on_every_call() {
+        let calls = Arc::new(AtomicU32::new(0));
+        let counter = Arc::clone(&calls);
+        let interceptor = CallInterceptor::new()
+            .with_token_refresher(move || Ok(format!("token-{}", counter.fetch_add(1, Ordering::SeqCst) + 1)));
+        let (unary, streaming) = sent_metadata(interceptor).await;
+        assert_eq!(header(&unary, "authorization"), "Bearer token-1");
+        assert_eq!(header(&streaming, "authorization"), "Bearer token-2");
+
+        let failing = CallInterceptor::new().with_token_refresher(|| Err(Status::unauthenticated("expired")));
+        let server = MockSynerexServer::start_in_memory().await;
+        let opts = ConnectOptions::default().with_interceptor(failing);
+        let clt = SxutilNode::new().new_sx_service_client(server.connect_with(&opts).await.unwrap(), 1, String::new()).await;
+        assert!(clt.notify_supply(supply()).await.is_err());
+        assert!(server.requests_of(MockMethod::NotifySupply).is_empty()); // not sent
+    }
+
+    #[tokio::test]
+    async fn shared_token_is_refreshed_asynchronously() {
+        let shared = SharedToken::new(String::from("first"));
+        let server = MockSynerexServer::start_in_memory().await;
+        let opts = ConnectOptions::default().with_interceptor(CallInterceptor::new().with_shared_token(shared.clone()));
+        let clt = SxutilNode::new().new_sx_service_client(server.connect_with(&opts).await.unwrap(), 1, String::new()).await;
+        clt.notify_supply(supply()).await.unwrap();
+
+        let refreshes = Arc::new(AtomicU32::new(0));
+        let counter = Arc::clone(&refreshes);
+        let refresh = shared.spawn_refresh(Duration::from_millis(10), move || {
+            let counter = Arc::clone(&counter);
+            async move {
+                tokio::task::yield_now().await; // e.g. token endpoint
+                Ok(format!("refreshed-{}", counter.fetch_add(1, Ordering::SeqCst) + 1))
+            }
+        });
+        tokio::time::timeout(WAIT, async {
+            while refreshes.load(Ordering::SeqCst) == 0 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        }).await.unwrap();
+        refresh.abort();
+        clt.notify_supply(supply()).await.unwrap();
+
+        let sent = server.metadata_of(MockMethod::NotifySupply);
+        assert_eq!(header(&sent[0], "authorization"), "Bearer first");
+        assert_eq!(header(&sent[1], "authorization"), format!("Bearer {}", shared.get()));
+        assert!(shared.get().starts_with("refreshed-"));
+    }
+
+    #[tokio::test]
+    async fn token_and_metadata_reach_node_server() {
+        let server = MockNodeServer::start().await.unwrap();
+        let interceptor = CallInterceptor::new()
+            .with_token(String::from("node-secret"))
+            .with_metadata("x-tenant", "tenant-a").unwrap();
+        let config = SxutilConfig::default()
+            .with_keepalive_interval(Duration::from_millis(20))
+            .with_connect(ConnectOptions::default().with_interceptor(interceptor));
+        let node = SxutilNode::with_config(config);
+        let keepalive = node.register_node(server.address(), String::from("Auth"), vec![1], None).await.unwrap();
+        server.wait_for_keepalives(node.node_id().await, 1, WAIT).await;
+        keepalive.stop().await.unwrap();
+
+        for method in [MockNodeMethod::RegisterNode, MockNodeMethod::KeepAlive, MockNodeMethod::UnRegisterNode] {
+            let sent = server.metadata_of(method);
+            assert!(!sent.is_empty(), "{:?}", method);
+            for metadata in &sent {
+                assert_eq!(header(metadata, "authorization"), "Bearer node-secret", "{:?}", method);
+                assert_eq!(header(metadata, "x-tenant"), "tenant-a", "{:?}", method);
+            }
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::fmt;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use arc_swap::ArcSwapOption;
+
+use crate::SXSynerexClient;
+
+// ConnectionSlot holds current connection of SXServiceClient.
+// Calls load a snapshot without lock, so unary RPCs, streams and reconnection
+// don't wait for each other (a redial swaps the connection atomically).
+pub struct ConnectionSlot {
+    current: ArcSwapOption<SXSynerexClient>,
+}
+
+impl ConnectionSlot {
+    pub fn new(clt: Option<SXSynerexClient>) -> Self {
+        ConnectionSlot { current: ArcSwapOption::from(clt.map(Arc::new)) }
+    }
+
+    // Load returns current connection (kept alive while the returned Arc is held)
+    pub fn load(&self) -> Option<Arc<SXSynerexClient>> {
+        self.current.load_full()
+    }
+
+    // Store replaces connection (None: disconnected)
+    pub fn store(&self, clt: Option<SXSynerexClient>) {
+        self.current.store(clt.map(Arc::new));
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.current.load().is_some()
+    }
+
+    pub fn server_address(&self) -> Option<String> {
+        self.current.load().as_ref().map(|clt| clt.server_address.clone())
+    }
+
+    // true while the slot uses the connection (or has no connection)
+    pub(crate) fn connected_by(&self, conn: &Arc<Mutex<()>>) -> bool {
+        match self.current.load().as_ref() {
+            Some(clt) => Arc::ptr_eq(&clt.redial, conn),
+            None => true,
+        }
+    }
+
+    // replaces connection by clt only when the slot still uses failed (or has no connection)
+    pub(crate) fn replace_if(&self, failed: &Arc<Mutex<()>>, clt: &SXSynerexClient) -> bool {
+        let new = Arc::new(clt.clone());
+        let prev = self.current.rcu(|current| match current {
+            Some(current) if !Arc::ptr_eq(&current.redial, failed) => Some(Arc::clone(current)),
+            _ => Some(Arc::clone(&new)),
+        });
+        match prev {
+            Some(prev) => Arc::ptr_eq(&prev.redial, failed),
+            None => true,
+        }
+    }
+}
+
+impl fmt::Debug for ConnectionSlot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("ConnectionSlot").field(&self.current.load()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::time::Duration;
+    use crate::testing::MockSynerexServer;
+    use crate::{SupplyOpts, SxutilNode};
+
+    // identity of current connection of slot
+    fn connection(slot: &ConnectionSlot) -> Arc<Mutex<()>> {
+        Arc::clone(&slot.load().unwrap().redial)
+    }
+
+    fn supply() -> SupplyOpts {
+        SupplyOpts { id: 0, target: 0, name: String::from("taxi"), json: String::new(), cdata: Default::default() }
+    }
+
+    #[tokio::test]
+    async fn clients_losing_connection_together_share_one_redial() {
+        let server = MockSynerexServer::start().await.unwrap();
+        let node = SxutilNode::new();
+        let clt = server.connect().await.unwrap();
+        let clt_a = node.new_sx_service_client(clt.clone(), 1, String::new()).await;
+        let clt_b = node.new_sx_service_client(clt, 2, String::new()).await;
+        let clt_a2 = clt_a.clone(); // shares slot of clt_a
+        assert!(Arc::ptr_eq(&clt_a.sxclient, &clt_a2.sxclient));
+        let old = connection(&clt_a.sxclient);
+        assert!(Arc::ptr_eq(&old, &connection(&clt_b.sxclient)));
+
+        server.shutdown().await;
+        assert!(clt_a.notify_supply(supply()).await.is_err());
+        assert!(clt_b.notify_supply(supply()).await.is_err());
+        server.restart().await.unwrap();
+        let wait = Duration::from_millis(300);
+        let address = server.address();
+        let started = tokio::time::Instant::now();
+        let (a, a2, b) = tokio::join!(
+            clt_a.redial(&address, wait),
+            clt_a2.redial(&address, wait),
+            clt_b.redial(&address, wait),
+        );
+        for result in [a, a2, b] {
+            result.unwrap();
+        }
+        assert!(started.elapsed() < wait * 2, "redialed one by one: {:?}", started.elapsed()); // others waited for the first
+
+        let new = connection(&clt_a.sxclient);
+        assert!(!Arc::ptr_eq(&new, &old));
+        assert!(Arc::ptr_eq(&new, &connection(&clt_b.sxclient)));
+        assert!(clt_b.sxclient.connected_by(&new));
+        clt_a.notify_supply(supply()).await.unwrap();
+        clt_b.notify_supply(supply()).await.unwrap();
+        assert_eq!(server.notified_supplies().len(), 2);
+    }
+}
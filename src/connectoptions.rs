@@ -0,0 +1,343 @@
+use core::time::Duration;
+use std::{fmt, fs, path::Path};
+
+use tonic::service::interceptor::InterceptedService;
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint, Identity};
+
+use crate::{CallInterceptor, SxChannel, SxutilError};
+
+// TlsOptions for TLS connection (rustls)
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct TlsOptions {
+    // PEM of CA certificate to verify server (None: system root certificates)
+    pub ca_cert: Option<Vec<u8>>,
+    // PEM of client certificate and private key (mutual TLS)
+    pub client_identity: Option<(Vec<u8>, Vec<u8>)>,
+    // server name to verify (None: host of server address)
+    pub domain_name: Option<String>,
+}
+
+impl TlsOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_ca_cert(mut self, pem: Vec<u8>) -> Self {
+        self.ca_cert = Some(pem);
+        self
+    }
+
+    pub fn with_client_identity(mut self, cert_pem: Vec<u8>, key_pem: Vec<u8>) -> Self {
+        self.client_identity = Some((cert_pem, key_pem));
+        self
+    }
+
+    pub fn with_domain_name(mut self, domain_name: String) -> Self {
+        self.domain_name = Some(domain_name);
+        self
+    }
+
+    // FromPemFiles reads CA certificate and client certificate/key from PEM files
+    pub fn from_pem_files<P: AsRef<Path>>(ca_cert: Option<P>, client_cert: Option<P>, client_key: Option<P>) -> Result<Self, SxutilError> {
+        let mut tls = TlsOptions::new();
+        if let Some(ca_cert) = ca_cert {
+            tls.ca_cert = Some(read_pem(ca_cert.as_ref())?);
+        }
+        match (client_cert, client_key) {
+            (Some(cert), Some(key)) => tls.client_identity = Some((read_pem(cert.as_ref())?, read_pem(key.as_ref())?)),
+            (None, None) => {},
+            _ => return Err(SxutilError::Config(String::from("client certificate and key should be set together"))),
+        }
+        Ok(tls)
+    }
+
+    fn client_tls_config(&self) -> ClientTlsConfig {
+        let mut config = ClientTlsConfig::new();
+        if let Some(ca_cert) = &self.ca_cert {
+            config = config.ca_certificate(Certificate::from_pem(ca_cert));
+        }
+        if let Some((cert, key)) = &self.client_identity {
+            config = config.identity(Identity::from_pem(cert, key));
+        }
+        if let Some(domain_name) = &self.domain_name {
+            config = config.domain_name(domain_name.clone());
+        }
+        config
+    }
+}
+
+// PEM bytes are not shown (client private key must not be logged)
+impl fmt::Debug for TlsOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TlsOptions")
+            .field("ca_cert", &self.ca_cert.is_some())
+            .field("client_identity", &self.client_identity.is_some())
+            .field("domain_name", &self.domain_name)
+            .finish()
+    }
+}
+
+fn read_pem(path: &Path) -> Result<Vec<u8>, SxutilError> {
+    fs::read(path).map_err(|err| SxutilError::Config(format!("can't read {}: {}", path.display(), err)))
+}
+
+// ConnectOptions for connections to synerex server and node server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectOptions {
+    // use TLS (None: plain HTTP/2)
+    pub tls: Option<TlsOptions>,
+    pub connect_timeout: Option<Duration>,
+    // timeout of each request (streams are limited until response headers)
+    pub request_timeout: Option<Duration>,
+    // HTTP/2 PING interval (also while idle)
+    pub http2_keepalive_interval: Option<Duration>,
+    // wait for PING ack before closing connection (used with http2_keepalive_interval)
+    pub http2_keepalive_timeout: Option<Duration>,
+    pub tcp_nodelay: bool,
+    // connect on first request instead of connecting in grpc_connect_server
+    pub lazy: bool,
+    // auth token and metadata added to every call
+    pub interceptor: CallInterceptor,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        ConnectOptions {
+            tls: None,
+            connect_timeout: None,
+            request_timeout: None,
+            http2_keepalive_interval: None,
+            http2_keepalive_timeout: None,
+            tcp_nodelay: true,
+            lazy: false,
+            interceptor: CallInterceptor::default(),
+        }
+    }
+}
+
+impl ConnectOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_tls(mut self, tls: TlsOptions) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    pub fn with_connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = Some(connect_timeout);
+        self
+    }
+
+    pub fn with_request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.request_timeout = Some(request_timeout);
+        self
+    }
+
+    pub fn with_http2_keepalive(mut self, interval: Duration, timeout: Option<Duration>) -> Self {
+        self.http2_keepalive_interval = Some(interval);
+        self.http2_keepalive_timeout = timeout;
+        self
+    }
+
+    pub fn with_tcp_nodelay(mut self, tcp_nodelay: bool) -> Self {
+        self.tcp_nodelay = tcp_nodelay;
+        self
+    }
+
+    pub fn with_lazy(mut self, lazy: bool) -> Self {
+        self.lazy = lazy;
+        self
+    }
+
+    pub fn with_interceptor(mut self, interceptor: CallInterceptor) -> Self {
+        self.interceptor = interceptor;
+        self
+    }
+
+    // Endpoint for address, "host:port" gets http:// (or https:// with TLS)
+    pub fn endpoint(&self, address: &str) -> Result<Endpoint, SxutilError> {
+        let uri = if address.contains("://") {
+            address.to_string()
+        } else if self.tls.is_some() {
+            format!("https://{}", address)
+        } else {
+            format!("http://{}", address)
+        };
+        let mut endpoint = Endpoint::from_shared(uri)
+            .map_err(|_| SxutilError::InvalidAddress(address.to_string()))?
+            .tcp_nodelay(self.tcp_nodelay);
+        if let Some(connect_timeout) = self.connect_timeout {
+            endpoint = endpoint.connect_timeout(connect_timeout);
+        }
+        if let Some(request_timeout) = self.request_timeout {
+            endpoint = endpoint.timeout(request_timeout);
+        }
+        if let Some(interval) = self.http2_keepalive_interval {
+            endpoint = endpoint.http2_keep_alive_interval(interval).keep_alive_while_idle(true);
+            if let Some(timeout) = self.http2_keepalive_timeout {
+                endpoint = endpoint.keep_alive_timeout(timeout);
+            }
+        }
+        if let Some(tls) = self.tls_for(address) {
+            endpoint = endpoint.tls_config(tls.client_tls_config())?;
+        }
+        Ok(endpoint)
+    }
+
+    // TLS options used for address, https:// address uses TLS (system root certificates) without tls
+    pub(crate) fn tls_for(&self, address: &str) -> Option<TlsOptions> {
+        match &self.tls {
+            Some(tls) => Some(tls.clone()),
+            None if address.starts_with("https://") => Some(TlsOptions::default()),
+            None => None,
+        }
+    }
+
+    // Connect opens channel to address (lazy channel connects on first request)
+    pub async fn connect(&self, address: &str) -> Result<SxChannel, SxutilError> {
+        let endpoint = self.endpoint(address)?;
+        let channel = if self.lazy {
+            endpoint.connect_lazy()
+        } else {
+            endpoint.connect().await?
+        };
+        Ok(InterceptedService::new(channel, self.interceptor.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use tokio::net::TcpListener;
+    use tonic::transport::server::TcpIncoming;
+    use tonic::transport::{Server, ServerTlsConfig};
+    use synerex_api::api;
+    use crate::{grpc_connect_server_with, SynerexBroker};
+
+    // PEM files generated by tests/fixtures/tls/gen.sh
+    fn fixture(name: &str) -> String {
+        format!("{}/tests/fixtures/tls/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    fn pem(name: &str) -> Vec<u8> {
+        fs::read(fixture(name)).unwrap()
+    }
+
+    // SynerexBroker on TLS requiring client certificate signed by the test CA
+    async fn start_tls_broker() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let tls = ServerTlsConfig::new()
+            .identity(Identity::from_pem(pem("server.pem"), pem("server.key")))
+            .client_ca_root(Certificate::from_pem(pem("ca.pem")));
+        let server = Server::builder().tls_config(tls).unwrap()
+            .add_service(SynerexBroker::new().service())
+            .serve_with_incoming(TcpIncoming::from_listener(listener, true, None).unwrap());
+        tokio::spawn(server);
+        addr
+    }
+
+    async fn create_mbus(address: String, opts: &ConnectOptions) -> Result<api::Mbus, SxutilError> {
+        let clt = grpc_connect_server_with(address, opts).await?;
+        Ok(clt.client.clone().create_mbus(api::MbusOpt::default()).await?.into_inner())
+    }
+
+    fn client_tls() -> TlsOptions {
+        TlsOptions::from_pem_files(Some(fixture("ca.pem")), Some(fixture("client.pem")), Some(fixture("client.key"))).unwrap()
+    }
+
+    #[tokio::test]
+    async fn mutual_tls_with_local_ca() {
+        let addr = start_tls_broker().await;
+        let opts = ConnectOptions::new().with_tls(client_tls()).with_connect_timeout(Duration::from_secs(5));
+        assert!(create_mbus(addr.to_string(), &opts).await.unwrap().mbus_id > 0);
+        assert!(create_mbus(format!("https://{}", addr), &opts).await.is_ok());
+
+        // certificate is for localhost / 127.0.0.1
+        let localhost = format!("localhost:{}", addr.port());
+        assert!(create_mbus(localhost.clone(), &opts).await.is_ok());
+        let opts = opts.with_tls(client_tls().with_domain_name(String::from("sxutil.example")));
+        assert!(create_mbus(localhost, &opts).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn tls_is_rejected_without_trust_or_identity() {
+        let addr = start_tls_broker().await;
+        let timeout = Duration::from_secs(5);
+
+        // server certificate is not signed by system roots
+        let untrusted = ConnectOptions::new().with_connect_timeout(timeout)
+            .with_tls(TlsOptions::new().with_client_identity(pem("client.pem"), pem("client.key")));
+        assert!(create_mbus(addr.to_string(), &untrusted).await.is_err());
+
+        // server requires client certificate
+        let anonymous = ConnectOptions::new().with_connect_timeout(timeout)
+            .with_tls(TlsOptions::new().with_ca_cert(pem("ca.pem")));
+        assert!(create_mbus(addr.to_string(), &anonymous).await.is_err());
+
+        // plain HTTP/2 to TLS server
+        let plain = ConnectOptions::new().with_connect_timeout(timeout).with_request_timeout(timeout);
+        assert!(create_mbus(addr.to_string(), &plain).await.is_err());
+    }
+
+    #[test]
+    fn endpoint_scheme() {
+        let plain = ConnectOptions::new();
+        let tls = ConnectOptions::new().with_tls(TlsOptions::new().with_ca_cert(pem("ca.pem")));
+        assert_eq!(plain.endpoint("127.0.0.1:10000").unwrap().uri().to_string(), "http://127.0.0.1:10000/");
+        assert_eq!(tls.endpoint("127.0.0.1:10000").unwrap().uri().to_string(), "https://127.0.0.1:10000/");
+        assert_eq!(tls.endpoint("http://127.0.0.1:10000").unwrap().uri().scheme_str(), Some("http")); // given scheme is kept
+        assert_eq!(plain.endpoint("https://localhost:10000").unwrap().uri().scheme_str(), Some("https"));
+
+        assert_eq!(plain.tls_for("127.0.0.1:10000"), None);
+        assert_eq!(plain.tls_for("http://127.0.0.1:10000"), None);
+        assert_eq!(plain.tls_for("https://localhost:10000"), Some(TlsOptions::default()));
+        assert_eq!(tls.tls_for("127.0.0.1:10000"), tls.tls);
+
+        assert!(matches!(plain.endpoint("bad address:1"), Err(SxutilError::InvalidAddress(_))));
+    }
+
+    #[test]
+    fn from_pem_files_reads_files() {
+        assert_eq!(TlsOptions::from_pem_files::<&str>(None, None, None).unwrap(), TlsOptions::default());
+        let tls = client_tls();
+        assert_eq!(tls.ca_cert, Some(pem("ca.pem")));
+        assert_eq!(tls.client_identity, Some((pem("client.pem"), pem("client.key"))));
+    }
+
+    #[test]
+    fn debug_does_not_show_pem() {
+        let tls = client_tls().with_domain_name(String::from("localhost"));
+        let config = crate::SxutilConfig::default().with_connect(ConnectOptions::default().with_tls(tls));
+        let debug = format!("{:?}", config);
+        for name in ["ca.pem", "client.pem", "client.key"] {
+            let bytes = format!("{:?}", &pem(name)[..32]); // as shown by derived Debug
+            assert!(!debug.contains(bytes.trim_matches(|c| c == '[' || c == ']')), "{} is shown: {}", name, debug);
+            let text = String::from_utf8(pem(name)).unwrap();
+            for line in text.lines().filter(|line| !line.starts_with("-----")) {
+                assert!(!debug.contains(line), "{} is shown: {}", name, debug);
+            }
+        }
+        assert!(!debug.contains("PRIVATE KEY"), "{}", debug);
+        assert!(debug.contains("ca_cert: true, client_identity: true, domain_name: Some(\"localhost\")"), "{}", debug);
+    }
+
+    #[test]
+    fn from_pem_files_errors() {
+        let cert_only = TlsOptions::from_pem_files(None, Some(fixture("client.pem")), None);
+        assert!(matches!(cert_only, Err(SxutilError::Config(_))));
+        let key_only = TlsOptions::from_pem_files(None, None, Some(fixture("client.key")));
+        assert!(matches!(key_only, Err(SxutilError::Config(_))));
+
+        let missing = fixture("missing.pem");
+        match TlsOptions::from_pem_files(Some(missing.as_str()), None, None) {
+            Err(SxutilError::Config(msg)) => assert!(msg.contains(&missing), "{}", msg),
+            other => panic!("unexpected {:?}", other),
+        }
+        let missing_key = TlsOptions::from_pem_files(None, Some(fixture("client.pem")), Some(missing));
+        assert!(matches!(missing_key, Err(SxutilError::Config(_))));
+    }
+}
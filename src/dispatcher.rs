@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{Mutex, Semaphore};
+use tokio::task::JoinHandle;
+
+use synerex_api::api;
+
+use crate::{DemandHandler, MbusHandler, SXServiceClient, SupplyHandler};
+
+// same shape as DemandHandler / SupplyHandler / MbusHandler
+type MessageHandler<M> = Pin<Box<dyn Fn(&SXServiceClient, M) -> futures::future::BoxFuture<()> + Send + Sync>>;
+
+// messages which have sender id (for per sender ordering)
+pub trait SenderId {
+    fn sender_id(&self) -> u64;
+}
+
+impl SenderId for api::Demand {
+    fn sender_id(&self) -> u64 {
+        self.sender_id
+    }
+}
+
+impl SenderId for api::Supply {
+    fn sender_id(&self) -> u64 {
+        self.sender_id
+    }
+}
+
+impl SenderId for api::MbusMsg {
+    fn sender_id(&self) -> u64 {
+        self.sender_id
+    }
+}
+
+// DispatchOptions for concurrent handler dispatch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DispatchOptions {
+    // max number of handler tasks (running or waiting for former message of the same sender).
+    // receiving stops while the limit is reached.
+    pub max_concurrency: usize,
+    // handle messages from the same sender_id one by one in arrival order
+    pub per_sender_order: bool,
+}
+
+impl DispatchOptions {
+    pub fn new(max_concurrency: usize) -> Self {
+        DispatchOptions {
+            max_concurrency: max_concurrency.max(1),
+            per_sender_order: false,
+        }
+    }
+
+    pub fn with_per_sender_order(mut self, per_sender_order: bool) -> Self {
+        self.per_sender_order = per_sender_order;
+        self
+    }
+}
+
+// DispatchStats is a snapshot of DispatchMetrics
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DispatchStats {
+    // messages not finished yet, also those waiting for max_concurrency (queue depth)
+    pub pending: usize,
+    // handler tasks running now
+    pub running: usize,
+    // max queue depth observed
+    pub max_pending: usize,
+    // messages handed to handler tasks
+    pub dispatched: u64,
+    pub completed: u64,
+}
+
+// DispatchMetrics is updated by concurrent handlers
+#[derive(Debug, Default)]
+pub struct DispatchMetrics {
+    pending: AtomicUsize,
+    running: AtomicUsize,
+    max_pending: AtomicUsize,
+    dispatched: AtomicU64,
+    completed: AtomicU64,
+}
+
+impl DispatchMetrics {
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    pub fn running(&self) -> usize {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    pub fn snapshot(&self) -> DispatchStats {
+        DispatchStats {
+            pending: self.pending.load(Ordering::Relaxed),
+            running: self.running.load(Ordering::Relaxed),
+            max_pending: self.max_pending.load(Ordering::Relaxed),
+            dispatched: self.dispatched.load(Ordering::Relaxed),
+            completed: self.completed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// ConcurrentDemandHandler wraps DemandHandler to run it on spawned tasks.
+// Each task gets a clone of the subscribing client (sharing its connection and mbus ids).
+pub fn concurrent_demand_handler(dmcb: DemandHandler, opts: DispatchOptions) -> (DemandHandler, Arc<DispatchMetrics>) {
+    concurrent_handler(dmcb, opts)
+}
+
+// ConcurrentSupplyHandler wraps SupplyHandler to run it on spawned tasks.
+pub fn concurrent_supply_handler(spcb: SupplyHandler, opts: DispatchOptions) -> (SupplyHandler, Arc<DispatchMetrics>) {
+    concurrent_handler(spcb, opts)
+}
+
+// ConcurrentMbusHandler wraps MbusHandler to run it on spawned tasks.
+pub fn concurrent_mbus_handler(mbcb: MbusHandler, opts: DispatchOptions) -> (MbusHandler, Arc<DispatchMetrics>) {
+    concurrent_handler(mbcb, opts)
+}
+
+// counts a message as pending from arrival (also while waiting for the limit) until its task finishes
+// (also when the handler panics)
+struct DispatchGuard {
+    metrics: Arc<DispatchMetrics>,
+    dispatched: bool,
+    running: bool,
+}
+
+impl DispatchGuard {
+    fn queued(metrics: Arc<DispatchMetrics>) -> Self {
+        let pending = metrics.pending.fetch_add(1, Ordering::Relaxed) + 1;
+        metrics.max_pending.fetch_max(pending, Ordering::Relaxed);
+        DispatchGuard { metrics, dispatched: false, running: false }
+    }
+
+    // permit is granted, message is handed to its task
+    fn dispatched(&mut self) {
+        self.metrics.dispatched.fetch_add(1, Ordering::Relaxed);
+        self.dispatched = true;
+    }
+
+    fn run(&mut self) {
+        self.metrics.running.fetch_add(1, Ordering::Relaxed);
+        self.running = true;
+    }
+}
+
+impl Drop for DispatchGuard {
+    fn drop(&mut self) {
+        if self.running {
+            self.metrics.running.fetch_sub(1, Ordering::Relaxed);
+        }
+        self.metrics.pending.fetch_sub(1, Ordering::Relaxed);
+        if self.dispatched { // not counted if subscription stopped while waiting for the limit
+            self.metrics.completed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn concurrent_handler<M>(handler: MessageHandler<M>, opts: DispatchOptions) -> (MessageHandler<M>, Arc<DispatchMetrics>)
+where
+    M: SenderId + Send + 'static,
+{
+    let handler = Arc::new(handler);
+    let limit = Arc::new(Semaphore::new(opts.max_concurrency.max(1)));
+    let metrics = Arc::new(DispatchMetrics::default());
+    // last task of each sender (per_sender_order)
+    let last_tasks: Arc<Mutex<HashMap<u64, JoinHandle<()>>>> = Arc::new(Mutex::new(HashMap::new()));
+
+    let dispatch_metrics = Arc::clone(&metrics);
+    let dispatch: MessageHandler<M> = Box::pin(move |clt: &SXServiceClient, msg: M| {
+        let clt = clt.clone(); // shares connection / mbus ids, no lock is held while handling
+        let handler = Arc::clone(&handler);
+        let limit = Arc::clone(&limit);
+        let metrics = Arc::clone(&dispatch_metrics);
+        let last_tasks = Arc::clone(&last_tasks);
+        let per_sender_order = opts.per_sender_order;
+        let max_concurrency = opts.max_concurrency;
+        Box::pin(async move {
+            let mut guard = DispatchGuard::queued(metrics);
+            // wait here (stop receiving) while max_concurrency tasks are dispatched
+            let permit = match limit.acquire_owned().await {
+                Ok(permit) => permit,
+                Err(_) => return, // never closed
+            };
+            guard.dispatched();
+
+            let sender_id = msg.sender_id();
+            let mut last_tasks = if per_sender_order { Some(last_tasks.lock().await) } else { None };
+            let former = last_tasks.as_mut().and_then(|tasks| tasks.remove(&sender_id));
+            let task = tokio::spawn(async move {
+                let _permit = permit;
+                if let Some(former) = former {
+                    let _ = former.await; // keep arrival order of the sender (also after panic)
+                }
+                guard.run();
+                handler(&clt, msg).await;
+                drop(guard);
+            });
+            if let Some(tasks) = last_tasks.as_mut() {
+                if tasks.len() > max_concurrency * 2 {
+                    tasks.retain(|_, task| !task.is_finished());
+                }
+                tasks.insert(sender_id, task);
+            }
+        }) as futures::future::BoxFuture<()>
+    });
+    (dispatch, metrics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::testing::MockSynerexServer;
+    use crate::{supply_handler, SxutilNode};
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    async fn service_client(server: &MockSynerexServer) -> SXServiceClient {
+        SxutilNode::new().new_sx_service_client(server.connect().await.unwrap(), 1, String::new()).await
+    }
+
+    fn supply(id: u64, sender_id: u64) -> api::Supply {
+        api::Supply { id, sender_id, ..Default::default() }
+    }
+
+    async fn wait_for_completed(metrics: &DispatchMetrics, count: u64) {
+        tokio::time::timeout(WAIT, async {
+            while metrics.snapshot().completed < count {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        }).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn dispatch_waits_at_max_concurrency() {
+        let server = MockSynerexServer::start_in_memory().await;
+        let clt = service_client(&server).await;
+        let gate = Arc::new(Semaphore::new(0));
+        let active = Arc::new(AtomicUsize::new(0));
+        let max_active = Arc::new(AtomicUsize::new(0));
+        let spcb = {
+            let (gate, active, max_active) = (Arc::clone(&gate), Arc::clone(&active), Arc::clone(&max_active));
+            supply_handler(move |_clt, _sp| {
+                let (gate, active, max_active) = (Arc::clone(&gate), Arc::clone(&active), Arc::clone(&max_active));
+                async move {
+                    max_active.fetch_max(active.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                    gate.acquire().await.unwrap().forget();
+                    active.fetch_sub(1, Ordering::SeqCst);
+                }
+            })
+        };
+        let (dispatch, metrics) = concurrent_supply_handler(spcb, DispatchOptions::new(2));
+
+        dispatch(&clt, supply(1, 1)).await;
+        dispatch(&clt, supply(2, 2)).await;
+        assert!(tokio::time::timeout(Duration::from_millis(50), dispatch(&clt, supply(3, 3))).await.is_err());
+        assert_eq!(metrics.pending(), 2); // dropped while waiting
+
+        gate.add_permits(10);
+        for id in 3..=6 {
+            dispatch(&clt, supply(id, id)).await;
+        }
+        wait_for_completed(&metrics, 6).await;
+        assert_eq!(max_active.load(Ordering::SeqCst), 2);
+        let stats = metrics.snapshot();
+        assert_eq!((stats.pending, stats.running, stats.max_pending, stats.dispatched), (0, 0, 3, 6));
+    }
+
+    #[tokio::test]
+    async fn messages_waiting_for_limit_are_pending() {
+        let server = MockSynerexServer::start_in_memory().await;
+        let clt = Arc::new(service_client(&server).await);
+        let gate = Arc::new(Semaphore::new(0));
+        let spcb = {
+            let gate = Arc::clone(&gate);
+            supply_handler(move |_clt, _sp| {
+                let gate = Arc::clone(&gate);
+                async move { gate.acquire().await.unwrap().forget() }
+            })
+        };
+        let (dispatch, metrics) = concurrent_supply_handler(spcb, DispatchOptions::new(2));
+        let dispatch = Arc::new(dispatch);
+
+        let waiting: Vec<_> = (1..=5).map(|id| {
+            let (dispatch, clt) = (Arc::clone(&dispatch), Arc::clone(&clt));
+            tokio::spawn(async move { dispatch(&clt, supply(id, id)).await })
+        }).collect();
+        tokio::time::timeout(WAIT, async {
+            while metrics.pending() < 5 || metrics.running() < 2 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        }).await.unwrap();
+        let stats = metrics.snapshot();
+        assert_eq!((stats.pending, stats.running, stats.max_pending, stats.dispatched), (5, 2, 5, 2));
+
+        gate.add_permits(5);
+        for task in waiting {
+            tokio::time::timeout(WAIT, task).await.unwrap().unwrap();
+        }
+        wait_for_completed(&metrics, 5).await;
+        let stats = metrics.snapshot();
+        assert_eq!((stats.pending, stats.running, stats.max_pending, stats.dispatched), (0, 0, 5, 5));
+    }
+
+    #[tokio::test]
+    async fn per_sender_order_keeps_arrival_order() {
+        let server = MockSynerexServer::start_in_memory().await;
+        let clt = service_client(&server).await;
+        let handled = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let spcb = {
+            let handled = Arc::clone(&handled);
+            supply_handler(move |_clt, sp: api::Supply| {
+                let handled = Arc::clone(&handled);
+                async move {
+                    // earlier messages take longer, so they would finish last without ordering
+                    tokio::time::sleep(Duration::from_millis(5 * (10 - sp.id % 10))).await;
+                    handled.lock().unwrap().push((sp.sender_id, sp.id));
+                }
+            })
+        };
+        let opts = DispatchOptions::new(8).with_per_sender_order(true);
+        let (dispatch, metrics) = concurrent_supply_handler(spcb, opts);
+
+        for id in 1..=5 {
+            dispatch(&clt, supply(id, 1)).await;
+            dispatch(&clt, supply(10 + id, 2)).await;
+        }
+        wait_for_completed(&metrics, 10).await;
+        let handled = handled.lock().unwrap().clone();
+        let of = |sender_id| handled.iter().filter(|(sender, _)| *sender == sender_id).map(|(_, id)| *id).collect::<Vec<_>>();
+        assert_eq!(of(1), vec![1, 2, 3, 4, 5]);
+        assert_eq!(of(2), vec![11, 12, 13, 14, 15]);
+    }
+
+    #[tokio::test]
+    async fn panicking_handler_is_not_left_pending() {
+        let server = MockSynerexServer::start_in_memory().await;
+        let clt = service_client(&server).await;
+        let spcb = supply_handler(|_clt, sp: api::Supply| async move {
+            if sp.id == 1 {
+                panic!("handler panics");
+            }
+        });
+        let opts = DispatchOptions::new(1).with_per_sender_order(true);
+        let (dispatch, metrics) = concurrent_supply_handler(spcb, opts);
+
+        dispatch(&clt, supply(1, 1)).await;
+        dispatch(&clt, supply(2, 1)).await; // waits for the permit of the panicked task
+        wait_for_completed(&metrics, 2).await;
+        let stats = metrics.snapshot();
+        assert_eq!((stats.pending, stats.running, stats.dispatched), (0, 0, 2));
+    }
+}
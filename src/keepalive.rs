@@ -0,0 +1,572 @@
+use std::sync::{Arc, Weak};
+use tokio::sync::{watch, Mutex, RwLock};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use systemstat::{Platform, System};
+
+use synerex_nodeapi::nodeapi;
+
+use crate::{grpc_connect_server_with, nodeservinfo::dial_node_server, NodeServInfo, SxutilError};
+
+// KeepAliveEvent is a lifecycle event of keepalive task
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeepAliveEvent {
+    // keepalive failed `failures` times in a row, node server is regarded as lost
+    NodeServerLost { failures: u32 },
+    // registered again to node server after node server failure
+    Reregistered { node_id: i32 },
+    // re-registration failed, retried on next keepalive
+    ReregisterFailed { error: String },
+}
+
+// KeepAliveHandler receives commands from node server given with keepalive response
+#[tonic::async_trait]
+pub trait KeepAliveHandler: Send + Sync {
+    // called after this node is registered again by RECONNECT
+    async fn on_reconnect(&self) {}
+
+    // called on SERVER_CHANGE after this node is registered again and
+    // SXServiceClients of this node are moved to the new server (server_info)
+    async fn on_server_change(&self, _server_info: String) {}
+
+    // called on PROV_DISCONN (only for SERVER node).
+    // provider_id is parsed from `err` of keepalive response.
+    async fn on_provider_disconnect(&self, _provider_id: Option<i32>, _err: String) {}
+
+    // called on lifecycle event of keepalive task
+    async fn on_event(&self, _event: KeepAliveEvent) {}
+}
+
+// bare fn callback (same as Go version's cmd_func)
+#[tonic::async_trait]
+impl KeepAliveHandler for fn(nodeapi::KeepAliveCommand, String) {
+    async fn on_server_change(&self, server_info: String) {
+        self(nodeapi::KeepAliveCommand::ServerChange, server_info);
+    }
+
+    async fn on_provider_disconnect(&self, _provider_id: Option<i32>, err: String) {
+        self(nodeapi::KeepAliveCommand::ProviderDisconnect, err);
+    }
+}
+
+// obtain provider id from err of PROV_DISCONN response (last number in the message)
+fn parse_provider_id(err: &str) -> Option<i32> {
+    err.rsplit(|c: char| !c.is_ascii_digit())
+        .find(|s| !s.is_empty())
+        .and_then(|s| s.parse().ok())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeepAliveStatus {
+    Running,
+    // stopped by KeepAliveHandle
+    Stopped,
+    // node is unregistered (secret is cleared by un_register_node)
+    Unregistered,
+    // keepalive task finished with error (e.g. registration failed on SERVER_CHANGE)
+    Failed,
+}
+
+// KeepAliveState is the latest state of keepalive task
+#[derive(Debug, Clone)]
+pub struct KeepAliveState {
+    pub status: KeepAliveStatus,
+    pub update_count: i32,
+    pub consecutive_failures: u32,
+    pub last_response: Option<nodeapi::Response>,
+    pub last_error: Option<String>,
+}
+
+impl KeepAliveState {
+    fn new() -> KeepAliveState {
+        KeepAliveState {
+            status: KeepAliveStatus::Running,
+            update_count: 0,
+            consecutive_failures: 0,
+            last_response: None,
+            last_error: None,
+        }
+    }
+}
+
+// KeepAliveHandle controls keepalive task started for a NodeServInfo.
+// Dropping the handle does not stop keepalive.
+#[derive(Debug)]
+pub struct KeepAliveHandle {
+    ni: Arc<RwLock<NodeServInfo>>,
+    cancel: CancellationToken,
+    join: Mutex<Option<JoinHandle<Result<(), SxutilError>>>>,
+    state: watch::Receiver<KeepAliveState>,
+}
+
+impl KeepAliveHandle {
+    pub(crate) fn spawn(ni: Arc<RwLock<NodeServInfo>>, handler: Option<Arc<dyn KeepAliveHandler>>) -> KeepAliveHandle {
+        let cancel = CancellationToken::new();
+        let (state_tx, state) = watch::channel(KeepAliveState::new());
+        let join = tokio::spawn(keep_alive_loop(Arc::clone(&ni), handler, cancel.clone(), state_tx));
+        KeepAliveHandle { ni, cancel, join: Mutex::new(Some(join)), state }
+    }
+
+    pub fn node_serv_info(&self) -> &Arc<RwLock<NodeServInfo>> {
+        &self.ni
+    }
+
+    // ServerInfo returns synerex server info given by node server
+    pub async fn server_info(&self) -> String {
+        self.ni.read().await.nid.server_info.clone()
+    }
+
+    pub fn status(&self) -> KeepAliveStatus {
+        self.state.borrow().status
+    }
+
+    pub fn state(&self) -> KeepAliveState {
+        self.state.borrow().clone()
+    }
+
+    // LastResponse returns last keepalive response from node server
+    pub fn last_response(&self) -> Option<nodeapi::Response> {
+        self.state.borrow().last_response.clone()
+    }
+
+    pub fn update_count(&self) -> i32 {
+        self.state.borrow().update_count
+    }
+
+    // Changed waits until keepalive state is updated
+    pub async fn changed(&mut self) -> Result<KeepAliveState, SxutilError> {
+        self.state.changed().await
+            .map_err(|_| SxutilError::InvalidState(String::from("keepalive task finished")))?;
+        Ok(self.state())
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.status() != KeepAliveStatus::Running
+    }
+
+    // Cancel requests keepalive task to finish without unregistering the node
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    // Join waits until keepalive task finishes
+    pub async fn join(&self) -> Result<(), SxutilError> {
+        let join = self.join.lock().await.take();
+        match join {
+            Some(join) => match join.await {
+                Ok(result) => result,
+                Err(err) => Err(SxutilError::InvalidState(format!("keepalive task failed: {}", err))),
+            },
+            None => Ok(()), // already joined
+        }
+    }
+
+    // Stop finishes keepalive task and unregisters the node from node server
+    pub async fn stop(&self) -> Result<(), SxutilError> {
+        self.cancel();
+        if let Err(err) = self.join().await {
+            error!("keepalive finished with error {}", err);
+        }
+        if self.ni.read().await.nid.secret == 0 {
+            return Ok(()); // already unregistered
+        }
+        NodeServInfo::un_register(&self.ni).await
+    }
+}
+
+// obtain cpu / memory status for SERVER node
+fn server_status(msg_count: u64) -> nodeapi::ServerStatus {
+    let sys = System::new();
+    let cpu_percent = match sys.load_average() {
+        Ok(loadavg) => loadavg.one,
+        Err(x) => {
+            error!("\nLoad average: error: {}", x);
+            0.0
+        }
+    };
+    let mem_percent = match sys.memory() {
+        Ok(mem) => {
+            ((mem.total.as_u64() - mem.free.as_u64()) as f64
+                / (mem.total.as_u64() as f64))
+                * 100.0
+        }
+        Err(x) => {
+            error!("\nMemory: error: {}", x);
+            0.0
+        }
+    };
+    nodeapi::ServerStatus {
+        cpu: cpu_percent as f64,
+        memory: mem_percent,
+        msg_count,
+    }
+}
+
+async fn emit_event(handler: &Option<Arc<dyn KeepAliveHandler>>, event: KeepAliveEvent) {
+    info!("KeepAlive event {:?}", event);
+    if let Some(handler) = handler {
+        handler.on_event(event).await;
+    }
+}
+
+// re_register after node server failure, returns true if registered again
+async fn recover_node_server(ni: &Arc<RwLock<NodeServInfo>>, handler: &Option<Arc<dyn KeepAliveHandler>>, state: &watch::Sender<KeepAliveState>) -> bool {
+    match re_register(ni).await {
+        Ok(node_id) => {
+            state.send_modify(|state| state.consecutive_failures = 0);
+            emit_event(handler, KeepAliveEvent::Reregistered { node_id }).await;
+            true
+        },
+        Err(err) => {
+            error!("Can't re-register to node server {}", err);
+            emit_event(handler, KeepAliveEvent::ReregisterFailed { error: err.to_string() }).await;
+            false
+        },
+    }
+}
+
+// dial node server again and register with the same node id
+async fn re_register(ni: &Arc<RwLock<NodeServInfo>>) -> Result<i32, SxutilError> {
+    let (address, opts, msg_timeout) = {
+        let ni = ni.read().await;
+        (ni.nodesrv_address.clone(), ni.config.node_connect_options().clone(), ni.config.msg_timeout)
+    };
+    let nodeclt = dial_node_server(&opts, &address, msg_timeout).await?;
+    ni.write().await.nodeclt = Some(Arc::new(Mutex::new(nodeclt)));
+    NodeServInfo::register_again(ni).await
+}
+
+// registration after unregistering for SERVER_CHANGE is tried keepalive_failure_threshold times
+// (waiting reconnect_wait in between), returns None if cancelled.
+async fn register_after_unregister(ni: &Arc<RwLock<NodeServInfo>>, cancel: &CancellationToken) -> Result<Option<i32>, SxutilError> {
+    let (attempts, wait) = {
+        let ni = ni.read().await;
+        (ni.config.keepalive_failure_threshold.max(1), ni.config.reconnect_wait)
+    };
+    let mut attempt = 1;
+    loop {
+        match NodeServInfo::register_again(ni).await {
+            Ok(node_id) => return Ok(Some(node_id)),
+            Err(err) if attempt >= attempts => return Err(err),
+            Err(err) => error!("Can't register for SERVER_CHANGE ({}/{}) {}", attempt, attempts, err),
+        }
+        attempt += 1;
+        tokio::select! {
+            _ = cancel.cancelled() => return Ok(None),
+            _ = tokio::time::sleep(wait) => {},
+        }
+    }
+}
+
+// SERVER_CHANGE: register again to obtain new synerex server and
+// reconnect all SXServiceClients of this node to it.
+// Subscriptions notice the change by server_change and subscribe again.
+async fn change_server(ni: &Arc<RwLock<NodeServInfo>>, cancel: &CancellationToken) -> Result<Option<String>, SxutilError> {
+    let mut call = ni.read().await.node_call().await?;
+    if let Err(err) = call.un_register().await {
+        error!("{} when nodeapi::KeepAliveCommand::ServerChange", err);
+    }
+    ni.write().await.nid.secret = 0;
+    if register_after_unregister(ni, cancel).await?.is_none() {
+        return Ok(None);
+    }
+
+    let (server_info, sx_clients, opts) = {
+        let mut ni = ni.write().await;
+        ni.sx_clients.retain(|clt| clt.strong_count() > 0);
+        let sx_clients: Vec<_> = ni.sx_clients.iter().filter_map(Weak::upgrade).collect();
+        (ni.nid.server_info.clone(), sx_clients, ni.config.connect.clone())
+    };
+    info!("sxutil: change synerex server to [{}] ({} clients)", server_info, sx_clients.len());
+
+    if !sx_clients.is_empty() {
+        let connected = grpc_connect_server_with(server_info.clone(), &opts).await?; // server_info may be a server list
+        for sxclient in sx_clients {
+            sxclient.store(Some(connected.clone())); // share one connection
+        }
+    }
+    ni.read().await.server_change.send_replace(server_info.clone());
+    Ok(Some(server_info))
+}
+
+// returns error only if this node is left unregistered (keepalive can't continue)
+async fn handle_server_change(ni: &Arc<RwLock<NodeServInfo>>, handler: &Option<Arc<dyn KeepAliveHandler>>, cancel: &CancellationToken) -> Result<(), SxutilError> {
+    let result = match change_server(ni, cancel).await {
+        Ok(Some(server_info)) => {
+            if let Some(handler) = handler {
+                handler.on_server_change(server_info).await;
+            }
+            Ok(())
+        },
+        Ok(None) => Ok(()), // cancelled
+        Err(err) => {
+            error!("Can't change server {}", err);
+            if ni.read().await.nid.secret == 0 { Err(err) } else { Ok(()) }
+        },
+    };
+    ni.write().await.node_state.init(); // unlock
+    result
+}
+
+// sleeps until the pending SERVER_CHANGE (if any)
+async fn server_change_at(at: Option<tokio::time::Instant>) {
+    match at {
+        Some(at) => tokio::time::sleep_until(at).await,
+        None => std::future::pending().await,
+    }
+}
+
+// keepalive loop for given node, runs until the node is unregistered or cancelled
+async fn keep_alive_loop(ni: Arc<RwLock<NodeServInfo>>, handler: Option<Arc<dyn KeepAliveHandler>>, cancel: CancellationToken, state: watch::Sender<KeepAliveState>) -> Result<(), SxutilError> {
+    let result = keep_alive(ni, handler, &cancel, &state).await;
+    state.send_modify(|state| {
+        state.status = match &result {
+            Ok(()) if cancel.is_cancelled() => KeepAliveStatus::Stopped,
+            Ok(()) => KeepAliveStatus::Unregistered,
+            Err(err) => {
+                state.last_error = Some(err.to_string());
+                KeepAliveStatus::Failed
+            },
+        };
+    });
+    result
+}
+
+async fn keep_alive(ni: Arc<RwLock<NodeServInfo>>, handler: Option<Arc<dyn KeepAliveHandler>>, cancel: &CancellationToken, state: &watch::Sender<KeepAliveState>) -> Result<(), SxutilError> {
+    let mut failures: u32 = 0;
+    let mut change_at = None; // SERVER_CHANGE waiting for proposals to finish
+    loop {
+        let keepalive_duration = {
+            let mut ni = ni.write().await;
+            ni.msg_count = 0; // how count message?
+            ni.config.keepalive_interval_or(ni.nid.keepalive_duration)
+        };
+        debug!(
+            "KeepAlive {} {:?}",
+            ni.read().await.nupd.read().await.node_status,
+            keepalive_duration
+        );
+        tokio::select! {
+            _ = cancel.cancelled() => {
+                debug!("KeepAlive stopped");
+                break;
+            },
+            _ = tokio::time::sleep(keepalive_duration) => {},
+            _ = server_change_at(change_at) => {
+                change_at = None;
+                handle_server_change(&ni, &handler, cancel).await?;
+                continue;
+            },
+        }
+
+        let (secret, threshold) = {
+            let ni = ni.read().await;
+            (ni.nid.secret, ni.config.keepalive_failure_threshold)
+        };
+        if secret == 0 {
+            // this means the node is disconnected
+            break;
+        }
+        if threshold != 0 && failures >= threshold {
+            // node server is lost: keepalive would be answered with RECONNECT
+            // (if at all), so register again instead
+            if recover_node_server(&ni, &handler, state).await {
+                failures = 0;
+            }
+            continue;
+        }
+
+        let (mut call, nupd) = {
+            let ni = ni.read().await;
+            if ni.my_node_type == nodeapi::NodeType::Server {
+                ni.nupd.write().await.status = Some(server_status(ni.msg_count));
+            }
+            let call = ni.node_call().await?;
+            let mut nupd = ni.nupd.write().await;
+            nupd.update_count += 1;
+            (call, nupd.clone())
+        };
+
+        let update_count = nupd.update_count;
+        // hung node server counts as failure after msg_timeout
+        let result = tokio::select! {
+            _ = cancel.cancelled() => {
+                debug!("KeepAlive stopped");
+                break;
+            },
+            result = call.keep_alive(nupd) => result,
+        };
+        let resp = match result {
+            Ok(resp) => resp,
+            Err(e) => {
+                error!("Error in response, may nodeserv failure {:?}", e);
+                failures += 1;
+                state.send_modify(|state| {
+                    state.last_error = Some(e.to_string());
+                    state.consecutive_failures = failures;
+                });
+                if threshold == 0 || failures < threshold {
+                    continue;
+                }
+                emit_event(&handler, KeepAliveEvent::NodeServerLost { failures }).await;
+                if recover_node_server(&ni, &handler, state).await {
+                    failures = 0;
+                }
+                continue;
+            }
+        };
+        failures = 0;
+        state.send_modify(|state| {
+            state.consecutive_failures = 0;
+            state.update_count = update_count;
+            state.last_response = Some(resp.clone());
+        });
+
+        // there might be some errors in response
+        debug!("KeepAlive Response: {:?}", resp.command());
+        match resp.command() {
+            nodeapi::KeepAliveCommand::None => {}
+            nodeapi::KeepAliveCommand::Reconnect => {
+                // order is reconnect to node.
+                match NodeServInfo::register_again(&ni).await {
+                    Ok(_) => {
+                        if let Some(handler) = &handler {
+                            handler.on_reconnect().await;
+                        }
+                    },
+                    Err(err) => error!("{} when nodeapi::KeepAliveCommand::Reconnect", err),
+                }
+            }
+            nodeapi::KeepAliveCommand::ServerChange => {
+                info!("receive SERVER_CHANGE\n");
+
+                let (safe, wait_time) = {
+                    let mut ni = ni.write().await;
+                    if ni.node_state.locked {
+                        continue; // already changing
+                    }
+                    ni.node_state.locked = true; // provider is locked until server is changed
+                    (ni.node_state.is_safe_state(), ni.config.wait_time)
+                };
+                if safe {
+                    handle_server_change(&ni, &handler, cancel).await?;
+                } else {
+                    // wait until proposals are finished (keepalive continues meanwhile)
+                    change_at = Some(tokio::time::Instant::now() + wait_time);
+                }
+            }
+            nodeapi::KeepAliveCommand::ProviderDisconnect => {
+                info!("receive PROV_DISCONN {:?}\n", resp);
+                let is_server = {
+                    let ni = ni.read().await;
+                    if ni.my_node_type != nodeapi::NodeType::Server {
+                        info!(
+                            "NodeType shoud be SERVER! {:?} {} {:?}",
+                            ni.my_node_type, ni.my_node_name, resp
+                        );
+                    }
+                    ni.my_node_type == nodeapi::NodeType::Server
+                };
+                if let (true, Some(handler)) = (is_server, &handler) {
+                    // work provider disconnect
+                    handler.on_provider_disconnect(parse_provider_id(&resp.err), resp.err.clone()).await;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::time::Duration;
+    use tokio::sync::mpsc;
+    use tonic::Status;
+    use crate::testing::{MockNodeMethod, MockNodeServer};
+    use crate::{SxutilConfig, SxutilNode};
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    struct Events(mpsc::UnboundedSender<KeepAliveEvent>);
+
+    #[tonic::async_trait]
+    impl KeepAliveHandler for Events {
+        async fn on_event(&self, event: KeepAliveEvent) {
+            let _ = self.0.send(event);
+        }
+    }
+
+    async fn next_event(events: &mut mpsc::UnboundedReceiver<KeepAliveEvent>) -> KeepAliveEvent {
+        tokio::time::timeout(WAIT, events.recv()).await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn lost_node_server_is_registered_again_without_keepalive() {
+        let mock = MockNodeServer::start().await.unwrap();
+        let config = SxutilConfig::default()
+            .with_keepalive_interval(Duration::from_millis(20))
+            .with_keepalive_failure_threshold(2)
+            .with_msg_timeout(Duration::from_secs(1));
+        let node = SxutilNode::with_config(config);
+        let (tx, mut events) = mpsc::unbounded_channel();
+        let handle = node.register_node_with_cmd(mock.address(), String::from("KeepAliveTest"), vec![1], None, Some(Arc::new(Events(tx)))).await.unwrap();
+        let node_id = node.node_id().await;
+        mock.wait_for_keepalives(node_id, 1, WAIT).await;
+
+        mock.fail(MockNodeMethod::KeepAlive, Status::unavailable("injected"));
+        mock.fail_times(MockNodeMethod::RegisterNode, Status::unavailable("injected"), 2);
+        let keepalives = mock.requests_of(MockNodeMethod::KeepAlive).len();
+        assert_eq!(next_event(&mut events).await, KeepAliveEvent::NodeServerLost { failures: 2 });
+        // registration is retried on each interval, keepalive is not sent meanwhile
+        assert!(matches!(next_event(&mut events).await, KeepAliveEvent::ReregisterFailed { .. }));
+        assert!(matches!(next_event(&mut events).await, KeepAliveEvent::ReregisterFailed { .. }));
+        assert_eq!(next_event(&mut events).await, KeepAliveEvent::Reregistered { node_id });
+        assert!(mock.requests_of(MockNodeMethod::KeepAlive).len() <= keepalives + 2);
+        assert_eq!(mock.requests_of(MockNodeMethod::RegisterNode).len(), 4);
+
+        mock.clear_faults();
+        mock.wait_for_keepalives(node_id, 2, WAIT).await;
+        assert_eq!(handle.state().consecutive_failures, 0);
+        handle.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn stalled_keepalive_fails_after_msg_timeout() {
+        let mock = MockNodeServer::start().await.unwrap();
+        let config = SxutilConfig::default()
+            .with_keepalive_interval(Duration::from_millis(20))
+            .with_keepalive_failure_threshold(2)
+            .with_msg_timeout(Duration::from_millis(100));
+        let node = SxutilNode::with_config(config);
+        let (tx, mut events) = mpsc::unbounded_channel();
+        let handle = node.register_node_with_cmd(mock.address(), String::from("KeepAliveTest"), vec![1], None, Some(Arc::new(Events(tx)))).await.unwrap();
+        let node_id = node.node_id().await;
+        mock.wait_for_keepalives(node_id, 1, WAIT).await;
+
+        mock.set_latency(MockNodeMethod::KeepAlive, Duration::from_secs(60));
+        assert_eq!(next_event(&mut events).await, KeepAliveEvent::NodeServerLost { failures: 2 });
+        assert!(matches!(handle.state().last_error, Some(err) if err.contains("timeout")), "{:?}", handle.state());
+        assert_eq!(next_event(&mut events).await, KeepAliveEvent::Reregistered { node_id });
+        handle.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn stop_does_not_wait_for_stalled_keepalive() {
+        let mock = MockNodeServer::start().await.unwrap();
+        let config = SxutilConfig::default()
+            .with_keepalive_interval(Duration::from_millis(20))
+            .with_msg_timeout(Duration::from_secs(60));
+        let node = SxutilNode::with_config(config);
+        let handle = node.register_node(mock.address(), String::from("KeepAliveTest"), vec![1], None).await.unwrap();
+        let node_id = node.node_id().await;
+        mock.wait_for_keepalives(node_id, 1, WAIT).await;
+
+        mock.set_latency(MockNodeMethod::KeepAlive, Duration::from_secs(60));
+        let sent = mock.requests_of(MockNodeMethod::KeepAlive).len();
+        mock.wait_for_requests(MockNodeMethod::KeepAlive, sent + 1, WAIT).await; // stalled
+        tokio::time::timeout(WAIT, handle.stop()).await.unwrap().unwrap();
+        assert_eq!(handle.status(), KeepAliveStatus::Stopped);
+        assert!(mock.nodes().is_empty());
+    }
+}
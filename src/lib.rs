@@ -2,15 +2,13 @@
 extern crate log;
 extern crate env_logger as logger;
 
-use core::time::Duration;
-use ticker::Ticker;
 use tokio::sync::{RwLock, Mutex};
-use std::{sync::Arc, error::Error, fmt, pin::Pin}; //, future::Future};
+use std::{sync::Arc, pin::Pin, future::Future, time::Duration};
 use once_cell::sync::Lazy;
+use tokio_util::sync::CancellationToken;
 
 use build_time::build_time_local;
 use git_version::git_version;
-use systemstat::{Platform, System};
 
 use synerex_api::api;
 use synerex_nodeapi::nodeapi;
@@ -20,6 +18,39 @@ mod nodeservinfo;
 pub use nodeservinfo::NodeServInfo;
 mod sxserviceclient;
 pub use sxserviceclient::SXServiceClient;
+mod sxutilerror;
+pub use sxutilerror::SxutilError;
+mod sxutilconfig;
+pub use sxutilconfig::SxutilConfig;
+mod keepalive;
+pub use keepalive::{KeepAliveEvent, KeepAliveHandle, KeepAliveHandler, KeepAliveState, KeepAliveStatus};
+mod sxutilnode;
+pub use sxutilnode::SxutilNode;
+mod subscriptionhandle;
+pub use subscriptionhandle::{SubscriptionEnd, SubscriptionHandle};
+use subscriptionhandle::until_cancelled;
+mod reconnectpolicy;
+pub use reconnectpolicy::{Backoff, GiveUp, ReconnectPolicy};
+use reconnectpolicy::ReconnectState;
+mod serverlist;
+pub use serverlist::{ServerEntry, ServerList, ServerSelection};
+mod connectoptions;
+pub use connectoptions::{ConnectOptions, TlsOptions};
+mod connectionslot;
+pub use connectionslot::ConnectionSlot;
+mod callinterceptor;
+pub use callinterceptor::{CallInterceptor, SharedToken, SxChannel, TokenSource};
+mod receivebuffer;
+pub use receivebuffer::{OverflowPolicy, ReceiveBufferOptions, ReceiveMetrics};
+mod dispatcher;
+pub use dispatcher::{concurrent_demand_handler, concurrent_supply_handler, concurrent_mbus_handler, DispatchMetrics, DispatchOptions, DispatchStats, SenderId};
+mod brokeroptions;
+pub use brokeroptions::BrokerOptions;
+mod brokerstate;
+mod serving;
+mod synerexbroker;
+pub use synerexbroker::{BrokerStats, SynerexBroker};
+pub mod testing;
 
 // sxutil is a helper utility package for Synerex
 
@@ -28,6 +59,7 @@ pub use sxserviceclient::SXServiceClient;
 // IDType for all ID in Synerex
 type IDType = u64;
 
+// default values for SxutilConfig
 static WAIT_TIME: u64 = 30;
 
 // this is for Message Timeout for synerex server
@@ -59,10 +91,35 @@ pub struct SupplyOpts {
 }
 
 // SXSynerexClient is for each server from v0.5.0
-#[derive(Debug)]
+// Clone is cheap and shares the connection (HTTP/2 channel is multiplexed),
+// pass clones to new_sx_service_client to use one connection for many channels.
+#[derive(Debug, Clone)]
 pub struct SXSynerexClient {
     pub server_address: String,
-    pub client: RwLock<api::synerex_client::SynerexClient<tonic::transport::Channel>>,
+    pub client: api::synerex_client::SynerexClient<SxChannel>,
+    // servers for failover (None: reconnect to server_address only)
+    pub servers: Option<Arc<ServerList>>,
+    // identity of the connection, locked while redialing it (shared by clients sharing the connection)
+    pub(crate) redial: Arc<Mutex<()>>,
+    // options to redial
+    pub(crate) connect: Arc<ConnectOptions>,
+}
+
+impl SXSynerexClient {
+    pub fn new(server_address: String, client: api::synerex_client::SynerexClient<SxChannel>) -> Self {
+        SXSynerexClient {
+            server_address,
+            client,
+            servers: None,
+            redial: Arc::new(Mutex::new(())),
+            connect: Arc::new(ConnectOptions::default()),
+        }
+    }
+
+    // true when both are on the same connection
+    pub fn same_connection(&self, other: &SXSynerexClient) -> bool {
+        Arc::ptr_eq(&self.redial, &other.redial)
+    }
 }
 
 
@@ -75,336 +132,373 @@ pub struct SxServerOpt {
 }
 
 
-#[derive(Debug, Clone)]
-struct SxutilError;
-
-impl fmt::Display for SxutilError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "invalid first item to double")
-    }
-}
-
-impl Error for SxutilError {
-    fn source(&self) -> Option<&(dyn Error + 'static)> {
-        None
-    }
-}
-
 // func init()
 static DEFAULT_NI: Lazy<Arc<RwLock<NodeServInfo>>> = Lazy::new(|| {
     debug!("sxutil: {} built {}", GIT_VER, BUILD_TIME);
     Arc::from(RwLock::new(NodeServInfo::new()))
 });
 
+// DefaultNode returns handle of default node used by free functions
+pub fn default_node() -> SxutilNode {
+    SxutilNode::from_node_serv_info(Arc::clone(&*DEFAULT_NI))
+}
+
+// SetConfig replaces runtime configuration of default node
+// SXServiceClient created after this call uses new config.
+pub async fn set_config(config: SxutilConfig) {
+    default_node().set_config(config).await
+}
+
+// Config returns runtime configuration of default node
+pub async fn config() -> SxutilConfig {
+    default_node().config().await
+}
+
 // InitNodeNum for initialize NodeNum again
 pub async fn init_node_num(n: i32) {
-    DEFAULT_NI.write().await.node = snowflake::SnowflakeIdGenerator::new(0, n);
-    info!("Successfully Initialize node {}", n);
+    default_node().init_node_num(n).await
 }
 
 // SetNodeStatus updates KeepAlive info to NodeServer
 pub async fn set_node_status(status: i32, arg: String) {
-    DEFAULT_NI.read().await.set_node_status(status, arg).await;
+    default_node().set_node_status(status, arg).await
 }
 
 pub async fn msg_count_up() { // is this needed?
-    DEFAULT_NI.write().await.msg_count_up();
+    default_node().msg_count_up().await
 }
 
 // RegisterNode is a function to register Node with node server address
-pub async fn register_node(nodesrv: String, nm: String, channels: Vec<u32>, serv: Option<&SxServerOpt>) -> Result<String, String> { // register ID to server
-	return register_node_with_cmd(nodesrv, nm, channels, serv, None).await
+// keepalive is started automatically (see KeepAliveHandle)
+pub async fn register_node(nodesrv: String, nm: String, channels: Vec<u32>, serv: Option<&SxServerOpt>) -> Result<KeepAliveHandle, SxutilError> { // register ID to server
+    default_node().register_node(nodesrv, nm, channels, serv).await
 }
 
 // RegisterNodeWithCmd is a function to register Node with node server address and KeepAlive Command Callback
-pub async fn register_node_with_cmd(nodesrv: String, nm: String, channels: Vec<u32>, serv: Option<&SxServerOpt>, cmd_func: Option<fn(nodeapi::KeepAliveCommand, String)>) -> Result<String, String> { // register ID to server
-    return match DEFAULT_NI.write().await.register_node_with_cmd(nodesrv, nm, channels, serv, cmd_func).await {
-        Ok(result) => Ok(result),
-        Err(err) => Err(format!("{}", err)),
-    };
+// keepalive is started automatically (see KeepAliveHandle)
+pub async fn register_node_with_cmd(nodesrv: String, nm: String, channels: Vec<u32>, serv: Option<&SxServerOpt>, handler: Option<Arc<dyn KeepAliveHandler>>) -> Result<KeepAliveHandle, SxutilError> { // register ID to server
+    default_node().register_node_with_cmd(nodesrv, nm, channels, serv, handler).await
 }
 
-pub async fn start_keep_alive_with_cmd(cmd_func: Option<fn(nodeapi::KeepAliveCommand, String)>) -> Result<String, String> {
-    let keepalive_duration = DEFAULT_NI.read().await.nid.keepalive_duration as u64;
-    loop {
-        DEFAULT_NI.write().await.msg_count = 0; // how count message?
-        {
-            debug!(
-                "KeepAlive {} {}",
-                // self.nupd.read().as_ref().unwrap().node_status,
-                DEFAULT_NI.read().await.nupd.read().await.node_status,
-                keepalive_duration
-            );
-        }
-        tokio::time::sleep(tokio::time::Duration::from_secs(keepalive_duration)).await;
-        if DEFAULT_NI.read().await.nid.secret == 0 {
-            // this means the node is disconnected
-            break;
-        }
+// UpdateChannels replaces channel types of default node and re-registers if already registered
+pub async fn update_channels(channels: Vec<u32>) -> Result<(), SxutilError> {
+    default_node().update_channels(channels).await
+}
 
-        if DEFAULT_NI.read().await.my_node_type == nodeapi::NodeType::Server {
-            // obtain cpu status
-            let sys = System::new();
-            let cpu_percent = match sys.load_average() {
-                Ok(loadavg) => loadavg.one,
-                Err(x) => {
-                    error!("\nLoad average: error: {}", x);
-                    0.0
-                }
-            };
-            let mem_percent = match sys.memory() {
-                Ok(mem) => {
-                    ((mem.total.as_u64() - mem.free.as_u64()) as f64
-                        / (mem.total.as_u64() as f64))
-                        * 100.0
-                }
-                Err(x) => {
-                    error!("\nMemory: error: {}", x);
-                    0.0
-                }
-            };
-            let status = nodeapi::ServerStatus {
-                cpu: cpu_percent as f64,
-                memory: mem_percent,
-                msg_count: DEFAULT_NI.read().await.msg_count,
-            };
-            DEFAULT_NI.read().await.nupd.write().await.status = Some(status);
-        }
+// UpdateArea replaces area id of default node and re-registers if already registered
+pub async fn update_area(area_id: String) -> Result<(), SxutilError> {
+    default_node().update_area(area_id).await
+}
 
-        {
-            DEFAULT_NI.read().await.nupd.write().await.update_count += 1;
-        }
+pub async fn un_register_node() -> Result<(), SxutilError> {
+    default_node().un_register_node().await
+}
 
-        let nupd_clone = DEFAULT_NI.read().await.nupd.read().await.clone();
-        let nodeclt_arc = Arc::clone(&DEFAULT_NI.read().await.nodeclt.as_ref().unwrap());
-
-        let fut = nodeclt_arc.lock().await.keep_alive(nupd_clone).await;
-
-        match fut {
-            Ok(resp) => {
-                // there might be some errors in response
-                debug!("KeepAlive Response: {:?}", resp.get_ref().command());
-                match resp.get_ref().command() {
-                    nodeapi::KeepAliveCommand::None => {}
-                    nodeapi::KeepAliveCommand::Reconnect => {
-                        // order is reconnect to node.
-                        match DEFAULT_NI.write().await.reconnect_node_serv().await {
-                            Ok(_) => {},
-                            Err(_) => { error!("Above error was happend when nodeapi::KeepAliveCommand::Reconnect for DEFAULT_NI") },
-                        };
-                    }
-                    nodeapi::KeepAliveCommand::ServerChange => {
-                        info!("receive SERVER_CHANGE\n");
-
-                        if DEFAULT_NI.read().await.node_state.is_safe_state() {
-                            DEFAULT_NI.write().await.un_register_node().await;
-
-                            // if !self.conn.is_none() {
-                            //     // self.conn.unwrap().close();  // TODO: inspect this.
-                            // }
-
-                            if cmd_func.is_some() {
-                                cmd_func.unwrap()(
-                                    resp.get_ref().command(),
-                                    resp.get_ref().err.clone(),
-                                );
-                                DEFAULT_NI.write().await.node_state.init();
-                            }
-                        } else {
-                            // wait
-                            if !DEFAULT_NI.read().await.node_state.locked {
-                                DEFAULT_NI.write().await.node_state.locked = true;
-                                // TODO: fix here (currently assume DEFAULT_NI)
-                                tokio::spawn(async {
-                                    let ticker = Ticker::new(0..1, Duration::from_secs(WAIT_TIME));
-                                    for _ in ticker {
-                                        DEFAULT_NI.write().await.node_state.init();
-                                    }
-                                });
-                            }
-                        }
-                    }
-                    nodeapi::KeepAliveCommand::ProviderDisconnect => {
-                        info!("receive PROV_DISCONN {:?}\n", resp);
-                        if DEFAULT_NI.read().await.my_node_type != nodeapi::NodeType::Server {
-                            info!(
-                                "NodeType shoud be SERVER! {:?} {} {:?}",
-                                DEFAULT_NI.read().await.my_node_type, DEFAULT_NI.read().await.my_node_name, resp
-                            );
-                        } else if !cmd_func.is_none() {
-                            // work provider disconnect
-                            cmd_func.unwrap()(resp.get_ref().command(), resp.get_ref().err.clone());
-                        }
-                    }
-                }
+// GrpcConnectServer is a utility function for conneting gRPC server
+// comma separated server_info (see ServerList::from_server_info) connects with failover.
+// Connection options are taken from config().connect of default node.
+pub async fn grpc_connect_server(server_address: String) -> Result<SXSynerexClient, SxutilError> {
+	let opts = config().await.connect;
+	grpc_connect_server_with(server_address, &opts).await
+}
 
-                true
-            }
-            Err(e) => {
-                error!("Error in response, may nodeserv failure {:?}", e);
-                false
-            }
-        };
-    }
-    // DEFAULT_NI.write().await.start_keep_alive_with_cmd(cmd_func).await;
-    Ok(String::from("keep alive finished"))
+// GrpcConnectServerWith is GrpcConnectServer with connection options (TLS, timeouts, ...)
+pub async fn grpc_connect_server_with(server_address: String, opts: &ConnectOptions) -> Result<SXSynerexClient, SxutilError> {
+	if server_address.contains(',') {
+		return grpc_connect_servers_with(ServerList::from_server_info(&server_address)?, opts).await;
+	}
+	dial_server(server_address, opts).await
 }
 
-pub async fn un_register_node() {
-    DEFAULT_NI.write().await.un_register_node().await;
+// GrpcConnectServers connects to the first reachable server of the list.
+// The client fails over to other servers of the list on reconnection.
+pub async fn grpc_connect_servers(servers: ServerList) -> Result<SXSynerexClient, SxutilError> {
+	let opts = config().await.connect;
+	grpc_connect_servers_with(servers, &opts).await
 }
 
-// GrpcConnectServer is a utility function for conneting gRPC server
-pub async fn grpc_connect_server(server_address: String) -> Option<SXSynerexClient> { // TODO: we may add connection option
-	if server_address == "" {
+// GrpcConnectServersWith is GrpcConnectServers with connection options
+pub async fn grpc_connect_servers_with(servers: ServerList, opts: &ConnectOptions) -> Result<SXSynerexClient, SxutilError> {
+	if servers.is_empty() {
+		error!("sxutil: [FATAL] no server address cor GrpcConnectServers");
+		return Err(SxutilError::InvalidAddress(String::new()))
+	}
+	let order = servers.failover_order(None);
+	serverlist::connect_servers(&Arc::new(servers), order, opts).await
+}
+
+// connects single server address
+async fn dial_server(server_address: String, opts: &ConnectOptions) -> Result<SXSynerexClient, SxutilError> {
+	if server_address.is_empty() {
 		error!("sxutil: [FATAL] no server address cor GrpcConnectServer");
-		return None
+		return Err(SxutilError::InvalidAddress(server_address))
 	}
-	// opts = append(opts, grpc.WithInsecure()) // currently we do not use sercure connection //TODO: we need to udpate SSL
-	// opts = append(opts, grpc.WithBlock()) // this is required to ensure client connection
-	let client = match api::synerex_client::SynerexClient::connect(server_address.clone()).await {
-        Ok(clt) => clt,
+	// TLS, timeouts and keepalive are given by ConnectOptions (opts.lazy is like not using grpc.WithBlock())
+	let client = match opts.connect(&server_address).await {
+        Ok(channel) => api::synerex_client::SynerexClient::new(channel),
         Err(err) => {
             error!("sxutil:GRPC-conn  Failed to connect server {} err: {}", server_address, err);
-            return None
+            return Err(err)
         },
     };
     debug!("sxutil: clt: {:?}", client);
 
 	// from v0.5.0 , we support Connection in sxutil.
-	Some(SXSynerexClient{
-		server_address: server_address,
-		client: RwLock::from(client),
-	})
+	let mut clt = SXSynerexClient::new(server_address, client);
+	clt.connect = Arc::new(opts.clone());
+	Ok(clt)
 }
 
 // NewSXServiceClient Creates wrapper structre SXServiceClient from SynerexClient
 pub async fn new_sx_service_client(clt: SXSynerexClient, mtype: u32, arg_json: String) -> SXServiceClient {
-    let client_id = DEFAULT_NI.write().await.generate_int_id();
-    // sxServiceClient.ni = Some(&DEFAULT_NI);
-    SXServiceClient {
-        client_id,
-        channel_type: mtype,
-        sxclient: RwLock::from(Some(clt)),
-        arg_json,
-        mbus_ids: RwLock::from(Vec::new()),
-        ni: Some(Arc::clone(&*DEFAULT_NI)),
-    }
-	// return defaultNI.NewSXServiceClient(clt, mtype, argJson)
+    default_node().new_sx_service_client(clt, mtype, arg_json).await
 }
 
 // GenerateIntID for generate uniquie ID
 pub async fn generate_int_id() -> u64 {
-    DEFAULT_NI.write().await.generate_int_id()
+    default_node().generate_int_id().await
 }
 
 // Simple Robust SubscribeDemand/Supply with ReConnect function. (2020/09~ v0.5.0)
 
 pub async fn reconnect_client(client: Arc<RwLock<SXServiceClient>>, serv_addr: String) {
-	// may need to reset old connection to stop redialing.
-	
-	if client.read().await.sxclient.read().await.is_some() {
-        // may need to reset old connection to stop redialing.
-        *client.read().await.sxclient.write().await = None;
-        info!("sxutil:Client reset with srvaddr: {}\n", serv_addr);
-	}
-
-    tokio::time::sleep(tokio::time::Duration::from_secs(RECONNECT_WAIT)).await;  // wait 5 seconds to reconnect
+    let reconnect_wait = client.read().await.config.reconnect_wait;
+    reconnect_client_after(client, serv_addr, reconnect_wait).await;
+}
 
+// reconnect_client with wait decided by ReconnectPolicy
+// (old connection is kept until new one is installed, see SXServiceClient::redial)
+async fn reconnect_client_after(client: Arc<RwLock<SXServiceClient>>, serv_addr: String, reconnect_wait: Duration) {
 	if serv_addr.len() > 0 {
-		let new_clt = grpc_connect_server(serv_addr.clone()).await;
-		if new_clt.is_some() && client.read().await.sxclient.read().await.is_some() {
-			info!("sxutil: Reconnect server [{}] {:?}\n", serv_addr, new_clt);
-			*client.read().await.sxclient.write().await = new_clt;
-		} else {
-			error!("sxutil: Can't re-connect server..");
+		if let Err(err) = client.read().await.redial(&serv_addr, reconnect_wait).await {
+			error!("sxutil: Can't re-connect server.. {}", err);
 		}
 	} else { // someone may connect!
-		info!("sxutil: Use reconnected client.. {:?} : svadr: {}\n", client.read().await.sxclient.read().await, serv_addr);
+		info!("sxutil: Use reconnected client.. {:?} : svadr: {}\n", client.read().await.sxclient.load(), serv_addr);
 	}
 }
 
 // Type definition of DemandHandler
 pub type DemandHandler = Pin<Box<dyn Fn(&SXServiceClient, api::Demand) -> futures::future::BoxFuture<()> + Send + Sync>>;
 
+// DemandHandler from async closure (returned future can't borrow SXServiceClient)
+pub fn demand_handler<F, Fut>(dmcb: F) -> DemandHandler
+where
+    F: Fn(&SXServiceClient, api::Demand) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    Box::pin(move |clt: &SXServiceClient, dm: api::Demand| Box::pin(dmcb(clt, dm)) as futures::future::BoxFuture<()>)
+}
+
 // Simple Continuous (error free) subscriber for demand
-pub fn simple_subscribe_demand(client: Arc<RwLock<SXServiceClient>>, dmcb: DemandHandler) -> Arc<Mutex<bool>> {
-	let loop_flag = Arc::new(Mutex::new(true));
-	tokio::spawn(subscribe_demand(Arc::clone(&client), dmcb, Arc::clone(&loop_flag))); // loop
-	return loop_flag;
+pub fn simple_subscribe_demand(client: Arc<RwLock<SXServiceClient>>, dmcb: DemandHandler) -> SubscriptionHandle {
+	SubscriptionHandle::spawn(|cancel| subscribe_demand(client, dmcb, cancel)) // loop
 }
 
-// Continuous (error free) subscriber for demand
-pub async fn subscribe_demand(client: Arc<RwLock<SXServiceClient>>, dmcb: DemandHandler, loop_flag: Arc<Mutex<bool>>) {
-    if client.read().await.sxclient.read().await.is_none() || client.read().await.sxclient.read().await.as_ref().unwrap().server_address == "" {
-        error!("sxutil: SubscribeDemand should called with correct info!");
-        return;
-    }
-	let mut serv_addr = client.read().await.sxclient.read().await.as_ref().unwrap().server_address.clone();
-	while *loop_flag.lock().await { // make it continuously working..
+// Continuous (error free) subscriber for demand, runs until cancel is cancelled
+pub async fn subscribe_demand(client: Arc<RwLock<SXServiceClient>>, dmcb: DemandHandler, cancel: CancellationToken) -> SubscriptionEnd {
+	until_cancelled(cancel, demand_loop(client, dmcb)).await
+}
+
+async fn demand_loop(client: Arc<RwLock<SXServiceClient>>, dmcb: DemandHandler) -> SubscriptionEnd {
+    let mut serv_addr = match client.read().await.sxclient.server_address() {
+        Some(addr) if !addr.is_empty() => addr,
+        _ => {
+            error!("sxutil: SubscribeDemand should called with correct info!");
+            return SubscriptionEnd::Failed(SxutilError::NotConnected);
+        },
+    };
+	let mut retry = ReconnectState::default();
+	loop { // make it continuously working..
+		let started = tokio::time::Instant::now();
 		let result = client.read().await.subscribe_demand(&dmcb).await;
 		//		log.Printf("sxutil:Error on subscribeDemand . %v", err)
-		if result && client.read().await.sxclient.read().await.is_some() { 
-			serv_addr = client.read().await.sxclient.read().await.as_ref().unwrap().server_address.clone();
-			info!("sxutil: SubscribeDemand: reset server address [{}]", serv_addr);
-		} else {
-			error!("sxutil:Error on SubscribeDemand.");
+		if let Some(addr) = server_moved(&client, &serv_addr).await {
+			info!("sxutil: SubscribeDemand: move to server [{}]", addr);
+			serv_addr = addr;
+			continue;
+		}
+		match result {
+			Ok(()) if client.read().await.sxclient.is_connected() => {
+				serv_addr = client.read().await.sxclient.server_address().unwrap_or_default();
+				info!("sxutil: SubscribeDemand: reset server address [{}]", serv_addr);
+			},
+			Ok(()) => error!("sxutil:Error on SubscribeDemand."),
+			Err(err) => error!("sxutil:Error on SubscribeDemand. {}", err),
+		}
+		match reconnect_delay(&client, &mut retry, "Demand", &serv_addr, started.elapsed()).await {
+			Ok(wait) => reconnect_client_after(Arc::clone(&client), serv_addr.clone(), wait).await,
+			Err(give_up) => return SubscriptionEnd::GaveUp(give_up),
+		}
+		if let Some(addr) = client.read().await.server_address().await {
+			serv_addr = addr; // may be failed over to another server
 		}
-		reconnect_client(Arc::clone(&client), serv_addr.clone()).await;
 	}
 }
 
 // Type definition of SupplyHandler
 pub type SupplyHandler = Pin<Box<dyn Fn(&SXServiceClient, api::Supply) -> futures::future::BoxFuture<()> + Send + Sync>>;
 
+// SupplyHandler from async closure (returned future can't borrow SXServiceClient)
+pub fn supply_handler<F, Fut>(spcb: F) -> SupplyHandler
+where
+    F: Fn(&SXServiceClient, api::Supply) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    Box::pin(move |clt: &SXServiceClient, sp: api::Supply| Box::pin(spcb(clt, sp)) as futures::future::BoxFuture<()>)
+}
+
 
 // Simple Continuous (error free) subscriber for supply
-pub fn simple_subscribe_supply(client: Arc<RwLock<SXServiceClient>>, spcb: SupplyHandler) -> Arc<Mutex<bool>> {
-	let loop_flag = Arc::new(Mutex::new(true));
-	tokio::spawn( subscribe_supply(Arc::clone(&client), spcb, Arc::clone(&loop_flag))); // loop
-	loop_flag
+pub fn simple_subscribe_supply(client: Arc<RwLock<SXServiceClient>>, spcb: SupplyHandler) -> SubscriptionHandle {
+	SubscriptionHandle::spawn(|cancel| subscribe_supply(client, spcb, cancel)) // loop
 }
 
-// Continuous (error free) subscriber for supply
-pub async fn subscribe_supply(client: Arc<RwLock<SXServiceClient>>, spcb: SupplyHandler, loop_flag: Arc<Mutex<bool>>) {
-    if client.read().await.sxclient.read().await.is_none() || client.read().await.sxclient.read().await.as_ref().unwrap().server_address == "" {
-        error!("sxutil: SubscribeSupply should called with correct info!");
-        return;
-    }
-    let mut serv_addr = client.read().await.sxclient.read().await.as_ref().unwrap().server_address.clone();
+// Continuous (error free) subscriber for supply, runs until cancel is cancelled
+pub async fn subscribe_supply(client: Arc<RwLock<SXServiceClient>>, spcb: SupplyHandler, cancel: CancellationToken) -> SubscriptionEnd {
+	until_cancelled(cancel, supply_loop(client, spcb)).await
+}
+
+async fn supply_loop(client: Arc<RwLock<SXServiceClient>>, spcb: SupplyHandler) -> SubscriptionEnd {
+    let mut serv_addr = match client.read().await.sxclient.server_address() {
+        Some(addr) if !addr.is_empty() => addr,
+        _ => {
+            error!("sxutil: SubscribeSupply should called with correct info!");
+            return SubscriptionEnd::Failed(SxutilError::NotConnected);
+        },
+    };
 	//	log.Printf("sxutil: SubscribeSupply with ServerAddress [%s]",servAddr)
-	while *loop_flag.lock().await { // make it continuously working..
+	let mut retry = ReconnectState::default();
+	loop { // make it continuously working..
+        let started = tokio::time::Instant::now();
         let result = client.read().await.subscribe_supply(&spcb).await;  // this may block until the connection broken
 		//
-		if result { 
-			serv_addr = client.read().await.sxclient.read().await.as_ref().unwrap().server_address.clone();
-			info!("sxutil: SubscribeSupply: reset server address [{}]", serv_addr);
-		} else {
-			error!("sxutil: SXClient is nil in SubscribeSupply.");
+		if let Some(addr) = server_moved(&client, &serv_addr).await {
+			info!("sxutil: SubscribeSupply: move to server [{}]", addr);
+			serv_addr = addr;
+			continue;
+		}
+		match result {
+			Ok(()) if client.read().await.sxclient.is_connected() => {
+				serv_addr = client.read().await.sxclient.server_address().unwrap_or_default();
+				info!("sxutil: SubscribeSupply: reset server address [{}]", serv_addr);
+			},
+			Ok(()) => error!("sxutil: SXClient is nil in SubscribeSupply."),
+			Err(err) => error!("sxutil: Error on SubscribeSupply. {}", err),
+		}
+		match reconnect_delay(&client, &mut retry, "Supply", &serv_addr, started.elapsed()).await {
+			Ok(wait) => reconnect_client_after(Arc::clone(&client), serv_addr.clone(), wait).await,
+			Err(give_up) => return SubscriptionEnd::GaveUp(give_up),
+		}
+		if let Some(addr) = client.read().await.server_address().await {
+			serv_addr = addr; // may be failed over to another server
+		}
+	}
+}
+
+
+// Type definition of MbusHandler
+pub type MbusHandler = Pin<Box<dyn Fn(&SXServiceClient, api::MbusMsg) -> futures::future::BoxFuture<()> + Send + Sync>>;
+
+// MbusHandler from async closure (returned future can't borrow SXServiceClient)
+pub fn mbus_handler<F, Fut>(mbcb: F) -> MbusHandler
+where
+    F: Fn(&SXServiceClient, api::MbusMsg) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    Box::pin(move |clt: &SXServiceClient, msg: api::MbusMsg| Box::pin(mbcb(clt, msg)) as futures::future::BoxFuture<()>)
+}
+
+// Simple Continuous (error free) subscriber for mbus
+pub fn simple_subscribe_mbus(client: Arc<RwLock<SXServiceClient>>, mbus_id: u64, mbcb: MbusHandler) -> SubscriptionHandle {
+	SubscriptionHandle::spawn(|cancel| subscribe_mbus(client, mbus_id, mbcb, cancel)) // loop
+}
+
+// Continuous (error free) subscriber for mbus, runs until cancel is cancelled
+pub async fn subscribe_mbus(client: Arc<RwLock<SXServiceClient>>, mbus_id: u64, mbcb: MbusHandler, cancel: CancellationToken) -> SubscriptionEnd {
+	until_cancelled(cancel, mbus_loop(client, mbus_id, mbcb)).await
+}
+
+async fn mbus_loop(client: Arc<RwLock<SXServiceClient>>, mbus_id: u64, mbcb: MbusHandler) -> SubscriptionEnd {
+    let mut serv_addr = match client.read().await.server_address().await {
+        Some(addr) if !addr.is_empty() => addr,
+        _ => {
+            error!("sxutil: SubscribeMbus should called with correct info!");
+            return SubscriptionEnd::Failed(SxutilError::NotConnected);
+        },
+    };
+	let mut retry = ReconnectState::default();
+	loop { // make it continuously working..
+        let started = tokio::time::Instant::now();
+        let result = client.read().await.subscribe_mbus(mbus_id, &mbcb).await;
+		if let Some(addr) = server_moved(&client, &serv_addr).await {
+			info!("sxutil: SubscribeMbus: move to server [{}]", addr);
+			serv_addr = addr;
+			continue;
+		}
+		match result {
+			Ok(()) => info!("sxutil: SubscribeMbus: mbus {} closed", mbus_id),
+			Err(err) => error!("sxutil: Error on SubscribeMbus. {}", err),
+		}
+		match reconnect_delay(&client, &mut retry, "Mbus", &serv_addr, started.elapsed()).await {
+			Ok(wait) => reconnect_client_after(Arc::clone(&client), serv_addr.clone(), wait).await,
+			Err(give_up) => return SubscriptionEnd::GaveUp(give_up),
+		}
+		if let Some(addr) = client.read().await.server_address().await {
+			serv_addr = addr; // may be failed over to another server
 		}
-		reconnect_client(Arc::clone(&client), serv_addr.clone()).await;
 	}
 }
 
+// returns wait before reconnecting, or gives up by ReconnectPolicy of the client
+async fn reconnect_delay(client: &Arc<RwLock<SXServiceClient>>, retry: &mut ReconnectState, subscription: &'static str, serv_addr: &str, alive: Duration) -> Result<Duration, GiveUp> {
+    let clt = client.read().await;
+    let policy = &clt.config.reconnect_policy;
+    retry.next_delay(policy, clt.config.reconnect_wait, alive)
+        .map_err(|(attempts, elapsed)| {
+            let give_up = GiveUp { subscription, server_address: serv_addr.to_string(), attempts, elapsed };
+            policy.give_up(&give_up);
+            give_up
+        })
+}
+
+// returns new server address when the client is moved by SERVER_CHANGE
+async fn server_moved(client: &Arc<RwLock<SXServiceClient>>, serv_addr: &str) -> Option<String> {
+    client.read().await.server_address().await
+        .filter(|addr| addr != serv_addr)
+}
 
 // We need to simplify the logic of separate NotifyDemand/SelectSupply
 
+// true when target_id is a supply proposed by node of clt (NotConnected when clt has no node)
+async fn proposed_by_me(clt: &SXServiceClient, target_id: u64) -> Result<bool, SxutilError> {
+    let ni = clt.ni.as_ref().ok_or(SxutilError::NotConnected)?.read().await;
+    info!("SelectSupply: {}: {:?}", target_id, ni.node_state.proposed_supply);
+    Ok(ni.node_state.proposed_supply_index(target_id) >= 0)
+}
+
 // composit callback with selection checking
-pub fn generate_demand_callback(ndcb: Arc<fn(&SXServiceClient, api::Demand)>, sscb: Arc<fn(&SXServiceClient, api::Demand)>) -> DemandHandler {
+pub fn generate_demand_callback<N, NFut, S, SFut>(ndcb: N, sscb: S) -> DemandHandler
+where
+    N: Fn(&SXServiceClient, api::Demand) -> NFut + Send + Sync + 'static,
+    NFut: Future<Output = ()> + Send + 'static,
+    S: Fn(&SXServiceClient, api::Demand) -> SFut + Send + Sync + 'static,
+    SFut: Future<Output = ()> + Send + 'static,
+{
+    let ndcb = Arc::new(ndcb);
+    let sscb = Arc::new(sscb);
     let async_fn_ptr: DemandHandler = Box::pin(move |clt: &SXServiceClient, dm: api::Demand| {
         let ndcb = ndcb.clone();
         let sscb = sscb.clone();
         Box::pin(async move {
             if dm.target_id == 0 {
-                ndcb(clt, dm);
+                ndcb(clt, dm).await;
             } else {
                 //
-                info!("SelectSupply: {}: {:?}", dm.target_id, clt.ni.as_ref().unwrap().read().await.node_state.proposed_supply);
-                let pos = clt.ni.as_ref().unwrap().write().await.node_state.proposed_supply_index(dm.target_id);
-                if pos >= 0 { // it is proposed by me.
-                    sscb(clt, dm);
-                } else {
-                    info!("sxutil:Other Proposal? {}", dm.target_id);
+                match proposed_by_me(clt, dm.target_id).await {
+                    Ok(true) => sscb(clt, dm).await,
+                    Ok(false) => info!("sxutil:Other Proposal? {}", dm.target_id),
+                    Err(err) => error!("sxutil: SelectSupply {} without node: {}", dm.target_id, err),
                 }
             }    
         })
@@ -414,18 +508,22 @@ pub fn generate_demand_callback(ndcb: Arc<fn(&SXServiceClient, api::Demand)>, ss
 }
 
 // Composit Subscriber for demand (ndcb = notify demand callback, sscb = selectsupply cb)
-pub async fn combined_subscribe_demand(client: Arc<RwLock<SXServiceClient>>, ndcb: Arc<fn(&SXServiceClient, api::Demand)>, sscb: Arc<fn(&SXServiceClient, api::Demand)>) -> Arc<Mutex<bool>> {
-	let loop_flag = Arc::new(Mutex::new(true));
+pub async fn combined_subscribe_demand<N, NFut, S, SFut>(client: Arc<RwLock<SXServiceClient>>, ndcb: N, sscb: S) -> SubscriptionHandle
+where
+    N: Fn(&SXServiceClient, api::Demand) -> NFut + Send + Sync + 'static,
+    NFut: Future<Output = ()> + Send + 'static,
+    S: Fn(&SXServiceClient, api::Demand) -> SFut + Send + Sync + 'static,
+    SFut: Future<Output = ()> + Send + 'static,
+{
 	let dmcb = generate_demand_callback(ndcb, sscb);
-	tokio::spawn(subscribe_demand(client, dmcb, Arc::clone(&loop_flag))); // loop
-	return loop_flag;
+	simple_subscribe_demand(client, dmcb) // loop
 }
 
 
 pub struct DemandCallbackAsync {
     pub on_notify_demand: Pin<Box<dyn for<'a> Fn(&'a SXServiceClient, &'a api::Demand) -> futures::future::BoxFuture<'a, Option<SupplyOpts>> + Send + Sync>>,
     pub on_select_supply: Pin<Box<dyn for<'a> Fn(&'a SXServiceClient, &'a api::Demand) -> futures::future::BoxFuture<'a, bool> + Send + Sync>>,
-    pub on_confirm_response: Pin<Box<dyn Fn(&SXServiceClient, IDType, Option<Box<dyn std::error::Error>>) -> futures::future::BoxFuture<()> + Send + Sync>>,
+    pub on_confirm_response: Pin<Box<dyn Fn(&SXServiceClient, IDType, Option<SxutilError>) -> futures::future::BoxFuture<()> + Send + Sync>>,
 }
 
 // composit callback with DemandHandler
@@ -435,23 +533,30 @@ pub fn demand_handler_callback(dh: Arc<DemandCallbackAsync>) -> DemandHandler {
         Box::pin(async move {
             if dm.target_id == 0 { // notify supply
                 let mut spo = (dh.on_notify_demand)(clt, &dm).await;
-                if spo.is_some() { // register propose Id.
-                    spo.as_mut().unwrap().target = dm.id; // need to set!
-                    clt.propose_supply(spo.as_ref().unwrap()).await;
+                if let Some(spo) = spo.as_mut() { // register propose Id.
+                    spo.target = dm.id; // need to set!
+                    if let Err(err) = clt.propose_supply(spo).await {
+                        error!("sxutil: ProposeSupply failed {}", err);
+                    }
                     // currentry not used proposed Id.
                 }
             } else { // select supply
                 //
-                info!("SelectSupply: {}: {:?}", dm.target_id, clt.ni.as_ref().unwrap().read().await.node_state.proposed_supply);
-                let pos = clt.ni.as_ref().unwrap().read().await.node_state.proposed_supply_index(dm.target_id);
-                if pos >= 0 { // it is proposed by me.
+                let proposed = match proposed_by_me(clt, dm.target_id).await {
+                    Ok(proposed) => proposed,
+                    Err(err) => {
+                        error!("sxutil: SelectSupply {} without node: {}", dm.target_id, err);
+                        return;
+                    },
+                };
+                if proposed { // it is proposed by me.
                     if (dh.on_select_supply)(clt, &dm).await { // if OK. send Confirm
                         match clt.confirm(dm.id as IDType, dm.target_id as IDType).await {
                             Ok(_) => {
-                                (dh.on_confirm_response)(clt, dm.id as IDType, None);
+                                (dh.on_confirm_response)(clt, dm.id as IDType, None).await;
                             },
                             Err(err) => {
-                                (dh.on_confirm_response)(clt, dm.id as IDType, Some(err));
+                                (dh.on_confirm_response)(clt, dm.id as IDType, Some(err)).await;
                             },
                         }; // send confirm to sender!
                     } else { // no confirm.
@@ -468,11 +573,9 @@ pub fn demand_handler_callback(dh: Arc<DemandCallbackAsync>) -> DemandHandler {
 }
 
 // Register DemandHandler
-pub async fn register_demand_handler(client: Arc<RwLock<SXServiceClient>>, dh: Arc<DemandCallbackAsync>) -> Arc<Mutex<bool>> {
-	let loop_flag = Arc::new(Mutex::new(true));
+pub async fn register_demand_handler(client: Arc<RwLock<SXServiceClient>>, dh: Arc<DemandCallbackAsync>) -> SubscriptionHandle {
 	let dmcb = demand_handler_callback(dh);
-	tokio::spawn(subscribe_demand(client, dmcb, Arc::clone(&loop_flag))); // loop
-	return loop_flag;
+	simple_subscribe_demand(client, dmcb) // loop
 }
 
 
@@ -524,3 +627,115 @@ pub async fn handle_sig_int() {
     //     }
     // });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use core::time::Duration;
+    use tokio::sync::mpsc;
+    use crate::testing::{MockMethod, MockSynerexServer};
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    fn supply_opts(target: u64) -> SupplyOpts {
+        SupplyOpts { id: 0, target, name: String::from("taxi"), json: String::new(), cdata: api::Content::default() }
+    }
+
+    fn demand_opts(name: &str) -> DemandOpts {
+        DemandOpts { id: 0, target: 0, name: String::from(name), json: String::new(), cdata: api::Content::default() }
+    }
+
+    async fn wait_for_subscribers(server: &MockSynerexServer, method: MockMethod, count: usize) {
+        tokio::time::timeout(WAIT, async {
+            while server.subscribers(method) != count {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        }).await.unwrap();
+    }
+
+    fn selection(target_id: u64) -> api::Demand {
+        api::Demand { id: 1, target_id, channel_type: 1, ..Default::default() }
+    }
+
+    #[tokio::test]
+    async fn selection_needs_node_of_client() {
+        let server = MockSynerexServer::start_in_memory().await;
+        let selected = Arc::new(AtomicU64::new(0));
+        let counter = Arc::clone(&selected);
+        let dmcb = generate_demand_callback(
+            |_, _| async {},
+            move |_, dm| {
+                let counter = Arc::clone(&counter);
+                async move { counter.store(dm.target_id, Ordering::SeqCst) }
+            },
+        );
+
+        // client without node can't tell its proposals (no panic)
+        let clt = NodeServInfo::new().new_sx_service_client(server.connect().await.unwrap(), 1, String::new());
+        dmcb(&clt, selection(5)).await;
+        assert_eq!(selected.load(Ordering::SeqCst), 0);
+
+        let clt = SxutilNode::new().new_sx_service_client(server.connect().await.unwrap(), 1, String::new()).await;
+        let proposed = clt.propose_supply(&supply_opts(1)).await.unwrap();
+        dmcb(&clt, selection(proposed + 1)).await;
+        assert_eq!(selected.load(Ordering::SeqCst), 0);
+        dmcb(&clt, selection(proposed)).await;
+        assert_eq!(selected.load(Ordering::SeqCst), proposed);
+    }
+
+    #[tokio::test]
+    async fn closure_handlers_run_with_captured_state() {
+        let server = MockSynerexServer::start_in_memory().await;
+        let node = SxutilNode::new();
+        let sender = node.new_sx_service_client(server.connect().await.unwrap(), 1, String::new()).await;
+        let receiver = node.new_sx_service_client(server.connect().await.unwrap(), 1, String::new()).await;
+        let receiver_id = receiver.client_id;
+        let mbus = sender.create_mbus(api::MbusOpt::default()).await.unwrap();
+        let receiver = Arc::new(RwLock::new(receiver));
+        let (tx, mut received) = mpsc::unbounded_channel();
+
+        let (demand_tx, demands) = (tx.clone(), Arc::new(AtomicU64::new(0)));
+        let demand_count = Arc::clone(&demands);
+        let dmcb = demand_handler(move |clt, dm| {
+            let (tx, count, client_id) = (demand_tx.clone(), Arc::clone(&demand_count), clt.client_id);
+            async move {
+                count.fetch_add(1, Ordering::SeqCst);
+                tx.send(format!("demand {} to {}", dm.demand_name, client_id)).unwrap();
+            }
+        });
+        let supply_tx = tx.clone();
+        let spcb = supply_handler(move |_, sp| {
+            let tx = supply_tx.clone();
+            async move { tx.send(format!("supply {}", sp.supply_name)).unwrap() }
+        });
+        let prefix = String::from("mbus");
+        let mbcb = mbus_handler(move |_, msg| {
+            let (tx, prefix) = (tx.clone(), prefix.clone());
+            async move { tx.send(format!("{} {}", prefix, msg.arg_json)).unwrap() }
+        });
+
+        let demand_sub = simple_subscribe_demand(Arc::clone(&receiver), dmcb);
+        let supply_sub = simple_subscribe_supply(Arc::clone(&receiver), spcb);
+        for clt in [&sender, &*receiver.read().await] {
+            clt.mbus_ids.write().await.push(mbus.mbus_id); // as after select / confirm
+        }
+        let mbus_sub = simple_subscribe_mbus(Arc::clone(&receiver), mbus.mbus_id, mbcb);
+        wait_for_subscribers(&server, MockMethod::SubscribeDemand, 1).await;
+        wait_for_subscribers(&server, MockMethod::SubscribeSupply, 1).await;
+        wait_for_subscribers(&server, MockMethod::SubscribeMbus, 1).await;
+
+        sender.notify_demand(demand_opts("taxi")).await.unwrap();
+        assert_eq!(tokio::time::timeout(WAIT, received.recv()).await.unwrap().unwrap(), format!("demand taxi to {}", receiver_id));
+        sender.notify_supply(supply_opts(0)).await.unwrap();
+        assert_eq!(tokio::time::timeout(WAIT, received.recv()).await.unwrap().unwrap(), "supply taxi");
+        sender.send_mbus_msg(mbus.mbus_id, api::MbusMsg { arg_json: String::from("hello"), ..Default::default() }).await.unwrap();
+        assert_eq!(tokio::time::timeout(WAIT, received.recv()).await.unwrap().unwrap(), "mbus hello");
+        assert_eq!(demands.load(Ordering::SeqCst), 1);
+
+        for sub in [demand_sub, supply_sub, mbus_sub] {
+            sub.cancel();
+            tokio::time::timeout(WAIT, sub.join()).await.unwrap();
+        }
+    }
+}
@@ -1,30 +1,36 @@
+use tokio::sync::{watch, RwLock, Mutex};
 use core::time::Duration;
-use ticker::Ticker;
-use tokio::sync::{RwLock, Mutex};
-use std::{error::Error, sync::Arc};
+use std::sync::{self, Arc, PoisonError, Weak};
 
 use snowflake::SnowflakeIdGenerator;
-use systemstat::{Platform, System};
 
 use synerex_nodeapi::nodeapi;
 use synerex_proto;
 
-use crate::{nodestate::NodeState, GIT_VER, WAIT_TIME, DEFAULT_NI, IDType, SxServerOpt, SXSynerexClient, SXServiceClient};
+use crate::{nodestate::NodeState, ConnectOptions, SxChannel, GIT_VER, IDType, SxServerOpt, ConnectionSlot, SXSynerexClient, SXServiceClient, SxutilError, SxutilConfig, KeepAliveHandle, KeepAliveHandler, ReceiveMetrics};
 
 
 // NodeservInfo is a connection info for each Node Server
 #[derive(Debug)]
 pub struct NodeServInfo {
     // we keep this for each nodeserver.
-    pub node: SnowflakeIdGenerator, // package variable for keeping unique ID.
+    pub node: sync::Mutex<SnowflakeIdGenerator>, // package variable for keeping unique ID (locked only while generating).
     pub nid: nodeapi::NodeId,
     pub nupd: RwLock<nodeapi::NodeUpdate>,
     pub my_node_name: String,
     pub my_server_info: String,
     pub my_node_type: nodeapi::NodeType,
-    pub nodeclt: Option<Arc<Mutex<nodeapi::node_client::NodeClient<tonic::transport::Channel>>>>,
+    pub nodeclt: Option<Arc<Mutex<nodeapi::node_client::NodeClient<SxChannel>>>>,
+    pub nodesrv_address: String,
+    // NodeInfo sent at registration, replayed on reconnect
+    pub node_info: nodeapi::NodeInfo,
+    // sxclient of SXServiceClients created from this node, reconnected on SERVER_CHANGE
+    pub sx_clients: Vec<Weak<ConnectionSlot>>,
+    // notified with new synerex server address on SERVER_CHANGE
+    pub server_change: watch::Sender<String>,
     pub msg_count: u64,
     pub node_state: NodeState,
+    pub config: SxutilConfig,
 }
 
 
@@ -34,7 +40,7 @@ impl NodeServInfo {
         debug!("Initializing NodeServInfo");
         NodeServInfo {
             node_state: NodeState::new(),
-            node: SnowflakeIdGenerator::new(0, 0),
+            node: sync::Mutex::new(SnowflakeIdGenerator::new(0, 0)),
             nid: nodeapi::NodeId {
                 node_id: -1,
                 secret: 0,
@@ -54,28 +60,19 @@ impl NodeServInfo {
             my_node_type: nodeapi::NodeType::Provider,
             // conn: None,
             nodeclt: None,
+            nodesrv_address: String::new(),
+            node_info: nodeapi::NodeInfo::default(),
+            sx_clients: Vec::new(),
+            server_change: watch::channel(String::new()).0,
             msg_count: 0,
+            config: SxutilConfig::default(),
         }
     }
 
     // GetNodeName returns node name from node_id
-    pub async fn get_node_name(&mut self, n: i32) -> String {
-        match self
-            .nodeclt
-            .as_mut()
-            .unwrap()
-            .lock().await
-            .query_node(nodeapi::NodeId {
-                node_id: n,
-                secret: 0,
-                server_info: String::new(),
-                keepalive_duration: 60,
-            })
-            .await
-        {
-            Ok(ni) => ni.get_ref().node_name.clone(),
-            Err(_) => String::from("Unknown"),
-        }
+    pub async fn get_node_name(&self, n: i32) -> Result<String, SxutilError> {
+        let nodeclt = self.nodeclt.clone().ok_or(SxutilError::NotConnected)?;
+        query_node_name(nodeclt, n, self.config.msg_timeout).await
     }
 
     // SetNodeStatus updates KeepAlive info to NodeServer
@@ -85,201 +82,163 @@ impl NodeServInfo {
         nupd.node_arg = arg;
     }
 
-    pub async fn reconnect_node_serv(&mut self) -> Result<(), Box<dyn Error>> {
-        // re_send connection info to server.
-        let nif = nodeapi::NodeInfo {
-            node_name: self.my_node_name.clone(),
-            node_type: self.my_node_type.into(),
-            server_info: self.my_server_info.clone(), // TODO: this is not correctly initialized
-            node_pbase_version: synerex_proto::CHANNEL_TYPE_VERSION.to_string(), // this is defined at compile time
-            with_node_id: self.nid.node_id,
-            bin_version: GIT_VER.to_string(),  // git bin tag version
-            cluster_id: 0,
-            area_id: String::new(),
-            channel_types: Vec::new(),
-            gw_info: String::new(),
-            count: 0,
-            last_alive_time: Some(prost_types::Timestamp {
-                seconds: 0,
-                nanos: 0,
-            }),
-            keepalive_arg: String::new(),
-        };
+    // ReconnectNodeServ re-sends registration info (with current node id) to node server
+    pub async fn reconnect_node_serv(&mut self) -> Result<(), SxutilError> {
+        let nid = self.node_call().await?.register().await?;
+        self.install_node_id(nid).await;
+        Ok(())
+    }
+
+    // NodeCall clones node client and registration, so that node server is called after the lock of NodeServInfo is released
+    pub(crate) async fn node_call(&self) -> Result<NodeCall, SxutilError> {
+        let nodeclt = self.nodeclt.as_ref().ok_or(SxutilError::NotConnected)?.lock().await.clone();
+        let mut node_info = self.node_info.clone();
+        node_info.with_node_id = self.nid.node_id;
+        Ok(NodeCall { nodeclt, nid: self.nid.clone(), node_info, msg_timeout: self.config.msg_timeout })
+    }
 
-        match self.nodeclt.as_mut().unwrap().lock().await.register_node(nif).await {
-            Ok(nid) => {
-                self.nid = nid.get_ref().clone();
-                self.node = snowflake::SnowflakeIdGenerator::new(0, self.nid.node_id);
-                info!("Successfully ReInitialize node {}", self.nid.node_id);
-                self.nupd = RwLock::new(nodeapi::NodeUpdate {
-                    node_id: self.nid.node_id,
-                    secret: self.nid.secret,
-                    update_count: 0,
-                    node_status: 0,
-                    node_arg: String::new(),
-                    status: None,
-                });
+    // InstallNodeId sets NodeId given by registration (and snowflake generator / NodeUpdate for it)
+    pub(crate) async fn install_node_id(&mut self, nid: nodeapi::NodeId) {
+        self.nid = nid;
+        self.init_node_num(self.nid.node_id);
+        info!("Successfully ReInitialize node {}", self.nid.node_id);
+        let mut nupd = self.nupd.write().await;
+        nupd.node_id = self.nid.node_id;
+        nupd.secret = self.nid.secret;
+        nupd.update_count = 0;
+    }
+
+    // ReconnectNodeClient dials node server again (the old channel may be dead after node server restart)
+    pub async fn reconnect_node_client(&mut self) -> Result<(), SxutilError> {
+        if self.nodesrv_address.is_empty() {
+            return Err(SxutilError::InvalidAddress(String::new()));
+        }
+        match self.config.node_connect_options().connect(&self.nodesrv_address).await {
+            Ok(channel) => {
+                self.nodeclt = Some(Arc::from(Mutex::from(nodeapi::node_client::NodeClient::new(channel))));
                 Ok(())
-            }
-            Err(e) => {
-                error!("{:?}", e);
-                Err(Box::from(e))
-            }
+            },
+            Err(err) => {
+                error!("reconnect_node_client: node connection error {:?}", err);
+                Err(err)
+            },
         }
     }
 
-    pub async fn start_keep_alive_with_cmd(
-        &mut self,
-        cmd_func: Option<fn(nodeapi::KeepAliveCommand, String)>,
-    ) {
-        let keepalive_duration = self.nid.keepalive_duration as u64;
-        loop {
-            self.msg_count = 0; // how count message?
-            {
-                debug!(
-                    "KeepAlive {} {}",
-                    // self.nupd.read().as_ref().unwrap().node_status,
-                    self.nupd.read().await.node_status,
-                    keepalive_duration
-                );
-            }
-            tokio::time::sleep(tokio::time::Duration::from_secs(keepalive_duration)).await;
-            if self.nid.secret == 0 {
-                // this means the node is disconnected
-                break;
-            }
-
-            if self.my_node_type == nodeapi::NodeType::Server {
-                // obtain cpu status
-                let sys = System::new();
-                let cpu_percent = match sys.load_average() {
-                    Ok(loadavg) => loadavg.one,
-                    Err(x) => {
-                        error!("\nLoad average: error: {}", x);
-                        0.0
-                    }
-                };
-                let mem_percent = match sys.memory() {
-                    Ok(mem) => {
-                        ((mem.total.as_u64() - mem.free.as_u64()) as f64
-                            / (mem.total.as_u64() as f64))
-                            * 100.0
-                    }
-                    Err(x) => {
-                        error!("\nMemory: error: {}", x);
-                        0.0
-                    }
-                };
-                let status = nodeapi::ServerStatus {
-                    cpu: cpu_percent as f64,
-                    memory: mem_percent,
-                    msg_count: self.msg_count,
-                };
-                self.nupd.write().await.status = Some(status);
-            }
-
-            self.nupd.write().await.update_count += 1;
-
-            let nupd_clone = self.nupd.read().await.clone();
-            let nodeclt_arc = Arc::clone(&self.nodeclt.as_ref().unwrap());
-
-            match nodeclt_arc.lock().await.keep_alive(nupd_clone).await {
-                Ok(resp) => {
-                    // there might be some errors in response
-                    match resp.get_ref().command() {
-                        nodeapi::KeepAliveCommand::None => {}
-                        nodeapi::KeepAliveCommand::Reconnect => {
-                            // order is reconnect to node.
-                            match self.reconnect_node_serv().await {
-                                Ok(_) => {},
-                                Err(_) => { error!("Above error was happend when nodeapi::KeepAliveCommand::Reconnect") },
-                            };
-                        }
-                        nodeapi::KeepAliveCommand::ServerChange => {
-                            info!("receive SERVER_CHANGE\n");
-
-                            if self.node_state.is_safe_state() {
-                                self.un_register_node().await;
-
-                                // if !self.conn.is_none() {
-                                //     // self.conn.unwrap().close();  // TODO: inspect this.
-                                // }
-
-                                if cmd_func.is_some() {
-                                    cmd_func.unwrap()(
-                                        resp.get_ref().command(),
-                                        resp.get_ref().err.clone(),
-                                    );
-                                    self.node_state.init();
-                                }
-                            } else {
-                                // wait
-                                if !self.node_state.locked {
-                                    self.node_state.locked = true;
-                                    // TODO: fix here (currently assume DEFAULT_NI)
-                                    tokio::spawn(async {
-                                        let ticker = Ticker::new(0..1, Duration::from_secs(WAIT_TIME));
-                                        for _ in ticker {
-                                            DEFAULT_NI.write().await.node_state.init();
-                                        }
-                                    });
-                                    // tokio::spawn(lazy_init_node(self));
-                                }
-                            }
-                        }
-                        nodeapi::KeepAliveCommand::ProviderDisconnect => {
-                            info!("receive PROV_DISCONN {:?}\n", resp);
-                            if self.my_node_type != nodeapi::NodeType::Server {
-                                info!(
-                                    "NodeType shoud be SERVER! {:?} {} {:?}",
-                                    self.my_node_type, self.my_node_name, resp
-                                );
-                            } else if !cmd_func.is_none() {
-                                // work provider disconnect
-                                cmd_func.unwrap()(resp.get_ref().command(), resp.get_ref().err.clone());
-                            }
-                        }
-                    }
-
-                    true
-                }
-                Err(e) => {
-                    error!("Error in response, may nodeserv failure {:?}", e);
-                    false
-                }
-            };
+    // UpdateChannels replaces channel types of this node and re-registers if already registered
+    pub async fn update_channels(&mut self, channels: Vec<u32>) -> Result<(), SxutilError> {
+        self.node_info.channel_types = channels;
+        self.push_node_info().await
+    }
+
+    // UpdateArea replaces area id of this node and re-registers if already registered
+    pub async fn update_area(&mut self, area_id: String) -> Result<(), SxutilError> {
+        self.node_info.area_id = area_id;
+        self.push_node_info().await
+    }
+
+    // UpdateNodeInfo changes registration info, then re-registers (if registered) without holding the lock
+    pub(crate) async fn update_node_info<F: FnOnce(&mut nodeapi::NodeInfo)>(ni: &Arc<RwLock<NodeServInfo>>, update: F) -> Result<(), SxutilError> {
+        let registered = {
+            let mut ni = ni.write().await;
+            update(&mut ni.node_info);
+            ni.nid.secret != 0
+        };
+        if !registered {
+            return Ok(()); // sent at next registration
+        }
+        Self::register_again(ni).await.map(|_| ())
+    }
+
+    // RegisterAgain registers with the same node id.
+    // node server is called without holding the lock, which is taken again only to install the new NodeId.
+    pub(crate) async fn register_again(ni: &Arc<RwLock<NodeServInfo>>) -> Result<i32, SxutilError> {
+        let mut call = ni.read().await.node_call().await?;
+        let nid = call.register().await?;
+        let node_id = nid.node_id;
+        ni.write().await.install_node_id(nid).await;
+        Ok(node_id)
+    }
+
+    // UnRegister unregisters without holding the lock during the call
+    pub(crate) async fn un_register(ni: &Arc<RwLock<NodeServInfo>>) -> Result<(), SxutilError> {
+        let mut call = {
+            let ni = ni.read().await;
+            info!("UnRegister Node {:?}", ni.nid);
+            ni.node_call().await?
+        };
+        let result = call.un_register().await;
+        ni.write().await.nid.secret = 0;
+        result
+    }
+
+    async fn push_node_info(&mut self) -> Result<(), SxutilError> {
+        if self.nid.secret == 0 {
+            return Ok(()); // sent at next registration
         }
+        self.reconnect_node_serv().await
+    }
+
+    // AddSxClient keeps sxclient of SXServiceClient to reconnect it on SERVER_CHANGE
+    pub fn add_sx_client(&mut self, sxclient: &Arc<ConnectionSlot>) {
+        self.sx_clients.retain(|clt| clt.strong_count() > 0);
+        self.sx_clients.push(Arc::downgrade(sxclient));
+    }
+
+    // StartKeepAliveWithCmd starts keepalive task for given node.
+    // The task finishes when the node is unregistered or handle.cancel() / handle.stop() is called.
+    pub fn start_keep_alive_with_cmd(
+        ni: &Arc<RwLock<NodeServInfo>>,
+        handler: Option<Arc<dyn KeepAliveHandler>>,
+    ) -> KeepAliveHandle {
+        KeepAliveHandle::spawn(Arc::clone(ni), handler)
     }
 
     pub fn msg_count_up(&mut self) {
         self.msg_count += 1;
     }
 
-    pub async fn un_register_node(&mut self) {
+    pub async fn un_register_node(&mut self) -> Result<(), SxutilError> {
         info!("UnRegister Node {:?}", self.nid);
-        let nid = self.nid.clone(); // TODO: fix nid definition,
-        match self.nodeclt.as_mut().unwrap().lock().await.un_register_node(nid).await {
-            Ok(resp) => {
-                if !resp.get_ref().ok {
-                    error!("Can't unregister (resp.ok == false)");
-                }
-            }
-            Err(err) => {
-                error!("Can't unregister {}", err);
-            }
-        };
+        let result = self.node_call().await?.un_register().await;
         self.nid.secret = 0;
+        result
     }
 
     // RegisterNodeWithCmd is a function to register Node with node server address and KeepAlive Command Callback
-    pub async fn register_node_with_cmd(&mut self, nodesrv: String, nm: String, channels: Vec<u32>, serv: Option<&SxServerOpt>, _cmd_func: Option<fn(nodeapi::KeepAliveCommand, String)>) -> Result<String, &str> { // register ID to server
-        self.nodeclt = match nodeapi::node_client::NodeClient::connect(nodesrv).await {
-            Ok(clt) => Some(Arc::from(Mutex::from(clt))),
-            Err(err) => { error!("{:?}", err); None },
+    // keepalive is started automatically, the returned handle can stop it (and unregister the node)
+    pub async fn register_node_with_cmd(
+        ni: &Arc<RwLock<NodeServInfo>>,
+        nodesrv: String,
+        nm: String,
+        channels: Vec<u32>,
+        serv: Option<&SxServerOpt>,
+        handler: Option<Arc<dyn KeepAliveHandler>>,
+    ) -> Result<KeepAliveHandle, SxutilError> {
+        let (opts, msg_timeout) = {
+            let ni = ni.read().await;
+            (ni.config.node_connect_options().clone(), ni.config.msg_timeout)
         };
-        if self.nodeclt.is_none() {
-            return Err("register_node_with_cmd: node connection error");
-        }
+        let nodeclt = dial_node_server(&opts, &nodesrv, msg_timeout).await?;
+        let mut call = ni.write().await.prepare_registration(nodeclt, nodesrv, nm, channels, serv).await?;
+        let nid = call.register().await?;
+        ni.write().await.install_registration(nid).await;
+        Ok(Self::start_keep_alive_with_cmd(ni, handler))
+    }
+
+    // RegisterNode only registers this node to node server and returns server info.
+    // keepalive is not started, use start_keep_alive_with_cmd (or register_node_with_cmd).
+    pub async fn register_node(&mut self, nodesrv: String, nm: String, channels: Vec<u32>, serv: Option<&SxServerOpt>) -> Result<String, SxutilError> { // register ID to server
+        let nodeclt = dial_node_server(self.config.node_connect_options(), &nodesrv, self.config.msg_timeout).await?; // config.node_connect (or config.connect)
+        let nid = self.prepare_registration(nodeclt, nodesrv, nm, channels, serv).await?.register().await?;
+        self.install_registration(nid).await;
+        Ok(self.nid.server_info.clone())
+    }
+
+    // sets node client and registration info, returns NodeCall to register them
+    async fn prepare_registration(&mut self, nodeclt: nodeapi::node_client::NodeClient<SxChannel>, nodesrv: String, nm: String, channels: Vec<u32>, serv: Option<&SxServerOpt>) -> Result<NodeCall, SxutilError> {
+        self.nodeclt = Some(Arc::new(Mutex::new(nodeclt)));
+        self.nodesrv_address = nodesrv;
 
         let node_id: i32 = self.nid.node_id;
         self.my_node_type = nodeapi::NodeType::Provider;
@@ -300,77 +259,134 @@ impl NodeServInfo {
             keepalive_arg: String::from(""),
         };
 
-        if serv.is_some() {
-            self.my_node_type = serv.unwrap().node_type;
-            self.my_server_info = serv.unwrap().server_info.clone();
+        if let Some(serv) = serv {
+            self.my_node_type = serv.node_type;
+            self.my_server_info = serv.server_info.clone();
             nif.node_type = self.my_node_type.into();
             nif.server_info = self.my_server_info.clone();
-            nif.cluster_id = serv.unwrap().cluster_id;
-            nif.area_id = serv.unwrap().area_id.clone();
-            nif.gw_info = serv.unwrap().gw_info.clone();
-        }
-
-        self.nid = match self.nodeclt.as_mut().unwrap().lock().await.register_node(nif).await {
-            Ok(resp) => resp.get_ref().clone(),
-            Err(status) => {
-                error!("{:?}", status);
-                nodeapi::NodeId {
-                    node_id: -1,
-                    secret: 0,
-                    server_info: String::from(""),
-                    keepalive_duration: -1,
-                }
-            },
-        };
-        if self.nid.keepalive_duration == -1 {  // register_node error
-            return Err("register_node error");
+            nif.cluster_id = serv.cluster_id;
+            nif.area_id = serv.area_id.clone();
+            nif.gw_info = serv.gw_info.clone();
         }
 
-        self.node = snowflake::SnowflakeIdGenerator::new(0, self.nid.node_id);
-
-        *self.nupd.write().await = nodeapi::NodeUpdate {
-            node_id: self.nid.node_id,
-            secret: self.nid.secret,
-            update_count: 0,
-            node_status: 0,
-            node_arg: String::from(""),
-            status: None,
-        };
-        // if let Ok(mut nupd) = self.nupd.write() {
-        //     *nupd = nodeapi::NodeUpdate {
-        //         node_id: self.nid.node_id,
-        //         secret: self.nid.secret,
-        //         update_count: 0,
-        //         node_status: 0,
-        //         node_arg: String::from(""),
-        //         status: None,
-        //     };
-        // }
-
-        // start keepalive routine
-        // tokio::spawn(self.startKeepAliveWithCmd(cmd_func));
-        // go ni.startKeepAliveWithCmd(cmd_func)
-        // //	fmt.Println("KeepAlive started!")
+        self.node_info = nif;
+        self.node_call().await
+    }
 
-        Ok(self.nid.server_info.clone())
+    // InstallRegistration sets NodeId of first registration (NodeUpdate is reset)
+    async fn install_registration(&mut self, nid: nodeapi::NodeId) {
+        self.install_node_id(nid).await;
+        let mut nupd = self.nupd.write().await;
+        nupd.node_status = 0;
+        nupd.node_arg = String::new();
+        nupd.status = None;
     }
 
     // NewSXServiceClient Creates wrapper structre SXServiceClient from SynerexClient
     // Warning: In Rust version, this function is not used.
     pub fn new_sx_service_client(&mut self, clt: SXSynerexClient, mtype: u32, arg_json: String) -> SXServiceClient {
+        let sxclient = Arc::new(ConnectionSlot::new(Some(clt)));
+        self.add_sx_client(&sxclient);
         SXServiceClient {
-            client_id: IDType::from(self.node.generate() as u64),
+            client_id: IDType::from(self.generate_int_id()),
             channel_type: mtype,
-            sxclient: RwLock::from(Some(clt)),
+            sxclient,
             arg_json,
-            mbus_ids: RwLock::from(Vec::new()),
+            mbus_ids: Arc::new(RwLock::from(Vec::new())),
             ni: None,
+            config: self.config.clone(),
+            receive_metrics: Arc::new(ReceiveMetrics::default()),
+        }
+    }
+
+    // InitNodeNum for initialize NodeNum again
+    pub fn init_node_num(&mut self, n: i32) {
+        *self.node.get_mut().unwrap_or_else(PoisonError::into_inner) = SnowflakeIdGenerator::new(0, n);
+    }
+
+    // GenerateIntID for generate uniquie ID (needs only read lock of NodeServInfo)
+    pub fn generate_int_id(&self) -> u64 {
+        self.node.lock().unwrap_or_else(PoisonError::into_inner).generate() as u64
+    }
+}
+
+// NodeCall is a node client and registration cloned from NodeServInfo.
+// Each call is bounded by msg_timeout (also sent as gRPC deadline).
+pub(crate) struct NodeCall {
+    nodeclt: nodeapi::node_client::NodeClient<SxChannel>,
+    nid: nodeapi::NodeId,
+    node_info: nodeapi::NodeInfo,
+    msg_timeout: Duration,
+}
+
+impl NodeCall {
+    // Register sends registration again with current node id, returns new NodeId
+    pub(crate) async fn register(&mut self) -> Result<nodeapi::NodeId, SxutilError> {
+        let req = node_request(self.node_info.clone(), self.msg_timeout);
+        match tokio::time::timeout(self.msg_timeout, self.nodeclt.register_node(req)).await? {
+            Ok(nid) => Ok(nid.into_inner()),
+            Err(status) => {
+                error!("{:?}", status);
+                Err(SxutilError::from(status))
+            },
+        }
+    }
+
+    pub(crate) async fn keep_alive(&mut self, nupd: nodeapi::NodeUpdate) -> Result<nodeapi::Response, SxutilError> {
+        let req = node_request(nupd, self.msg_timeout);
+        Ok(tokio::time::timeout(self.msg_timeout, self.nodeclt.keep_alive(req)).await??.into_inner())
+    }
+
+    pub(crate) async fn un_register(&mut self) -> Result<(), SxutilError> {
+        let req = node_request(self.nid.clone(), self.msg_timeout);
+        match tokio::time::timeout(self.msg_timeout, self.nodeclt.un_register_node(req)).await? {
+            Ok(resp) if resp.get_ref().ok => Ok(()),
+            Ok(_) => {
+                error!("Can't unregister (resp.ok == false)");
+                Err(SxutilError::NodeServerRejected(String::from("can't unregister node")))
+            },
+            Err(status) => {
+                error!("Can't unregister {}", status);
+                Err(SxutilError::from(status))
+            },
         }
     }
+}
 
-    // GenerateIntID for generate uniquie ID
-    pub fn generate_int_id(&mut self) -> u64 {
-        self.node.generate() as u64
+// dials node server within msg_timeout
+pub(crate) async fn dial_node_server(opts: &ConnectOptions, nodesrv: &str, msg_timeout: Duration) -> Result<nodeapi::node_client::NodeClient<SxChannel>, SxutilError> {
+    if nodesrv.is_empty() {
+        return Err(SxutilError::InvalidAddress(String::new()));
     }
+    match tokio::time::timeout(msg_timeout, opts.connect(nodesrv)).await? {
+        Ok(channel) => Ok(nodeapi::node_client::NodeClient::new(channel)),
+        Err(err) => {
+            error!("node connection error {:?}", err);
+            Err(err)
+        },
+    }
+}
+
+fn node_request<T>(msg: T, msg_timeout: Duration) -> tonic::Request<T> {
+    let mut req = tonic::Request::new(msg);
+    req.set_timeout(msg_timeout);
+    req
 }
 
+// QueryNode on node client cloned from NodeServInfo (no lock is held during the call)
+pub(crate) async fn query_node_name(nodeclt: Arc<Mutex<nodeapi::node_client::NodeClient<SxChannel>>>, n: i32, msg_timeout: Duration) -> Result<String, SxutilError> {
+    let mut nodeclt = nodeclt.lock().await.clone();
+    let req = node_request(nodeapi::NodeId {
+        node_id: n,
+        secret: 0,
+        server_info: String::new(),
+        keepalive_duration: 60,
+    }, msg_timeout);
+    match tokio::time::timeout(msg_timeout, nodeclt.query_node(req)).await? {
+        Ok(ni) => Ok(ni.into_inner().node_name),
+        Err(status) => {
+            error!("Error on QueryNode {}", status);
+            Err(SxutilError::from(status))
+        },
+    }
+}
@@ -0,0 +1,304 @@
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+use crate::{SenderId, SxutilError};
+
+// OverflowPolicy decides what to do when receive buffer is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    // stop receiving until handler takes a message
+    Block,
+    // drop the oldest buffered message
+    DropOldest,
+    // drop the received message
+    DropNewest,
+    // buffer keeps only the latest message of each sender_id
+    // (replaces older one of the same sender, drops the oldest when full)
+    KeepLatestPerSender,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = SxutilError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "block" => Ok(OverflowPolicy::Block),
+            "drop_oldest" => Ok(OverflowPolicy::DropOldest),
+            "drop_newest" => Ok(OverflowPolicy::DropNewest),
+            "keep_latest_per_sender" => Ok(OverflowPolicy::KeepLatestPerSender),
+            _ => Err(SxutilError::Config(format!("unknown overflow policy: {}", s))),
+        }
+    }
+}
+
+// ReceiveBufferOptions for subscription loops of SXServiceClient
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReceiveBufferOptions {
+    pub capacity: usize,
+    pub policy: OverflowPolicy,
+}
+
+impl ReceiveBufferOptions {
+    pub fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        ReceiveBufferOptions {
+            capacity: capacity.max(1),
+            policy,
+        }
+    }
+}
+
+// ReceiveMetrics counts messages passed through receive buffer of SXServiceClient
+// (shared by all subscriptions of the client and its clones, counters are totals of them)
+#[derive(Debug, Default)]
+pub struct ReceiveMetrics {
+    received: AtomicU64,
+    dropped: AtomicU64,
+    buffered: AtomicUsize,
+    max_buffered: AtomicUsize,
+}
+
+impl ReceiveMetrics {
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    // messages dropped by overflow policy
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    // messages waiting for handler now (in all buffers)
+    pub fn buffered(&self) -> usize {
+        self.buffered.load(Ordering::Relaxed)
+    }
+
+    // high-water mark of buffered messages (in all buffers)
+    pub fn max_buffered(&self) -> usize {
+        self.max_buffered.load(Ordering::Relaxed)
+    }
+}
+
+struct BufferState<M> {
+    queue: VecDeque<M>,
+    closed: bool,
+}
+
+// bounded buffer between receiving stream and handler (one writer, one reader)
+pub(crate) struct ReceiveBuffer<M> {
+    state: Mutex<BufferState<M>>,
+    readable: Notify,
+    writable: Notify,
+    opts: ReceiveBufferOptions,
+    metrics: Arc<ReceiveMetrics>,
+}
+
+impl<M: SenderId> ReceiveBuffer<M> {
+    pub(crate) fn new(opts: ReceiveBufferOptions, metrics: Arc<ReceiveMetrics>) -> Self {
+        ReceiveBuffer {
+            state: Mutex::new(BufferState { queue: VecDeque::with_capacity(opts.capacity), closed: false }),
+            readable: Notify::new(),
+            writable: Notify::new(),
+            opts,
+            metrics,
+        }
+    }
+
+    pub(crate) async fn push(&self, msg: M) {
+        self.metrics.received.fetch_add(1, Ordering::Relaxed);
+        let mut msg = Some(msg);
+        loop {
+            {
+                let mut st = self.state.lock().unwrap();
+                let len = st.queue.len();
+                if let Some(dropped) = self.push_locked(&mut st, &mut msg) {
+                    if dropped {
+                        self.metrics.dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                    if st.queue.len() > len {
+                        let buffered = self.metrics.buffered.fetch_add(1, Ordering::Relaxed) + 1;
+                        self.metrics.max_buffered.fetch_max(buffered, Ordering::Relaxed);
+                    }
+                    break;
+                }
+            }
+            self.writable.notified().await; // Block: wait for handler
+        }
+        self.readable.notify_one();
+    }
+
+    // returns None when msg should wait (Block), Some(true) when a message is dropped
+    fn push_locked(&self, st: &mut BufferState<M>, msg: &mut Option<M>) -> Option<bool> {
+        let full = st.queue.len() >= self.opts.capacity;
+        match self.opts.policy {
+            OverflowPolicy::KeepLatestPerSender => {
+                let sender_id = msg.as_ref()?.sender_id();
+                if let Some(pos) = st.queue.iter().position(|m| m.sender_id() == sender_id) {
+                    st.queue[pos] = msg.take()?;
+                    return Some(true);
+                }
+                let dropped = full && st.queue.pop_front().is_some();
+                st.queue.push_back(msg.take()?);
+                Some(dropped)
+            },
+            _ if !full => {
+                st.queue.push_back(msg.take()?);
+                Some(false)
+            },
+            OverflowPolicy::Block => None,
+            OverflowPolicy::DropOldest => {
+                st.queue.pop_front();
+                st.queue.push_back(msg.take()?);
+                Some(true)
+            },
+            OverflowPolicy::DropNewest => {
+                msg.take();
+                Some(true)
+            },
+        }
+    }
+
+    // returns None after close() and all messages are taken
+    pub(crate) async fn pop(&self) -> Option<M> {
+        loop {
+            {
+                let mut st = self.state.lock().unwrap();
+                if let Some(msg) = st.queue.pop_front() {
+                    self.metrics.buffered.fetch_sub(1, Ordering::Relaxed);
+                    drop(st);
+                    self.writable.notify_one();
+                    return Some(msg);
+                }
+                if st.closed {
+                    return None;
+                }
+            }
+            self.readable.notified().await;
+        }
+    }
+
+    pub(crate) fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.readable.notify_one();
+    }
+}
+
+impl<M> Drop for ReceiveBuffer<M> {
+    // messages left in the buffer are no longer waiting for handler
+    fn drop(&mut self) {
+        let left = self.state.get_mut().map(|st| st.queue.len()).unwrap_or_default();
+        self.metrics.buffered.fetch_sub(left, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use synerex_api::api;
+
+    fn buffer(capacity: usize, policy: OverflowPolicy) -> ReceiveBuffer<api::Supply> {
+        ReceiveBuffer::new(ReceiveBufferOptions::new(capacity, policy), Arc::new(ReceiveMetrics::default()))
+    }
+
+    fn supply(id: u64, sender_id: u64) -> api::Supply {
+        api::Supply { id, sender_id, ..Default::default() }
+    }
+
+    async fn drain(buffer: &ReceiveBuffer<api::Supply>) -> Vec<u64> {
+        buffer.close();
+        let mut ids = Vec::new();
+        while let Some(sp) = buffer.pop().await {
+            ids.push(sp.id);
+        }
+        ids
+    }
+
+    // pushes interleaved with other subscriptions
+    async fn push_all(buffer: &ReceiveBuffer<api::Supply>, ids: std::ops::RangeInclusive<u64>) {
+        for id in ids {
+            buffer.push(supply(id, id)).await;
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn block_waits_for_handler() {
+        let buffer = buffer(2, OverflowPolicy::Block);
+        buffer.push(supply(1, 1)).await;
+        buffer.push(supply(2, 1)).await;
+
+        let mut third = Box::pin(buffer.push(supply(3, 1)));
+        assert!(tokio::time::timeout(Duration::from_millis(20), third.as_mut()).await.is_err());
+        assert_eq!(buffer.pop().await.map(|sp| sp.id), Some(1));
+        tokio::time::timeout(Duration::from_secs(5), third).await.unwrap();
+
+        assert_eq!(drain(&buffer).await, vec![2, 3]);
+        assert_eq!((buffer.metrics.received(), buffer.metrics.dropped(), buffer.metrics.max_buffered()), (3, 0, 2));
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_keeps_newer_messages() {
+        let buffer = buffer(2, OverflowPolicy::DropOldest);
+        for id in 1..=4 {
+            buffer.push(supply(id, 1)).await;
+        }
+        assert_eq!(buffer.metrics.buffered(), 2);
+        assert_eq!(drain(&buffer).await, vec![3, 4]);
+        assert_eq!((buffer.metrics.received(), buffer.metrics.dropped(), buffer.metrics.max_buffered()), (4, 2, 2));
+        assert_eq!(buffer.metrics.buffered(), 0);
+    }
+
+    #[tokio::test]
+    async fn drop_newest_keeps_buffered_messages() {
+        let buffer = buffer(2, OverflowPolicy::DropNewest);
+        for id in 1..=4 {
+            buffer.push(supply(id, 1)).await;
+        }
+        assert_eq!(drain(&buffer).await, vec![1, 2]);
+        assert_eq!((buffer.metrics.received(), buffer.metrics.dropped(), buffer.metrics.max_buffered()), (4, 2, 2));
+    }
+
+    #[tokio::test]
+    async fn keep_latest_per_sender_replaces_in_place() {
+        let buffer = buffer(2, OverflowPolicy::KeepLatestPerSender);
+        buffer.push(supply(1, 10)).await;
+        buffer.push(supply(2, 20)).await;
+        buffer.push(supply(3, 10)).await; // replaces 1 (keeps its position)
+        assert_eq!(buffer.metrics.dropped(), 1);
+        buffer.push(supply(4, 30)).await; // full: drops 3, the oldest
+        assert_eq!(drain(&buffer).await, vec![2, 4]);
+        assert_eq!((buffer.metrics.received(), buffer.metrics.dropped(), buffer.metrics.max_buffered()), (4, 2, 2));
+    }
+
+    #[tokio::test]
+    async fn high_water_mark_is_kept_after_pop() {
+        let buffer = buffer(8, OverflowPolicy::Block);
+        for id in 1..=5 {
+            buffer.push(supply(id, id)).await;
+        }
+        for _ in 0..4 {
+            buffer.pop().await;
+        }
+        buffer.push(supply(6, 6)).await;
+        assert_eq!((buffer.metrics.buffered(), buffer.metrics.max_buffered()), (2, 5));
+    }
+
+    #[tokio::test]
+    async fn subscriptions_sharing_metrics_add_up() {
+        let metrics = Arc::new(ReceiveMetrics::default());
+        let opts = ReceiveBufferOptions::new(4, OverflowPolicy::DropOldest);
+        let (demands, supplies) = (ReceiveBuffer::new(opts, Arc::clone(&metrics)), ReceiveBuffer::new(opts, Arc::clone(&metrics)));
+        tokio::join!(push_all(&demands, 1..=3), push_all(&supplies, 11..=16)); // supplies drops 2
+        assert_eq!((metrics.received(), metrics.dropped(), metrics.buffered(), metrics.max_buffered()), (9, 2, 7, 7));
+
+        demands.pop().await;
+        assert_eq!(metrics.buffered(), 6);
+        drop(supplies); // subscription ended with buffered messages
+        assert_eq!((metrics.buffered(), metrics.max_buffered()), (2, 7));
+        assert_eq!(drain(&demands).await, vec![2, 3]);
+        assert_eq!(metrics.buffered(), 0);
+    }
+}
@@ -0,0 +1,268 @@
+use core::time::Duration;
+use std::fmt;
+use std::sync::Arc;
+use tokio::time::Instant;
+
+use rand::Rng;
+
+// default duration after which a subscription is regarded as recovered
+const RESET_AFTER: u64 = 30;
+
+// Backoff decides wait before each reconnection (base is SxutilConfig::reconnect_wait)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backoff {
+    // wait base every time
+    Fixed,
+    // full jitter: random wait in [0, min(max_wait, base * 2^(attempt-1))]
+    ExponentialJitter { max_wait: Duration },
+}
+
+// GiveUp is reported when reconnection is given up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GiveUp {
+    // "Demand", "Supply" or "Mbus"
+    pub subscription: &'static str,
+    pub server_address: String,
+    pub attempts: u32,
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for GiveUp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "gave up reconnecting {} subscription to [{}] after {} attempts ({:?})",
+            self.subscription, self.server_address, self.attempts, self.elapsed)
+    }
+}
+
+type GiveUpCallback = Arc<dyn Fn(&GiveUp) + Send + Sync>;
+
+// ReconnectPolicy for subscribe_demand / subscribe_supply / subscribe_mbus loops
+#[derive(Clone)]
+pub struct ReconnectPolicy {
+    pub backoff: Backoff,
+    // give up after this number of consecutive reconnections (None: retry forever)
+    pub max_attempts: Option<u32>,
+    // give up when reconnecting for this duration (None: retry forever)
+    pub max_elapsed: Option<Duration>,
+    // a subscription alive longer than this is regarded as recovered (attempts are reset)
+    pub reset_after: Duration,
+    on_give_up: Option<GiveUpCallback>,
+}
+
+impl fmt::Debug for ReconnectPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ReconnectPolicy")
+            .field("backoff", &self.backoff)
+            .field("max_attempts", &self.max_attempts)
+            .field("max_elapsed", &self.max_elapsed)
+            .field("reset_after", &self.reset_after)
+            .field("on_give_up", &self.on_give_up.is_some())
+            .finish()
+    }
+}
+
+impl PartialEq for ReconnectPolicy {
+    fn eq(&self, other: &Self) -> bool {
+        let same_callback = match (&self.on_give_up, &other.on_give_up) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        self.backoff == other.backoff
+            && self.max_attempts == other.max_attempts
+            && self.max_elapsed == other.max_elapsed
+            && self.reset_after == other.reset_after
+            && same_callback
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self::fixed()
+    }
+}
+
+impl ReconnectPolicy {
+    // wait reconnect_wait and retry forever (same as Go version)
+    pub fn fixed() -> Self {
+        ReconnectPolicy {
+            backoff: Backoff::Fixed,
+            max_attempts: None,
+            max_elapsed: None,
+            reset_after: Duration::from_secs(RESET_AFTER),
+            on_give_up: None,
+        }
+    }
+
+    // exponential backoff from reconnect_wait up to max_wait with full jitter
+    pub fn exponential(max_wait: Duration) -> Self {
+        ReconnectPolicy {
+            backoff: Backoff::ExponentialJitter { max_wait },
+            ..Self::fixed()
+        }
+    }
+
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+
+    pub fn with_max_elapsed(mut self, max_elapsed: Duration) -> Self {
+        self.max_elapsed = Some(max_elapsed);
+        self
+    }
+
+    pub fn with_reset_after(mut self, reset_after: Duration) -> Self {
+        self.reset_after = reset_after;
+        self
+    }
+
+    // OnGiveUp sets callback called when a subscription gives up reconnecting
+    pub fn on_give_up<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&GiveUp) + Send + Sync + 'static,
+    {
+        self.on_give_up = Some(Arc::new(callback));
+        self
+    }
+
+    // wait before attempt-th reconnection (attempt starts from 1)
+    pub fn delay(&self, base: Duration, attempt: u32) -> Duration {
+        match self.backoff {
+            Backoff::Fixed => base,
+            Backoff::ExponentialJitter { max_wait } => {
+                let exp = attempt.saturating_sub(1).min(31);
+                let cap = base.saturating_mul(1 << exp).min(max_wait);
+                cap.mul_f64(rand::thread_rng().gen::<f64>())
+            },
+        }
+    }
+
+    pub(crate) fn give_up(&self, give_up: &GiveUp) {
+        error!("sxutil: {}", give_up);
+        if let Some(callback) = &self.on_give_up {
+            callback(give_up);
+        }
+    }
+}
+
+// consecutive reconnections of a subscription loop
+#[derive(Debug, Default)]
+pub(crate) struct ReconnectState {
+    attempts: u32,
+    since: Option<Instant>,
+}
+
+impl ReconnectState {
+    // called when subscription finished after `alive`.
+    // returns wait before reconnecting, or Err(attempts, elapsed) to give up.
+    pub(crate) fn next_delay(&mut self, policy: &ReconnectPolicy, base: Duration, alive: Duration) -> Result<Duration, (u32, Duration)> {
+        if alive >= policy.reset_after {
+            self.attempts = 0;
+            self.since = None;
+        }
+        let since = *self.since.get_or_insert_with(Instant::now);
+        let elapsed = since.elapsed();
+        let exceeded = policy.max_attempts.is_some_and(|max| self.attempts >= max)
+            || policy.max_elapsed.is_some_and(|max| elapsed >= max);
+        if exceeded {
+            return Err((self.attempts, elapsed));
+        }
+        self.attempts += 1;
+        Ok(policy.delay(base, self.attempts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: Duration = Duration::from_millis(100);
+
+    #[test]
+    fn fixed_waits_base() {
+        let policy = ReconnectPolicy::fixed();
+        for attempt in [1, 2, 10, u32::MAX] {
+            assert_eq!(policy.delay(BASE, attempt), BASE);
+        }
+    }
+
+    #[test]
+    fn exponential_jitter_is_capped() {
+        let max_wait = Duration::from_secs(1);
+        let policy = ReconnectPolicy::exponential(max_wait);
+        for attempt in 1..=40 {
+            let cap = BASE.saturating_mul(1 << (attempt - 1).min(31)).min(max_wait);
+            for _ in 0..50 {
+                assert!(policy.delay(BASE, attempt) <= cap, "attempt {}", attempt);
+            }
+        }
+        assert!(policy.delay(BASE, u32::MAX) <= max_wait);
+        assert!(policy.delay(Duration::MAX, 1) <= max_wait);
+    }
+
+    #[test]
+    fn exponential_jitter_is_random() {
+        let policy = ReconnectPolicy::exponential(Duration::from_secs(10));
+        let delays: Vec<Duration> = (0..100).map(|_| policy.delay(BASE, 4)).collect();
+        assert!(delays.iter().any(|delay| *delay != delays[0]));
+        assert!(delays.iter().any(|delay| *delay < BASE * 4)); // below the middle of [0, 800ms]
+        assert!(delays.iter().any(|delay| *delay > BASE * 4));
+    }
+
+    #[tokio::test]
+    async fn max_attempts_gives_up() {
+        tokio::time::pause();
+        let policy = ReconnectPolicy::fixed().with_max_attempts(3);
+        let mut state = ReconnectState::default();
+        for _ in 0..3 {
+            assert_eq!(state.next_delay(&policy, BASE, Duration::ZERO), Ok(BASE));
+        }
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert_eq!(state.next_delay(&policy, BASE, Duration::ZERO), Err((3, Duration::from_secs(2))));
+    }
+
+    #[tokio::test]
+    async fn max_elapsed_gives_up() {
+        tokio::time::pause();
+        let policy = ReconnectPolicy::fixed().with_max_elapsed(Duration::from_secs(10));
+        let mut state = ReconnectState::default();
+        assert_eq!(state.next_delay(&policy, BASE, Duration::ZERO), Ok(BASE));
+        tokio::time::advance(Duration::from_secs(9)).await;
+        assert_eq!(state.next_delay(&policy, BASE, Duration::ZERO), Ok(BASE));
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert_eq!(state.next_delay(&policy, BASE, Duration::ZERO), Err((2, Duration::from_secs(10))));
+    }
+
+    #[tokio::test]
+    async fn long_alive_subscription_resets_attempts() {
+        tokio::time::pause();
+        let policy = ReconnectPolicy::fixed()
+            .with_max_attempts(2)
+            .with_max_elapsed(Duration::from_secs(60))
+            .with_reset_after(Duration::from_secs(30));
+        let mut state = ReconnectState::default();
+        assert!(state.next_delay(&policy, BASE, Duration::ZERO).is_ok());
+        assert!(state.next_delay(&policy, BASE, Duration::from_secs(29)).is_ok());
+        assert!(state.next_delay(&policy, BASE, Duration::from_secs(29)).is_err());
+
+        // alive for reset_after: attempts and elapsed start again
+        tokio::time::advance(Duration::from_secs(90)).await;
+        assert_eq!(state.next_delay(&policy, BASE, Duration::from_secs(30)), Ok(BASE));
+        assert_eq!(state.next_delay(&policy, BASE, Duration::ZERO), Ok(BASE));
+        assert_eq!(state.next_delay(&policy, BASE, Duration::ZERO), Err((2, Duration::ZERO)));
+    }
+
+    #[test]
+    fn give_up_calls_callback() {
+        let called = Arc::new(std::sync::Mutex::new(None));
+        let policy = {
+            let called = Arc::clone(&called);
+            ReconnectPolicy::fixed().on_give_up(move |give_up| *called.lock().unwrap() = Some(give_up.clone()))
+        };
+        let give_up = GiveUp { subscription: "Supply", server_address: String::from("127.0.0.1:10000"), attempts: 3, elapsed: BASE };
+        policy.give_up(&give_up);
+        assert_eq!(called.lock().unwrap().as_ref(), Some(&give_up));
+        assert_ne!(policy, ReconnectPolicy::fixed());
+    }
+}
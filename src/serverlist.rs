@@ -0,0 +1,261 @@
+use core::time::Duration;
+use std::sync::Arc;
+
+use rand::Rng;
+
+use crate::{dial_server, ConnectOptions, SXSynerexClient, SxutilError};
+
+// ServerSelection decides which server of ServerList is used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerSelection {
+    // the first reachable server in list order (the first one is primary)
+    Ordered,
+    // weighted random choice (weight 0 is used only when others are unreachable)
+    Weighted,
+}
+
+// ServerEntry is a synerex server address with its weight
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerEntry {
+    pub address: String,
+    pub weight: u32,
+}
+
+// ServerList is a list of synerex servers for failover
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerList {
+    pub servers: Vec<ServerEntry>,
+    pub selection: ServerSelection,
+    // interval to try more preferred servers while using another one (None: no failback)
+    pub failback: Option<Duration>,
+}
+
+impl ServerList {
+    // Ordered list of servers, the first one is primary
+    pub fn ordered<I, S>(addresses: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        ServerList {
+            servers: addresses.into_iter().map(|address| ServerEntry { address: address.into(), weight: 1 }).collect(),
+            selection: ServerSelection::Ordered,
+            failback: None,
+        }
+    }
+
+    // Weighted list of (address, weight)
+    pub fn weighted<I, S>(servers: I) -> Self
+    where
+        I: IntoIterator<Item = (S, u32)>,
+        S: Into<String>,
+    {
+        ServerList {
+            servers: servers.into_iter().map(|(address, weight)| ServerEntry { address: address.into(), weight }).collect(),
+            selection: ServerSelection::Weighted,
+            failback: None,
+        }
+    }
+
+    // FromServerInfo parses server_info of node server: "addr1,addr2" (ordered) or "addr1;3,addr2;1" (weighted)
+    pub fn from_server_info(server_info: &str) -> Result<Self, SxutilError> {
+        let mut servers = Vec::new();
+        let mut weighted = false;
+        for server in server_info.split(',').map(str::trim).filter(|server| !server.is_empty()) {
+            let (address, weight) = match server.split_once(';') {
+                Some((address, weight)) => {
+                    weighted = true;
+                    let weight = weight.trim().parse()
+                        .map_err(|_| SxutilError::InvalidAddress(server_info.to_string()))?;
+                    (address.trim(), weight)
+                },
+                None => (server, 1),
+            };
+            servers.push(ServerEntry { address: address.to_string(), weight });
+        }
+        if servers.is_empty() {
+            return Err(SxutilError::InvalidAddress(server_info.to_string()));
+        }
+        let selection = if weighted { ServerSelection::Weighted } else { ServerSelection::Ordered };
+        Ok(ServerList { servers, selection, failback: None })
+    }
+
+    pub fn with_failback(mut self, interval: Duration) -> Self {
+        self.failback = Some(interval);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.servers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.servers.is_empty()
+    }
+
+    // addresses in the order to try, failed server is tried last
+    pub fn failover_order(&self, failed: Option<&str>) -> Vec<String> {
+        let mut order: Vec<&ServerEntry> = match self.selection {
+            ServerSelection::Ordered => self.servers.iter().collect(),
+            ServerSelection::Weighted => {
+                // weighted random permutation (key = u^(1/weight), larger first)
+                let mut rng = rand::thread_rng();
+                let mut keyed: Vec<(f64, &ServerEntry)> = self.servers.iter()
+                    .map(|server| match server.weight {
+                        0 => (-1.0, server),
+                        weight => (rng.gen::<f64>().powf(1.0 / weight as f64), server),
+                    })
+                    .collect();
+                keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+                keyed.into_iter().map(|(_, server)| server).collect()
+            },
+        };
+        if let Some(pos) = failed.and_then(|failed| order.iter().position(|server| server.address == failed)) {
+            let failed = order.remove(pos);
+            order.push(failed);
+        }
+        order.into_iter().map(|server| server.address.clone()).collect()
+    }
+
+    // addresses more preferred than current (for failback)
+    pub fn preferred_over(&self, current: &str) -> Vec<String> {
+        let Some(pos) = self.servers.iter().position(|server| server.address == current) else {
+            return self.failover_order(None);
+        };
+        match self.selection {
+            ServerSelection::Ordered => self.servers[..pos].iter().map(|server| server.address.clone()).collect(),
+            ServerSelection::Weighted => {
+                let weight = self.servers[pos].weight;
+                let mut preferred: Vec<&ServerEntry> = self.servers.iter().filter(|server| server.weight > weight).collect();
+                preferred.sort_by_key(|server| std::cmp::Reverse(server.weight));
+                preferred.into_iter().map(|server| server.address.clone()).collect()
+            },
+        }
+    }
+}
+
+// connects to the first reachable server of order.
+// servers are always dialed eagerly (opts.lazy is ignored), otherwise unreachable server can't be skipped.
+pub(crate) async fn connect_servers(servers: &Arc<ServerList>, order: Vec<String>, opts: &ConnectOptions) -> Result<SXSynerexClient, SxutilError> {
+    let opts = opts.clone().with_lazy(false);
+    let mut last_err = SxutilError::InvalidAddress(String::new());
+    for address in order {
+        match dial_server(address, &opts).await {
+            Ok(mut clt) => {
+                clt.servers = Some(Arc::clone(servers));
+                return Ok(clt);
+            },
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+// dials serv_addr again, or the next server of the list if the client has one
+pub(crate) async fn reconnect_server(serv_addr: &str, servers: Option<&Arc<ServerList>>, opts: &ConnectOptions) -> Result<SXSynerexClient, SxutilError> {
+    match servers {
+        Some(servers) if servers.len() > 1 => connect_servers(servers, servers.failover_order(Some(serv_addr)), opts).await,
+        _ => {
+            let mut clt = dial_server(serv_addr.to_string(), opts).await?;
+            clt.servers = servers.cloned();
+            Ok(clt)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockSynerexServer;
+
+    fn entry(address: &str, weight: u32) -> ServerEntry {
+        ServerEntry { address: address.to_string(), weight }
+    }
+
+    #[tokio::test]
+    async fn lazy_options_still_fail_over() {
+        let server = MockSynerexServer::start().await.unwrap();
+        let unreachable = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().to_string()
+        }; // closed port
+        let opts = ConnectOptions::default().with_lazy(true).with_connect_timeout(Duration::from_secs(1));
+        let servers = ServerList::ordered([unreachable.clone(), server.address()]);
+
+        let clt = crate::grpc_connect_servers_with(servers.clone(), &opts).await.unwrap();
+        assert_eq!(clt.server_address, server.address());
+        let clt = reconnect_server(&unreachable, Some(&Arc::new(servers)), &opts).await.unwrap();
+        assert_eq!(clt.server_address, server.address());
+    }
+
+    #[test]
+    fn from_server_info_ordered() {
+        let servers = ServerList::from_server_info(" 127.0.0.1:10000, ,127.0.0.1:10001 ").unwrap();
+        assert_eq!(servers, ServerList::ordered(["127.0.0.1:10000", "127.0.0.1:10001"]));
+        assert_eq!(ServerList::from_server_info("127.0.0.1:10000").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn from_server_info_weighted() {
+        let servers = ServerList::from_server_info("a:1;3, b:2 ; 0,c:3").unwrap();
+        assert_eq!(servers.selection, ServerSelection::Weighted);
+        assert_eq!(servers.servers, vec![entry("a:1", 3), entry("b:2", 0), entry("c:3", 1)]);
+    }
+
+    #[test]
+    fn from_server_info_errors() {
+        for server_info in ["", " , ", "a:1;x", "a:1;-1"] {
+            assert!(matches!(ServerList::from_server_info(server_info), Err(SxutilError::InvalidAddress(_))), "{:?}", server_info);
+        }
+    }
+
+    #[test]
+    fn ordered_failover_moves_failed_server_last() {
+        let servers = ServerList::ordered(["a", "b", "c"]);
+        assert_eq!(servers.failover_order(None), vec!["a", "b", "c"]);
+        assert_eq!(servers.failover_order(Some("a")), vec!["b", "c", "a"]);
+        assert_eq!(servers.failover_order(Some("b")), vec!["a", "c", "b"]);
+        assert_eq!(servers.failover_order(Some("x")), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn weighted_failover_prefers_heavier_servers() {
+        let servers = ServerList::weighted([("a", 3), ("b", 1)]);
+        let a_first = (0..2000).filter(|_| servers.failover_order(None)[0] == "a").count();
+        assert!((1300..=1700).contains(&a_first), "a first {} / 2000", a_first); // expected 1500
+    }
+
+    #[test]
+    fn weight_zero_is_tried_after_others() {
+        let servers = ServerList::weighted([("standby", 0), ("a", 1), ("b", 2)]);
+        for _ in 0..100 {
+            let order = servers.failover_order(None);
+            assert_eq!(order.len(), 3);
+            assert_eq!(order[2], "standby");
+        }
+    }
+
+    #[test]
+    fn weighted_failover_moves_failed_server_last() {
+        let servers = ServerList::weighted([("a", 100), ("b", 1), ("standby", 0)]);
+        for _ in 0..100 {
+            assert_eq!(servers.failover_order(Some("a")).last().map(String::as_str), Some("a"));
+        }
+    }
+
+    #[test]
+    fn preferred_over_ordered() {
+        let servers = ServerList::ordered(["a", "b", "c"]);
+        assert!(servers.preferred_over("a").is_empty());
+        assert_eq!(servers.preferred_over("c"), vec!["a", "b"]);
+        assert_eq!(servers.preferred_over("x"), vec!["a", "b", "c"]); // unknown server: all of them
+    }
+
+    #[test]
+    fn preferred_over_weighted() {
+        let servers = ServerList::weighted([("a", 3), ("b", 1), ("c", 5), ("standby", 0)]);
+        assert_eq!(servers.preferred_over("b"), vec!["c", "a"]);
+        assert_eq!(servers.preferred_over("standby"), vec!["c", "a", "b"]);
+        assert!(servers.preferred_over("c").is_empty());
+    }
+}
@@ -0,0 +1,128 @@
+use std::io;
+use std::pin::Pin;
+
+use futures::{stream, Stream};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tonic::{Response, Status};
+
+use synerex_api::api;
+
+// serving parts shared by SynerexBroker and mock servers of testing
+
+// SubscriptionStream is a response stream of subscribe methods
+pub(crate) type SubscriptionStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+// open subscription stream (key is channel_type or mbus_id)
+struct Subscriber<T> {
+    client_id: u64,
+    key: u64,
+    tx: mpsc::Sender<Result<T, Status>>,
+}
+
+// Subscribers holds open subscription streams of one kind
+pub(crate) struct Subscribers<T> {
+    list: Vec<Subscriber<T>>,
+    buffer: usize,
+}
+
+// Delivery counts messages put to streams and messages dropped for full streams
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Delivery {
+    pub(crate) delivered: usize,
+    pub(crate) dropped: usize,
+}
+
+impl<T: Send + 'static> Subscribers<T> {
+    // buffer is the number of messages kept for each stream not read by its client
+    pub(crate) fn new(buffer: usize) -> Self {
+        Subscribers { list: Vec::new(), buffer: buffer.max(1) }
+    }
+
+    // opens stream of client, the older stream of the same client and key is ended
+    pub(crate) fn subscribe(&mut self, client_id: u64, key: u64) -> SubscriptionStream<T> {
+        self.list.retain(|sub| !sub.tx.is_closed() && (sub.client_id != client_id || sub.key != key));
+        let (tx, rx) = mpsc::channel(self.buffer);
+        self.list.push(Subscriber { client_id, key, tx });
+        Box::pin(stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|msg| (msg, rx))
+        }))
+    }
+
+    // sends msg to subscribers of key accepted by `to` (closed streams are removed)
+    pub(crate) fn send<F: Fn(u64) -> bool>(&mut self, key: u64, to: F, msg: &T) -> Delivery
+    where
+        T: Clone,
+    {
+        let mut delivery = Delivery::default();
+        self.list.retain(|sub| {
+            if sub.key != key || !to(sub.client_id) {
+                return !sub.tx.is_closed();
+            }
+            match sub.tx.try_send(Ok(msg.clone())) {
+                Ok(()) => delivery.delivered += 1,
+                Err(TrySendError::Full(_)) => delivery.dropped += 1,
+                Err(TrySendError::Closed(_)) => return false,
+            }
+            true
+        });
+        delivery
+    }
+
+    // ends streams matching `closing` (client_id, key) normally, returns the number of ended streams
+    pub(crate) fn close<F: Fn(u64, u64) -> bool>(&mut self, closing: F) -> usize {
+        self.close_with(closing, None)
+    }
+
+    // ends streams matching `closing` with status (if not full), or normally when None
+    pub(crate) fn close_with<F: Fn(u64, u64) -> bool>(&mut self, closing: F, status: Option<&Status>) -> usize {
+        let before = self.list.len();
+        self.list.retain(|sub| {
+            if sub.tx.is_closed() {
+                return false;
+            }
+            if !closing(sub.client_id, sub.key) {
+                return true;
+            }
+            if let Some(status) = status {
+                let _ = sub.tx.try_send(Err(status.clone()));
+            }
+            false
+        });
+        before - self.list.len()
+    }
+
+    // clients with open stream of key
+    pub(crate) fn clients(&mut self, key: u64) -> Vec<u64> {
+        self.list.retain(|sub| !sub.tx.is_closed());
+        self.list.iter().filter(|sub| sub.key == key).map(|sub| sub.client_id).collect()
+    }
+
+    pub(crate) fn len(&mut self) -> usize {
+        self.list.retain(|sub| !sub.tx.is_closed());
+        self.list.len()
+    }
+}
+
+pub(crate) fn ok() -> Response<api::Response> {
+    Response::new(api::Response { ok: true, err: String::new() })
+}
+
+pub(crate) fn rejected(err: impl Into<String>) -> Response<api::Response> {
+    Response::new(api::Response { ok: false, err: err.into() })
+}
+
+pub(crate) fn confirmed(mbus_id: u64) -> Response<api::ConfirmResponse> {
+    Response::new(api::ConfirmResponse { ok: true, mbus_id, wait: None, err: String::new() })
+}
+
+// connections accepted by listener (TCP_NODELAY as clients, small responses are not delayed)
+pub(crate) fn tcp_incoming(listener: TcpListener) -> impl Stream<Item = io::Result<TcpStream>> {
+    stream::unfold(listener, |listener| async move {
+        let conn = listener.accept().await.and_then(|(stream, _)| {
+            stream.set_nodelay(true)?;
+            Ok(stream)
+        });
+        Some((conn, listener))
+    })
+}
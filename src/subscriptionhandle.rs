@@ -0,0 +1,242 @@
+use std::fmt;
+use std::future::Future;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::{GiveUp, SxutilError};
+
+// SubscriptionEnd tells why a subscription finished
+#[derive(Debug)]
+pub enum SubscriptionEnd {
+    // cancelled by SubscriptionHandle
+    Cancelled,
+    // subscription can't continue
+    Failed(SxutilError),
+    // handler panicked
+    Panicked(String),
+    // reconnection given up by ReconnectPolicy
+    GaveUp(GiveUp),
+}
+
+impl fmt::Display for SubscriptionEnd {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SubscriptionEnd::Cancelled => write!(f, "subscription cancelled"),
+            SubscriptionEnd::Failed(err) => write!(f, "subscription failed: {}", err),
+            SubscriptionEnd::Panicked(msg) => write!(f, "subscription panicked: {}", msg),
+            SubscriptionEnd::GaveUp(give_up) => write!(f, "subscription {}", give_up),
+        }
+    }
+}
+
+// SubscriptionHandle controls subscription task started by simple_subscribe_* functions.
+// Dropping the handle does not stop the subscription unless cancel_on_drop(true) is set.
+#[derive(Debug)]
+pub struct SubscriptionHandle {
+    cancel: CancellationToken,
+    join: Option<JoinHandle<SubscriptionEnd>>,
+    cancel_on_drop: bool,
+}
+
+impl SubscriptionHandle {
+    pub(crate) fn spawn<F, Fut>(subscribe: F) -> SubscriptionHandle
+    where
+        F: FnOnce(CancellationToken) -> Fut,
+        Fut: Future<Output = SubscriptionEnd> + Send + 'static,
+    {
+        let cancel = CancellationToken::new();
+        let join = tokio::spawn(subscribe(cancel.clone()));
+        SubscriptionHandle { cancel, join: Some(join), cancel_on_drop: false }
+    }
+
+    pub fn cancel_on_drop(mut self, cancel_on_drop: bool) -> Self {
+        self.cancel_on_drop = cancel_on_drop;
+        self
+    }
+
+    // token cancelled with this subscription (e.g. to stop it together with other tasks)
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    // Cancel stops subscription immediately (also while waiting for message or reconnection)
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        match self.join.as_ref() {
+            Some(join) => join.is_finished(),
+            None => true,
+        }
+    }
+
+    // Join waits until subscription finishes and returns the reason
+    pub async fn join(mut self) -> SubscriptionEnd {
+        match self.join.take() {
+            Some(join) => match join.await {
+                Ok(end) => end,
+                Err(err) if err.is_panic() => SubscriptionEnd::Panicked(err.to_string()),
+                Err(_) => SubscriptionEnd::Cancelled,
+            },
+            None => SubscriptionEnd::Cancelled,
+        }
+    }
+
+    // Stop cancels subscription and waits for termination
+    pub async fn stop(self) -> SubscriptionEnd {
+        self.cancel();
+        self.join().await
+    }
+}
+
+impl Drop for SubscriptionHandle {
+    fn drop(&mut self) {
+        if self.cancel_on_drop {
+            self.cancel.cancel();
+        }
+    }
+}
+
+// runs subscription loop until cancelled
+pub(crate) async fn until_cancelled<Fut>(cancel: CancellationToken, subscription: Fut) -> SubscriptionEnd
+where
+    Fut: Future<Output = SubscriptionEnd>,
+{
+    tokio::select! {
+        _ = cancel.cancelled() => SubscriptionEnd::Cancelled,
+        end = subscription => end,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::time::Duration;
+    use std::sync::Arc;
+    use tokio::sync::{mpsc, RwLock};
+    use synerex_api::api;
+    use crate::testing::{MockMethod, MockSynerexServer};
+    use crate::{demand_handler, simple_subscribe_demand, NodeServInfo, ReconnectPolicy, SXServiceClient, SxutilConfig, SxutilNode};
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    async fn service_client(server: &MockSynerexServer, config: SxutilConfig) -> Arc<RwLock<SXServiceClient>> {
+        client_of(&SxutilNode::with_config(config), server).await
+    }
+
+    async fn client_of(node: &SxutilNode, server: &MockSynerexServer) -> Arc<RwLock<SXServiceClient>> {
+        Arc::new(RwLock::new(node.new_sx_service_client(server.connect().await.unwrap(), 1, String::new()).await))
+    }
+
+    async fn wait_for_subscribers(server: &MockSynerexServer, count: usize) {
+        tokio::time::timeout(WAIT, async {
+            while server.subscribers(MockMethod::SubscribeDemand) != count {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        }).await.unwrap();
+    }
+
+    // handler forwarding demand ids
+    fn forward_ids() -> (crate::DemandHandler, mpsc::UnboundedReceiver<u64>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let dmcb = demand_handler(move |_, dm| {
+            let tx = tx.clone();
+            async move { let _ = tx.send(dm.id); }
+        });
+        (dmcb, rx)
+    }
+
+    #[tokio::test]
+    async fn cancel_ends_subscription_waiting_for_message() {
+        let server = MockSynerexServer::start_in_memory().await;
+        let client = service_client(&server, SxutilConfig::default()).await;
+        let (dmcb, mut ids) = forward_ids();
+        let handle = simple_subscribe_demand(client, dmcb);
+        wait_for_subscribers(&server, 1).await;
+        server.publish_demand(api::Demand { id: 1, channel_type: 1, ..Default::default() });
+        assert_eq!(tokio::time::timeout(WAIT, ids.recv()).await.unwrap(), Some(1));
+
+        handle.cancel();
+        assert!(handle.is_cancelled());
+        let end = tokio::time::timeout(WAIT, handle.join()).await.unwrap();
+        assert!(matches!(end, SubscriptionEnd::Cancelled), "{}", end);
+        wait_for_subscribers(&server, 0).await;
+    }
+
+    #[tokio::test]
+    async fn drop_cancels_only_with_cancel_on_drop() {
+        let server = MockSynerexServer::start_in_memory().await;
+        let node = SxutilNode::new(); // distinct client ids
+        let (dmcb, _ids) = forward_ids();
+        let kept = simple_subscribe_demand(client_of(&node, &server).await, dmcb);
+        let kept_token = kept.cancellation_token();
+        let (dmcb, _ids) = forward_ids();
+        let dropped = simple_subscribe_demand(client_of(&node, &server).await, dmcb).cancel_on_drop(true);
+        let dropped_token = dropped.cancellation_token();
+        wait_for_subscribers(&server, 2).await;
+
+        drop(kept);
+        drop(dropped);
+        assert!(dropped_token.is_cancelled());
+        assert!(!kept_token.is_cancelled());
+        wait_for_subscribers(&server, 1).await; // detached one keeps running
+        kept_token.cancel();
+        wait_for_subscribers(&server, 0).await;
+    }
+
+    #[tokio::test]
+    async fn join_reports_why_subscription_ended() {
+        let server = MockSynerexServer::start().await.unwrap();
+        let config = SxutilConfig::default()
+            .with_reconnect_wait(Duration::from_millis(10))
+            .with_reconnect_policy(ReconnectPolicy::fixed().with_max_attempts(2));
+        let client = service_client(&server, config).await;
+        let (dmcb, _ids) = forward_ids();
+        let handle = simple_subscribe_demand(Arc::clone(&client), dmcb);
+        wait_for_subscribers(&server, 1).await;
+
+        // closed stream is subscribed again, the subscription goes on
+        server.drop_streams(tonic::Status::unavailable("dropped"));
+        tokio::time::timeout(WAIT, async {
+            while server.requests_of(MockMethod::SubscribeDemand).len() < 2 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        }).await.unwrap();
+        assert!(!handle.is_finished());
+
+        // server is gone: gives up after max_attempts
+        server.shutdown().await;
+        let end = tokio::time::timeout(WAIT, handle.join()).await.unwrap();
+        match end {
+            SubscriptionEnd::GaveUp(give_up) => {
+                assert_eq!((give_up.subscription, give_up.attempts), ("Demand", 2));
+                assert_eq!(give_up.server_address, server.address());
+            },
+            end => panic!("unexpected end {}", end),
+        }
+
+        // client without server can't subscribe
+        let clt = NodeServInfo::new().new_sx_service_client(MockSynerexServer::start_in_memory().await.connect().await.unwrap(), 1, String::new());
+        clt.sxclient.store(None);
+        let (dmcb, _ids) = forward_ids();
+        let end = simple_subscribe_demand(Arc::new(RwLock::new(clt)), dmcb).join().await;
+        assert!(matches!(end, SubscriptionEnd::Failed(SxutilError::NotConnected)), "{}", end);
+    }
+
+    #[tokio::test]
+    async fn join_reports_panicked_handler() {
+        let server = MockSynerexServer::start_in_memory().await;
+        let client = service_client(&server, SxutilConfig::default()).await;
+        let dmcb = demand_handler(|_, _| async { panic!("handler panics") });
+        let handle = simple_subscribe_demand(client, dmcb);
+        wait_for_subscribers(&server, 1).await;
+        server.publish_demand(api::Demand { id: 1, channel_type: 1, ..Default::default() });
+        let end = tokio::time::timeout(WAIT, handle.join()).await.unwrap();
+        assert!(matches!(end, SubscriptionEnd::Panicked(ref msg) if msg.contains("panic")), "{}", end);
+    }
+}
@@ -1,23 +1,31 @@
 use chrono::{Local, Datelike, Timelike};
 use prost_types::Timestamp;
-use tokio::sync::RwLock;
+use tokio::sync::{watch, Mutex, RwLock};
 use tokio::time::timeout;
-use std::{time, sync::Arc, error::Error}; //, future::Future};
+use core::time::Duration;
+use std::{sync::{Arc, Weak}, pin::Pin, ops::Deref, future::{self, Future}};
+use futures::{future::BoxFuture, stream::{self, Stream}};
 
 use synerex_api::api;
 
-use crate::{IDType, SXSynerexClient, NodeServInfo, SupplyOpts, generate_int_id, MSG_TIME_OUT, DemandOpts, SxutilError, SupplyHandler, DemandHandler};
+use crate::{IDType, SxChannel, SXSynerexClient, ConnectionSlot, NodeServInfo, SupplyOpts, generate_int_id, DemandOpts, SxutilError, SxutilConfig, SupplyHandler, DemandHandler, MbusHandler, SenderId, ReceiveMetrics};
+use crate::receivebuffer::ReceiveBuffer;
+use crate::serverlist::{connect_servers, reconnect_server};
 
 
 // SXServiceClient Wrappter Structure for synerex client
-#[derive(Debug)]
+// (clones share connection, mbus ids and metrics)
+#[derive(Debug, Clone)]
 pub struct SXServiceClient {
     pub client_id: IDType,
     pub channel_type: u32,
-    pub sxclient: RwLock<Option<SXSynerexClient>>,
+    pub sxclient: Arc<ConnectionSlot>,
     pub arg_json: String,
-    pub mbus_ids: RwLock<Vec<IDType>>,
+    pub mbus_ids: Arc<RwLock<Vec<IDType>>>,
     pub ni: Option<Arc<RwLock<NodeServInfo>>>,
+    pub config: SxutilConfig,
+    // counters of receive buffer (config.receive_buffer)
+    pub receive_metrics: Arc<ReceiveMetrics>,
 }
 
 
@@ -26,6 +34,60 @@ impl SXServiceClient {
         api::Channel { client_id: self.client_id, channel_type: self.channel_type, arg_json: self.arg_json.clone() }
     }
 
+    // returns true while the node is locked for server change
+    async fn is_locked(&self) -> bool {
+        match self.ni.as_ref() {
+            Some(ni) => ni.read().await.node_state.locked,
+            None => false,
+        }
+    }
+
+    // ServerAddress returns address of connected synerex server
+    pub async fn server_address(&self) -> Option<String> {
+        self.sxclient.server_address()
+    }
+
+    // returns current gRPC client (a clone on the shared channel, no lock is taken)
+    async fn grpc_client(&self) -> Result<api::synerex_client::SynerexClient<SxChannel>, SxutilError> {
+        match self.sxclient.load() {
+            Some(sxclient) => Ok(sxclient.client.clone()),
+            None => {
+                error!("sxutil: SXClient is None!");
+                Err(SxutilError::NotConnected)
+            },
+        }
+    }
+
+    // receiver notified on SERVER_CHANGE of the node this client belongs to
+    async fn watch_server_change(&self) -> Option<watch::Receiver<String>> {
+        match self.ni.as_ref() {
+            Some(ni) => Some(ni.read().await.server_change.subscribe()),
+            None => None,
+        }
+    }
+
+    // generates ID by the node this client belongs to
+    async fn generate_int_id(&self) -> u64 {
+        match self.ni.as_ref() {
+            Some(ni) => ni.read().await.generate_int_id(),
+            None => generate_int_id().await,
+        }
+    }
+
+    // runs unary RPC within msg_timeout
+    async fn with_deadline<T, F>(&self, msg_timeout: Duration, fut: F) -> Result<T, SxutilError>
+    where
+        F: Future<Output = Result<T, SxutilError>>,
+    {
+        match timeout(msg_timeout, fut).await {
+            Ok(result) => result,
+            Err(err) => {
+                error!("sxutil: Timeout occurred. ({:?})", msg_timeout);
+                Err(SxutilError::from(err))
+            },
+        }
+    }
+
     // IsSupplyTarget is a helper function to check target
     pub fn is_supply_target(&self, sp: &api::Supply, idlist: Vec<u64>) -> bool {
         let spid = sp.target_id;
@@ -39,8 +101,13 @@ impl SXServiceClient {
     }
 
     // ProposeSupply send proposal Supply message to server
-    pub async fn propose_supply(&self, spo: &SupplyOpts) -> u64 {
-        let pid = generate_int_id().await;
+    pub async fn propose_supply(&self, spo: &SupplyOpts) -> Result<u64, SxutilError> {
+        self.propose_supply_with_timeout(spo, self.config.msg_timeout).await
+    }
+
+    // ProposeSupplyWithTimeout is ProposeSupply with per-call message timeout
+    pub async fn propose_supply_with_timeout(&self, spo: &SupplyOpts, msg_timeout: Duration) -> Result<u64, SxutilError> {
+        let pid = self.generate_int_id().await;
         let dt = Local::now();
         let ts = Timestamp::date_time_nanos(dt.year() as i64, dt.month() as u8, dt.day() as u8, dt.hour() as u8, dt.minute() as u8, dt.second() as u8, dt.nanosecond() as u32).unwrap();
         let sp = api::Supply {
@@ -55,41 +122,36 @@ impl SXServiceClient {
             cdata: Some(spo.cdata.clone()),
         };
 
-        let async_func = || async {
-            if self.sxclient.read().await.is_some() {
-                let pid = match self.sxclient.read().await.as_ref().unwrap().client.write().await.propose_supply(sp.clone()).await {
+        self.with_deadline(msg_timeout, async {
+            if self.sxclient.is_connected() {
+                match self.grpc_client().await?.propose_supply(deadline_request(sp.clone(), msg_timeout)).await {
                     Ok(resp) => {
                         debug!("ProposeSupply Response: {:?} PID: {}", resp, pid);
-                        pid
                     },
                     Err(err) => {
                         error!("{:?}.ProposeSupply err {}, [{:?}]", self, err, sp);
-                       0
+                        return Err(SxutilError::from(err));
                     },
                 };
-                self.ni.as_ref().unwrap().write().await.node_state.propose_supply(sp);
-                pid
+                if let Some(ni) = self.ni.as_ref() {
+                    ni.write().await.node_state.propose_supply(sp);
+                }
+                Ok(pid)
             } else {
                 error!("SXClient is None!");
-                0
+                Err(SxutilError::NotConnected)
             }
-        };
-
-        let timeout_duration = time::Duration::from_secs(MSG_TIME_OUT);
-        let result = timeout(timeout_duration, async_func()).await;
-    
-        match result {
-            Ok(value) => value,
-            Err(_) =>  {
-                error!("Timeout occurred.");
-                0
-            },
-        }
+        }).await
     }
     
     // ProposeDemand send proposal Demand message to server
-    pub async fn propose_demand(&self, dmo: DemandOpts) -> u64 {
-        let pid = generate_int_id().await;
+    pub async fn propose_demand(&self, dmo: DemandOpts) -> Result<u64, SxutilError> {
+        self.propose_demand_with_timeout(dmo, self.config.msg_timeout).await
+    }
+
+    // ProposeDemandWithTimeout is ProposeDemand with per-call message timeout
+    pub async fn propose_demand_with_timeout(&self, dmo: DemandOpts, msg_timeout: Duration) -> Result<u64, SxutilError> {
+        let pid = self.generate_int_id().await;
         let dt = Local::now();
         let ts = Timestamp::date_time_nanos(dt.year() as i64, dt.month() as u8, dt.day() as u8, dt.hour() as u8, dt.minute() as u8, dt.second() as u8, dt.nanosecond() as u32).unwrap();
         let dm = api::Demand {
@@ -108,41 +170,36 @@ impl SXServiceClient {
         //Todo: We need to make if for each channel type
         //	}
 
-        let async_func = || async {
-            if self.sxclient.read().await.is_some() {
-                let pid = match self.sxclient.read().await.as_ref().unwrap().client.write().await.propose_demand(dm.clone()).await {
+        self.with_deadline(msg_timeout, async {
+            if self.sxclient.is_connected() {
+                match self.grpc_client().await?.propose_demand(deadline_request(dm.clone(), msg_timeout)).await {
                     Ok(resp) => {
                         debug!("ProposeDemand Response: {:?} PID: {}", resp, pid);
-                        pid
                     },
                     Err(err) => {
                         error!("{:?}.ProposeDemand err {}, [{:?}]", self, err, dm);
-                        0
+                        return Err(SxutilError::from(err));
                     },
                 };
-                self.ni.as_ref().unwrap().write().await.node_state.propose_demand(dm);
-                pid
+                if let Some(ni) = self.ni.as_ref() {
+                    ni.write().await.node_state.propose_demand(dm);
+                }
+                Ok(pid)
             } else {
                 error!("SXClient is None!");
-                0
+                Err(SxutilError::NotConnected)
             }
-        };
-
-        let timeout_duration = time::Duration::from_secs(MSG_TIME_OUT);
-        let result = timeout(timeout_duration, async_func()).await;
-    
-        match result {
-            Ok(value) => value,
-            Err(_) =>  {
-                error!("Timeout occurred.");
-                0
-            },
-        }
+        }).await
     }
 
     // SelectSupply send select message to server
-    pub async fn select_supply(&self, sp: api::Supply) -> Option<u64> {
-        let pid = generate_int_id().await;
+    pub async fn select_supply(&self, sp: api::Supply) -> Result<u64, SxutilError> {
+        self.select_supply_with_timeout(sp, self.config.msg_timeout).await
+    }
+
+    // SelectSupplyWithTimeout is SelectSupply with per-call message timeout
+    pub async fn select_supply_with_timeout(&self, sp: api::Supply, msg_timeout: Duration) -> Result<u64, SxutilError> {
+        let pid = self.generate_int_id().await;
         let tgt = api::Target {
             id: pid,
             sender_id: self.client_id,
@@ -152,32 +209,37 @@ impl SXServiceClient {
             mbus_id: u64::MAX,
         };
 
-        // ctx, cancel := context.WithTimeout(context.Background(), MSG_TIME_OUT*time.Second)
-        // defer cancel()
+        self.with_deadline(msg_timeout, async {
+            if !self.sxclient.is_connected() {
+                error!("sxutil: SXClient is None!");
+                return Err(SxutilError::NotConnected);
+            }
 
-        if self.sxclient.read().await.is_some() {
-            return match self.sxclient.read().await.as_ref().unwrap().client.write().await.select_supply(tgt.clone()).await {
+            match self.grpc_client().await?.select_supply(deadline_request(tgt.clone(), msg_timeout)).await {
                 Ok(resp) => {
                     debug!("SelectSupply Response: {:?} PID: {}", resp, pid);
                     self.mbus_ids.write().await.push(resp.get_ref().mbus_id);
                     //TODO:  We need to implement Mbus systems
                     //		clt.SubscribeMbus()
                     //	}
-                    Some(resp.get_ref().mbus_id)
+                    Ok(resp.get_ref().mbus_id)
                 },
                 Err(err) => {
                     error!("{:?}.SelectSupply err {}, [{:?}]", self, err, tgt);
-                    None
+                    Err(SxutilError::from(err))
                 },
             }
-        } else {
-            None
-        } 
+        }).await
     }
 
     // SelectDemand send select message to server
-    pub async fn select_demand(&self, dm: api::Demand) -> Option<u64> {
-        let pid = generate_int_id().await;
+    pub async fn select_demand(&self, dm: api::Demand) -> Result<u64, SxutilError> {
+        self.select_demand_with_timeout(dm, self.config.msg_timeout).await
+    }
+
+    // SelectDemandWithTimeout is SelectDemand with per-call message timeout
+    pub async fn select_demand_with_timeout(&self, dm: api::Demand, msg_timeout: Duration) -> Result<u64, SxutilError> {
+        let pid = self.generate_int_id().await;
         let tgt = api::Target {
             id: pid,
             sender_id: self.client_id,
@@ -187,117 +249,131 @@ impl SXServiceClient {
             mbus_id: u64::MAX,
         };
 
-        // ctx, cancel := context.WithTimeout(context.Background(), MSG_TIME_OUT*time.Second)
-        // defer cancel()
+        self.with_deadline(msg_timeout, async {
+            if !self.sxclient.is_connected() {
+                error!("sxutil: SXClient is None!");
+                return Err(SxutilError::NotConnected);
+            }
 
-        if self.sxclient.read().await.is_some() {
-            return match self.sxclient.read().await.as_ref().unwrap().client.write().await.select_demand(tgt.clone()).await {
+            match self.grpc_client().await?.select_demand(deadline_request(tgt.clone(), msg_timeout)).await {
                 Ok(resp) => {
                     debug!("SelectDemand Response: {:?} PID: {}", resp, pid);
                     self.mbus_ids.write().await.push(resp.get_ref().mbus_id);
                     //TODO:  We need to implement Mbus systems
                     //		clt.SubscribeMbus()
                     //	}
-                    Some(resp.get_ref().mbus_id)
+                    Ok(resp.get_ref().mbus_id)
                 },
                 Err(err) => {
                     error!("{:?}.SelectDemand err {}, [{:?}]", self, err, tgt);
-                    None
+                    Err(SxutilError::from(err))
                 },
             }
-        } else {
-            None
-        } 
+        }).await
     }
         
     
     // SubscribeSupply  Wrapper function for SXServiceClient
-    pub async fn subscribe_supply(&self, spcb: &SupplyHandler) -> bool {
-        let ch = self.get_channel();
-        if self.sxclient.read().await.is_none() {
-            error!("sxutil: SXClient is None!");
-            return false;
-        }
-        
-        let mut smc = match self.sxclient.read().await.as_ref().unwrap().client.write().await.subscribe_supply(ch).await {
-            Ok(smc) => smc,
-            Err(err) => {
-                error!("sxutil: SXServiceClient.SubscribeSupply Error {}", err);
-                return false;
-            },
-        };
-
-        debug!("Start SubscribeSupply: {:?}", smc);
-
-        loop {
-            let sp: api::Supply = match smc.get_mut().message().await {  // receive Supply
-                Ok(msg) => msg.unwrap(),
-                Err(err) => {
-                    // if err == io.EOF {
-                    //     log.Print("sxutil: End Supply subscribe OK")
-                    // }
-                    error!("sxutil: SXServiceClient SubscribeSupply error [{}]", err);
-                    break;
-                },
-            };
+    pub async fn subscribe_supply(&self, spcb: &SupplyHandler) -> Result<(), SxutilError> {
+        let server_change = self.watch_server_change().await;
+        let smc = self.open_supply().await?;
 
+        self.receive_messages("Supply", smc, server_change, |sp| async move {
             debug!("Receive SubscribeSupply: {:?}", sp);
 
-            if !self.ni.as_ref().unwrap().write().await.node_state.locked {
+            if !self.is_locked().await {
                 spcb(self, sp).await;
             } else {
                 error!("sxutil: Provider is locked!"); // for movement
             }
-        }
-        
-        true
+        }).await
     }
 
 
-    // SubscribeDemand  Wrapper function for SXServiceClient
-    pub async fn subscribe_demand(&self, dmcb: &DemandHandler) -> bool {
+    // opens supply stream of this channel
+    async fn open_supply(&self) -> Result<tonic::Streaming<api::Supply>, SxutilError> {
         let ch = self.get_channel();
-        if self.sxclient.read().await.is_none() {
-            error!("sxutil: SXClient is None!");
-            return false;
-        }
+        let mut client = self.grpc_client().await?;
 
-        let mut dmc = match self.sxclient.read().await.as_ref().unwrap().client.write().await.subscribe_demand(ch).await {
-            Ok(dmc) => dmc,
+        let smc = match client.subscribe_supply(ch).await {
+            Ok(smc) => smc.into_inner(),
             Err(err) => {
-                error!("sxutil: clt.SubscribeDemand Error [{}] {:?}", err, self);
-                return false; // sender should handle error...
+                error!("sxutil: SXServiceClient.SubscribeSupply Error {}", err);
+                return Err(SxutilError::from(err));
             },
         };
+        debug!("Start SubscribeSupply: {:?}", smc);
+        Ok(smc)
+    }
 
-        debug!("Start SubscribeDemand: {:?}", dmc);
+    // SupplyStream returns stream of supplies, which subscribes again on reconnect / server change.
+    // Errors are yielded as items and the stream continues after reconnection.
+    pub fn supply_stream(&self) -> impl Stream<Item = Result<api::Supply, SxutilError>> + '_ {
+        resubscribe_stream(self, Self::open_supply, false)
+    }
 
-        loop {
-            let dm: api::Demand = match dmc.get_mut().message().await {  // receive Demand
-                Ok(msg) => msg.unwrap(),
-                Err(err) => {
-                    // if err == io.EOF {
-                    //     log.Print("sxutil: End Demand subscribe OK")
-                    // }
-                    error!("sxutil: SXServiceClient SubscribeDemand error [{}]", err);
-                    break;
-                },
-            };
+    // IntoSupplyStream is supply_stream holding the client by Arc (the stream is 'static, so it can be spawned)
+    pub fn into_supply_stream(self: Arc<Self>) -> impl Stream<Item = Result<api::Supply, SxutilError>> + Send + 'static {
+        resubscribe_stream(self, |clt: Arc<Self>| async move { clt.open_supply().await }, false)
+    }
 
+    // SubscribeDemand  Wrapper function for SXServiceClient
+    pub async fn subscribe_demand(&self, dmcb: &DemandHandler) -> Result<(), SxutilError> {
+        let server_change = self.watch_server_change().await;
+        let dmc = self.open_demand().await?;
+
+        self.receive_messages("Demand", dmc, server_change, |dm| async move {
             debug!("Receive SubscribeDemand: {:?}", dm);
 
-            if !self.ni.as_ref().unwrap().write().await.node_state.locked {
+            if !self.is_locked().await {
                 dmcb(self, dm).await;
             } else {
                 error!("sxutil: Provider is locked!");
             }
-        }
-        
-        true
+        }).await
     }
     
+    // opens demand stream of this channel
+    async fn open_demand(&self) -> Result<tonic::Streaming<api::Demand>, SxutilError> {
+        let ch = self.get_channel();
+        let mut client = self.grpc_client().await?;
+
+        let dmc = match client.subscribe_demand(ch).await {
+            Ok(dmc) => dmc.into_inner(),
+            Err(err) => {
+                error!("sxutil: clt.SubscribeDemand Error [{}] {:?}", err, self);
+                return Err(SxutilError::from(err)); // sender should handle error...
+            },
+        };
+        debug!("Start SubscribeDemand: {:?}", dmc);
+        Ok(dmc)
+    }
+
+    // DemandStream returns stream of demands, which subscribes again on reconnect / server change.
+    // Errors are yielded as items and the stream continues after reconnection.
+    pub fn demand_stream(&self) -> impl Stream<Item = Result<api::Demand, SxutilError>> + '_ {
+        resubscribe_stream(self, Self::open_demand, false)
+    }
+
+    // IntoDemandStream is demand_stream holding the client by Arc
+    pub fn into_demand_stream(self: Arc<Self>) -> impl Stream<Item = Result<api::Demand, SxutilError>> + Send + 'static {
+        resubscribe_stream(self, |clt: Arc<Self>| async move { clt.open_demand().await }, false)
+    }
+
     // SubscribeMbus  Wrapper function for SXServiceClient
-    pub async fn subscribe_mbus(&self, mbus_id: u64, mbcb: fn(&SXServiceClient, api::MbusMsg)) -> bool {
+    pub async fn subscribe_mbus(&self, mbus_id: u64, mbcb: &MbusHandler) -> Result<(), SxutilError> {
+        let server_change = self.watch_server_change().await;
+        let smc = self.open_mbus(mbus_id).await?;
+
+        self.receive_messages("Mbus", smc, server_change, |mes| async move {
+            debug!("Receive Mbus Message {:?}", mes);
+            // call Callback!
+            mbcb(self, mes).await;
+        }).await
+    }
+    
+    // opens message stream of given mbus
+    async fn open_mbus(&self, mbus_id: u64) -> Result<tonic::Streaming<api::MbusMsg>, SxutilError> {
 
         //TODO: we need to check there is mbus in the clt.MbusIDs
 
@@ -307,107 +383,220 @@ impl SXServiceClient {
             arg_json: String::from(""),
         };
 
-        if self.sxclient.read().await.is_none() {
-            error!("sxutil: SXClient is None!");
-            return false;
-        }
+        let mut client = self.grpc_client().await?;
 
         debug!("SubscribeMbus Starting... mbus_id:{}, self.id:{}", mbus_id, self.client_id);
 
-        let mut smc = match self.sxclient.read().await.as_ref().unwrap().client.write().await.subscribe_mbus(mb).await {
-            Ok(smc) => smc,
+        let smc = match client.subscribe_mbus(mb).await {
+            Ok(smc) => smc.into_inner(),
             Err(err) => {
                 error!("sxutil: Synerex_SubscribeMbusClient Error [{}] {:?}", err, self);
-                return false; // sender should handle error...
+                return Err(SxutilError::from(err)); // sender should handle error...
             },
         };
 
         debug!("SubscribeMbus Started mbus_id:{}, self.id:{}", mbus_id, self.client_id);
+        Ok(smc)
+    }
 
-        loop {
-            let mes: api::MbusMsg = match smc.get_mut().message().await {  // receive Demand
-                Ok(msg) => msg.unwrap(),
-                Err(err) => {
-                    // if err == io.EOF {
-                    //     log.Print("sxutil: End Demand subscribe OK")
-                    // }
-                    error!("sxutil: SXServiceClient SubscribeMbus error [{}] {:?}", err, self);
-                    break;
-                },
+    // MbusStream returns stream of mbus messages, which subscribes again on reconnect / server change.
+    // The stream ends when the mbus is closed.
+    pub fn mbus_stream(&self, mbus_id: u64) -> impl Stream<Item = Result<api::MbusMsg, SxutilError>> + '_ {
+        resubscribe_stream(self, move |clt: &Self| clt.open_mbus(mbus_id), true)
+    }
+
+    // IntoMbusStream is mbus_stream holding the client by Arc
+    pub fn into_mbus_stream(self: Arc<Self>, mbus_id: u64) -> impl Stream<Item = Result<api::MbusMsg, SxutilError>> + Send + 'static {
+        resubscribe_stream(self, move |clt: Arc<Self>| async move { clt.open_mbus(mbus_id).await }, true)
+    }
+
+    // receives messages of subscription and passes them to handle
+    // (through bounded receive buffer if config.receive_buffer is set)
+    async fn receive_messages<M, F, Fut>(&self, name: &str, mut subscription: tonic::Streaming<M>, mut server_change: Option<watch::Receiver<String>>, handle: F) -> Result<(), SxutilError>
+    where
+        M: prost::Message + Default + SenderId,
+        F: Fn(M) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let mut failback = Box::pin(self.failback());
+        let Some(opts) = self.config.receive_buffer else {
+            while let Some(msg) = next_message(name, &mut subscription, &mut server_change, failback.as_mut()).await? {
+                handle(msg).await;
+            }
+            return Ok(());
+        };
+
+        let buffer = ReceiveBuffer::new(opts, Arc::clone(&self.receive_metrics));
+        let receiver = async {
+            let result = loop {
+                match next_message(name, &mut subscription, &mut server_change, failback.as_mut()).await {
+                    Ok(Some(msg)) => buffer.push(msg).await,
+                    Ok(None) => break Ok(()),
+                    Err(err) => break Err(err),
+                }
             };
+            buffer.close(); // handler takes remaining messages
+            result
+        };
+        let handler = async {
+            while let Some(msg) = buffer.pop().await {
+                handle(msg).await;
+            }
+        };
+        tokio::join!(receiver, handler).0
+    }
 
-            debug!("Receive Mbus Message {:?}", mes);
-            // call Callback!
-            mbcb(self, mes);
+    // dials server again after reconnect_wait
+    async fn reconnect(&self, serv_addr: &str) {
+        if let Err(err) = self.redial(serv_addr, self.config.reconnect_wait).await {
+            error!("sxutil: Can't re-connect server.. {}", err);
         }
+    }
 
-        true
+    // Redial replaces current connection by new one to serv_addr (or next server of ServerList) after wait.
+    // Concurrent callers on the same connection share one redial; the old connection is kept
+    // until the new one is installed to every SXServiceClient sharing it.
+    pub(crate) async fn redial(&self, serv_addr: &str, wait: Duration) -> Result<(), SxutilError> {
+        let (failed, servers, opts) = match self.sxclient.load() {
+            Some(clt) => (Arc::clone(&clt.redial), clt.servers.clone(), clt.connect.clone()),
+            None => (Arc::new(Mutex::new(())), None, Arc::new(self.config.connect.clone())),
+        };
+        let _redialing = failed.lock().await;
+        if !self.sxclient.connected_by(&failed) {
+            debug!("sxutil: Use reconnected client [{:?}]", self.server_address().await);
+            return Ok(()); // redialed by other caller
+        }
+        tokio::time::sleep(wait).await;  // wait 5 seconds (by default) to reconnect
+        let clt = reconnect_server(serv_addr, servers.as_ref(), &opts).await?;
+        info!("sxutil: Reconnect server [{}]", clt.server_address);
+        self.install(&failed, clt).await;
+        Ok(())
     }
-    
+
+    // replaces failed connection by clt in this client and the clients of the node sharing it
+    async fn install(&self, failed: &Arc<Mutex<()>>, clt: SXSynerexClient) {
+        let sx_clients: Vec<Arc<ConnectionSlot>> = match self.ni.as_ref() {
+            Some(ni) => ni.read().await.sx_clients.iter().filter_map(Weak::upgrade).collect(),
+            None => Vec::new(),
+        };
+        for sxclient in sx_clients.iter().filter(|sxclient| !Arc::ptr_eq(sxclient, &self.sxclient)) {
+            sxclient.replace_if(failed, &clt);
+        }
+        self.sxclient.replace_if(failed, &clt);
+    }
+
+    // returns after switching to more preferred server of ServerList (never returns without failback)
+    async fn failback(&self) {
+        let (servers, current, conn, opts) = match self.sxclient.load() {
+            Some(clt) => (clt.servers.clone(), clt.server_address.clone(), Arc::clone(&clt.redial), clt.connect.clone()),
+            None => return future::pending().await,
+        };
+        let Some((servers, interval)) = servers.and_then(|servers| servers.failback.map(|interval| (servers, interval))) else {
+            return future::pending().await;
+        };
+        let preferred = servers.preferred_over(&current);
+        if preferred.is_empty() { // already on the most preferred server
+            return future::pending().await;
+        }
+        loop {
+            tokio::time::sleep(interval).await;
+            if self.server_address().await.as_deref() != Some(current.as_str()) {
+                return; // moved by other subscription of this client
+            }
+            if let Ok(clt) = connect_servers(&servers, preferred.clone(), &opts).await {
+                info!("sxutil: Fail back to server [{}] from [{}]", clt.server_address, current);
+                self.install(&conn, clt).await;
+                return;
+            }
+        }
+    }
+
+
     // v0.4.1 name change
-    pub async fn send_mbus_msg(&self, mbus_id: u64, mut msg: api::MbusMsg) -> Option<u64> { // return from mbus_msgID(sxutil v0.5.3)
-        if self.mbus_ids.read().await.len() == 0 {
+    pub async fn send_mbus_msg(&self, mbus_id: u64, msg: api::MbusMsg) -> Result<u64, SxutilError> { // return from mbus_msgID(sxutil v0.5.3)
+        self.send_mbus_msg_with_timeout(mbus_id, msg, self.config.msg_timeout).await
+    }
+
+    // SendMbusMsgWithTimeout is SendMbusMsg with per-call message timeout
+    pub async fn send_mbus_msg_with_timeout(&self, mbus_id: u64, mut msg: api::MbusMsg, msg_timeout: Duration) -> Result<u64, SxutilError> {
+        if self.mbus_ids.read().await.is_empty() {
             error!("sxutil: No Mbus opened!");
-            return None;
+            return Err(SxutilError::MbusNotOpen);
         }
-        msg.msg_id = generate_int_id().await;
+        msg.msg_id = self.generate_int_id().await;
         msg.sender_id = self.client_id;
         msg.mbus_id = mbus_id; // uint64(clt.MbusID) // now we can use multiple mbus from v0.6.0
 
-        if self.sxclient.read().await.is_none() {
-            error!("sxutil: SXClient is None!");
-            return None;
-        }
+        self.with_deadline(msg_timeout, async {
+            if !self.sxclient.is_connected() {
+                error!("sxutil: SXClient is None!");
+                return Err(SxutilError::NotConnected);
+            }
 
-        //TODO: need to check response
-        let resp = match self.sxclient.read().await.as_ref().unwrap().client.write().await.send_mbus_msg(msg).await {
-            Ok(resp) => resp,
-            Err(err) => {
-                error!("sxutil: Error sending Mbus msg: {}", err);
-                return None;
-            },
-        };
-        if !resp.get_ref().ok {
-            error!("sxutil: Error sending Mbus msg: {}", resp.get_ref().err);
-            return None;
-        }
-    
-        Some(mbus_id)
+            //TODO: need to check response
+            let resp = match self.grpc_client().await?.send_mbus_msg(deadline_request(msg, msg_timeout)).await {
+                Ok(resp) => resp,
+                Err(err) => {
+                    error!("sxutil: Error sending Mbus msg: {}", err);
+                    return Err(SxutilError::from(err));
+                },
+            };
+            if !resp.get_ref().ok {
+                error!("sxutil: Error sending Mbus msg: {}", resp.get_ref().err);
+                return Err(SxutilError::ServerRejected(resp.into_inner().err));
+            }
+
+            Ok(mbus_id)
+        }).await
     }
 
     // from synerex_api v0.4.0
-    pub async fn create_mbus(&self, opt: api::MbusOpt) -> Option<api::Mbus> {
-        if self.sxclient.read().await.is_none() {
-            error!("sxutil: SXClient is None!");
-            return None;
-        }
+    pub async fn create_mbus(&self, opt: api::MbusOpt) -> Result<api::Mbus, SxutilError> {
+        self.create_mbus_with_timeout(opt, self.config.msg_timeout).await
+    }
 
-        let mut mbus = match self.sxclient.read().await.as_ref().unwrap().client.write().await.create_mbus(opt).await {
-            Ok(mbus) => mbus,
-            Err(err) => {
-                error!("sxutil: Error creating Mbus: {}", err);
-                return None;
-            },
-        };
-        mbus.get_mut().client_id = self.client_id;
-        Some(mbus.into_inner())
+    // CreateMbusWithTimeout is CreateMbus with per-call message timeout
+    pub async fn create_mbus_with_timeout(&self, opt: api::MbusOpt, msg_timeout: Duration) -> Result<api::Mbus, SxutilError> {
+        self.with_deadline(msg_timeout, async {
+            if !self.sxclient.is_connected() {
+                error!("sxutil: SXClient is None!");
+                return Err(SxutilError::NotConnected);
+            }
+
+            let mut mbus = match self.grpc_client().await?.create_mbus(deadline_request(opt, msg_timeout)).await {
+                Ok(mbus) => mbus,
+                Err(err) => {
+                    error!("sxutil: Error creating Mbus: {}", err);
+                    return Err(SxutilError::from(err));
+                },
+            };
+            mbus.get_mut().client_id = self.client_id;
+            Ok(mbus.into_inner())
+        }).await
     }
     
     // from synerex_api v0.4.0
-    pub async fn get_mbus_status(&self, mb: api::Mbus) -> Option<api::MbusState> {
-        if self.sxclient.read().await.is_none() {
-            error!("sxutil: SXClient is None!");
-            return None;
-        }
+    pub async fn get_mbus_status(&self, mb: api::Mbus) -> Result<api::MbusState, SxutilError> {
+        self.get_mbus_status_with_timeout(mb, self.config.msg_timeout).await
+    }
 
-        let mbs = match self.sxclient.read().await.as_ref().unwrap().client.write().await.get_mbus_state(mb).await {
-            Ok(mbs) => mbs,
-            Err(err) => {
-                error!("sxutil: Error getting MbusState: {}", err);
-                return None;
-            },
-        };
-        Some(mbs.into_inner())
+    // GetMbusStatusWithTimeout is GetMbusStatus with per-call message timeout
+    pub async fn get_mbus_status_with_timeout(&self, mb: api::Mbus, msg_timeout: Duration) -> Result<api::MbusState, SxutilError> {
+        self.with_deadline(msg_timeout, async {
+            if !self.sxclient.is_connected() {
+                error!("sxutil: SXClient is None!");
+                return Err(SxutilError::NotConnected);
+            }
+
+            let mbs = match self.grpc_client().await?.get_mbus_state(deadline_request(mb, msg_timeout)).await {
+                Ok(mbs) => mbs,
+                Err(err) => {
+                    error!("sxutil: Error getting MbusState: {}", err);
+                    return Err(SxutilError::from(err));
+                },
+            };
+            Ok(mbs.into_inner())
+        }).await
     }
     
     pub async fn mbus_index(&self, id: u64) -> isize {
@@ -425,29 +614,37 @@ impl SXServiceClient {
         self.mbus_ids.write().await.remove(pos);
     }
 
-    pub async fn close_mbus(&self, mbus_id: u64) -> bool {
-        if self.mbus_ids.read().await.len() == 0 {
+    pub async fn close_mbus(&self, mbus_id: u64) -> Result<(), SxutilError> {
+        self.close_mbus_with_timeout(mbus_id, self.config.msg_timeout).await
+    }
+
+    // CloseMbusWithTimeout is CloseMbus with per-call message timeout
+    pub async fn close_mbus_with_timeout(&self, mbus_id: u64, msg_timeout: Duration) -> Result<(), SxutilError> {
+        if self.mbus_ids.read().await.is_empty() {
             error!("sxutil: No Mbus opened!");
-            return false;
+            return Err(SxutilError::MbusNotOpen);
         }
         let mbus = api::Mbus{
             client_id: self.client_id,
             mbus_id,
             arg_json: String::from(""),
         };
-        if self.sxclient.read().await.is_none() {
-            error!("sxutil: SXClient is None!");
-            return false;
-        }
-        match self.sxclient.read().await.as_ref().unwrap().client.write().await.close_mbus(mbus).await {
-            Ok(res) => {
-                debug!("{:?}", res);
-            },
-            Err(err) => {
-                error!("sxutil: Error closing Mbus: {}", err);
-                return false;
-            },
-        };
+        self.with_deadline(msg_timeout, async {
+            if !self.sxclient.is_connected() {
+                error!("sxutil: SXClient is None!");
+                return Err(SxutilError::NotConnected);
+            }
+            match self.grpc_client().await?.close_mbus(deadline_request(mbus, msg_timeout)).await {
+                Ok(res) => {
+                    debug!("{:?}", res);
+                    Ok(())
+                },
+                Err(err) => {
+                    error!("sxutil: Error closing Mbus: {}", err);
+                    Err(SxutilError::from(err))
+                },
+            }
+        }).await?;
         let pos = self.mbus_index(mbus_id).await;
         if pos >= 0 {
             self.remove_mbus_index(pos as usize).await;
@@ -455,12 +652,17 @@ impl SXServiceClient {
             error!("not found mbusID[{}]\n", mbus_id);
         }
 
-        true
+        Ok(())
     }
         
     // NotifyDemand sends Typed Demand to Server
-    pub async fn notify_demand(&self, mut dmo: DemandOpts) -> Option<u64> {
-        let id = generate_int_id().await;
+    pub async fn notify_demand(&self, dmo: DemandOpts) -> Result<u64, SxutilError> {
+        self.notify_demand_with_timeout(dmo, self.config.msg_timeout).await
+    }
+
+    // NotifyDemandWithTimeout is NotifyDemand with per-call message timeout
+    pub async fn notify_demand_with_timeout(&self, mut dmo: DemandOpts, msg_timeout: Duration) -> Result<u64, SxutilError> {
+        let id = self.generate_int_id().await;
         let dt = Local::now();
         let ts = Timestamp::date_time_nanos(dt.year() as i64, dt.month() as u8, dt.day() as u8, dt.hour() as u8, dt.minute() as u8, dt.second() as u8, dt.nanosecond() as u32).unwrap();
         let dm = api::Demand {
@@ -481,31 +683,36 @@ impl SXServiceClient {
         //Todo: We need to make if for each channel type
         //	}
 
-        // ctx, cancel := context.WithTimeout(context.Background(), MSG_TIME_OUT*time.Second)
-        // defer cancel()
-
-        if self.sxclient.read().await.is_none() {
-            error!("sxutil: SXClient is None!");
-            return None;
-        }
+        self.with_deadline(msg_timeout, async {
+            if !self.sxclient.is_connected() {
+                error!("sxutil: SXClient is None!");
+                return Err(SxutilError::NotConnected);
+            }
 
-        match self.sxclient.read().await.as_ref().unwrap().client.write().await.notify_demand(dm.clone()).await {
-            Ok(resp) => {
-                debug!("NotifyDemand Response: {:?} PID: {}", resp, id);
-            },
-            Err(err) => {
-                error!("{:?}.NotifyDemand err {}, [{:?}]", self, err, dm);
-                return None;
-            },
-        }
+            match self.grpc_client().await?.notify_demand(deadline_request(dm.clone(), msg_timeout)).await {
+                Ok(resp) => {
+                    debug!("NotifyDemand Response: {:?} PID: {}", resp, id);
+                    Ok(())
+                },
+                Err(err) => {
+                    error!("{:?}.NotifyDemand err {}, [{:?}]", self, err, dm);
+                    Err(SxutilError::from(err))
+                },
+            }
+        }).await?;
 
         dmo.id = id;
-        Some(id)
+        Ok(id)
     }
         
     // NotifySupply sends Typed Supply to Server
-    pub async fn notify_supply(&self, mut smo: SupplyOpts) -> Option<u64> {
-        let id = generate_int_id().await;
+    pub async fn notify_supply(&self, smo: SupplyOpts) -> Result<u64, SxutilError> {
+        self.notify_supply_with_timeout(smo, self.config.msg_timeout).await
+    }
+
+    // NotifySupplyWithTimeout is NotifySupply with per-call message timeout
+    pub async fn notify_supply_with_timeout(&self, mut smo: SupplyOpts, msg_timeout: Duration) -> Result<u64, SxutilError> {
+        let id = self.generate_int_id().await;
         let dt = Local::now();
         let ts = Timestamp::date_time_nanos(dt.year() as i64, dt.month() as u8, dt.day() as u8, dt.hour() as u8, dt.minute() as u8, dt.second() as u8, dt.nanosecond() as u32).unwrap();
         let sp = api::Supply {
@@ -526,32 +733,37 @@ impl SXServiceClient {
         //Todo: We need to make if for each channel type
         //	}
 
-        // ctx, cancel := context.WithTimeout(context.Background(), MSG_TIME_OUT*time.Second)
-        // defer cancel()
-
-        if self.sxclient.read().await.is_none() {
-            error!("sxutil: SXClient is None!");
-            return None;
-        }
+        self.with_deadline(msg_timeout, async {
+            if !self.sxclient.is_connected() {
+                error!("sxutil: SXClient is None!");
+                return Err(SxutilError::NotConnected);
+            }
 
-        match self.sxclient.read().await.as_ref().unwrap().client.write().await.notify_supply(sp.clone()).await {
-            Ok(resp) => {
-                debug!("NotifySupply Response: {:?} PID: {}", resp, id);
-            },
-            Err(err) => {
-                error!("{:?}.NotifySupply err {}, [{:?}]", self, err, sp);
-                return None;
-            },
-        }
+            match self.grpc_client().await?.notify_supply(deadline_request(sp.clone(), msg_timeout)).await {
+                Ok(resp) => {
+                    debug!("NotifySupply Response: {:?} PID: {}", resp, id);
+                    Ok(())
+                },
+                Err(err) => {
+                    error!("{:?}.NotifySupply err {}, [{:?}]", self, err, sp);
+                    Err(SxutilError::from(err))
+                },
+            }
+        }).await?;
 
         smo.id = id;
-        Some(id)
+        Ok(id)
     }
 
     // Confirm sends confirm message to sender
-    pub async fn confirm(&self, id: IDType, pid: IDType) -> Result<(), Box<dyn Error>> {
+    pub async fn confirm(&self, id: IDType, pid: IDType) -> Result<(), SxutilError> {
+        self.confirm_with_timeout(id, pid, self.config.msg_timeout).await
+    }
+
+    // ConfirmWithTimeout is Confirm with per-call message timeout
+    pub async fn confirm_with_timeout(&self, id: IDType, pid: IDType, msg_timeout: Duration) -> Result<(), SxutilError> {
         let tg = api::Target{
-            id: generate_int_id().await,
+            id: self.generate_int_id().await,
             sender_id: self.client_id,
             target_id: id,
             channel_type: self.channel_type,
@@ -559,29 +771,260 @@ impl SXServiceClient {
             mbus_id: id,
         };
 
-        // ctx, cancel := context.WithTimeout(context.Background(), MSG_TIME_OUT*time.Second)
-        // defer cancel()
-
-        if self.sxclient.read().await.is_none() {
-            error!("sxutil: SXClient is None!");
-            return Err(Box::from(SxutilError));
-        }
+        let resp = self.with_deadline(msg_timeout, async {
+            if !self.sxclient.is_connected() {
+                error!("sxutil: SXClient is None!");
+                return Err(SxutilError::NotConnected);
+            }
 
-        let resp = match self.sxclient.read().await.as_ref().unwrap().client.write().await.confirm(tg.clone()).await {
-            Ok(resp) => resp,
-            Err(err) => {
-                error!("{:?}.Confirm failed {}, [{:?}]", self, err, tg);
-                return Err(Box::from(err))
-            },
-        };
+            match self.grpc_client().await?.confirm(deadline_request(tg.clone(), msg_timeout)).await {
+                Ok(resp) => Ok(resp),
+                Err(err) => {
+                    error!("{:?}.Confirm failed {}, [{:?}]", self, err, tg);
+                    Err(SxutilError::from(err))
+                },
+            }
+        }).await?;
 
         self.mbus_ids.write().await.push(id);
         debug!("Confirm Success: {:?}", resp);
 
         // nodestate may not work v0.5.0.
         //	clt.NI.nodeState.selectDemand(uint64(id))
-        self.ni.as_ref().unwrap().write().await.node_state.select_supply(pid);
+        if let Some(ni) = self.ni.as_ref() {
+            ni.write().await.node_state.select_supply(pid);
+        }
 
         Ok(())
     }
 }
+
+// stream which opens subscription by `open` again after error (with reconnection) or server change.
+// clt is the client (borrowed or owned by Arc) passed to `open`.
+// end_on_close: finish when server closes the stream (otherwise reconnect).
+fn resubscribe_stream<'a, C, T, F, Fut>(clt: C, open: F, end_on_close: bool) -> impl Stream<Item = Result<T, SxutilError>> + 'a
+where
+    C: Deref<Target = SXServiceClient> + Clone + Send + 'a,
+    T: prost::Message + Default + 'a,
+    F: Fn(C) -> Fut + 'a,
+    Fut: Future<Output = Result<tonic::Streaming<T>, SxutilError>> + 'a,
+{
+    let state = SubscribeState::<T> { subscription: None, serv_addr: None, server_change: None, failback: None, reconnect: false };
+    stream::unfold((state, open, clt), move |(mut st, open, clt)| async move {
+        loop {
+            let Some(subscription) = st.subscription.as_mut() else {
+                if st.server_change.is_none() {
+                    st.server_change = clt.watch_server_change().await;
+                }
+                let current = clt.server_address().await;
+                if st.reconnect && current == st.serv_addr { // not moved by server change
+                    match st.serv_addr.clone() {
+                        Some(serv_addr) => clt.reconnect(&serv_addr).await,
+                        None => return None,
+                    }
+                }
+                st.serv_addr = clt.server_address().await;
+                match open(clt.clone()).await {
+                    Ok(subscription) => {
+                        st.reconnect = false;
+                        st.subscription = Some(subscription);
+                        let failback = clt.clone();
+                        st.failback = Some(Box::pin(async move { failback.failback().await }));
+                        continue;
+                    },
+                    Err(err) => {
+                        st.reconnect = true;
+                        return Some((Err(err), (st, open, clt)));
+                    },
+                }
+            };
+
+            let received = tokio::select! {
+                msg = subscription.message() => Some(msg),
+                _ = server_changed(&mut st.server_change) => None,
+                _ = failback_due(&mut st.failback) => None,
+            };
+            match received {
+                Some(Ok(Some(msg))) => {
+                    if clt.is_locked().await {
+                        error!("sxutil: Provider is locked!");
+                        continue;
+                    }
+                    return Some((Ok(msg), (st, open, clt)));
+                },
+                Some(Ok(None)) => {
+                    info!("sxutil: End subscribe stream");
+                    st.subscription = None;
+                    if end_on_close {
+                        return None;
+                    }
+                    st.reconnect = true;
+                },
+                Some(Err(err)) => {
+                    error!("sxutil: subscribe stream error [{}]", err);
+                    st.subscription = None;
+                    st.reconnect = true;
+                    return Some((Err(SxutilError::from(err)), (st, open, clt)));
+                },
+                None => {
+                    info!("sxutil: subscribe again by server change / failback");
+                    st.subscription = None;
+                },
+            }
+        }
+    })
+}
+
+// state of resubscribe_stream
+struct SubscribeState<'a, T> {
+    subscription: Option<tonic::Streaming<T>>,
+    serv_addr: Option<String>,
+    server_change: Option<watch::Receiver<String>>,
+    failback: Option<BoxFuture<'a, ()>>,
+    reconnect: bool,
+}
+
+// receives next message, None when stream is finished or server is changed
+async fn next_message<M, F>(name: &str, subscription: &mut tonic::Streaming<M>, server_change: &mut Option<watch::Receiver<String>>, failback: Pin<&mut F>) -> Result<Option<M>, SxutilError>
+where
+    M: prost::Message + Default,
+    F: Future<Output = ()>,
+{
+    tokio::select! {
+        msg = subscription.message() => match msg {
+            Ok(Some(msg)) => Ok(Some(msg)),
+            Ok(None) => {
+                info!("sxutil: End {} subscribe OK", name);
+                Ok(None)
+            },
+            Err(err) => {
+                error!("sxutil: SXServiceClient Subscribe{} error [{}]", name, err);
+                Err(SxutilError::from(err))
+            },
+        },
+        _ = server_changed(server_change) => {
+            info!("sxutil: End {} subscribe by server change", name);
+            Ok(None)
+        },
+        _ = failback => {
+            info!("sxutil: End {} subscribe by failback", name);
+            Ok(None)
+        },
+    }
+}
+
+// resolves when synerex server is changed (never without node)
+async fn server_changed(server_change: &mut Option<watch::Receiver<String>>) {
+    if let Some(rx) = server_change {
+        if rx.changed().await.is_ok() {
+            return;
+        }
+    }
+    future::pending().await
+}
+
+async fn failback_due(failback: &mut Option<BoxFuture<'_, ()>>) {
+    match failback {
+        Some(failback) => failback.await,
+        None => future::pending().await,
+    }
+}
+
+// wraps message into request with gRPC deadline (grpc-timeout header)
+fn deadline_request<T>(msg: T, msg_timeout: Duration) -> tonic::Request<T> {
+    let mut req = tonic::Request::new(msg);
+    req.set_timeout(msg_timeout);
+    req
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use tokio::sync::mpsc;
+    use tonic::Status;
+    use crate::SxutilNode;
+    use crate::testing::{MockMethod, MockSynerexServer};
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    async fn service_client(server: &MockSynerexServer) -> Arc<SXServiceClient> {
+        let node = SxutilNode::with_config(SxutilConfig::default().with_reconnect_wait(Duration::from_millis(10)));
+        let clt = server.connect().await.unwrap();
+        Arc::new(node.new_sx_service_client(clt, 1, String::new()).await)
+    }
+
+    async fn wait_for_subscribers(server: &MockSynerexServer, method: MockMethod, count: usize) {
+        timeout(WAIT, async {
+            while server.subscribers(method) != count {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        }).await.unwrap();
+    }
+
+    // owned stream runs on its own task and forwards items
+    fn forward<T: Send + 'static>(stream: impl Stream<Item = T> + Send + 'static) -> (mpsc::UnboundedReceiver<T>, tokio::task::JoinHandle<()>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let forwarder = tokio::spawn(async move {
+            let mut stream = std::pin::pin!(stream);
+            while let Some(item) = stream.next().await {
+                if tx.send(item).is_err() {
+                    break;
+                }
+            }
+        });
+        (rx, forwarder)
+    }
+
+    #[tokio::test]
+    async fn supply_stream_resubscribes_after_drop() {
+        let server = MockSynerexServer::start().await.unwrap();
+        let clt = service_client(&server).await;
+        let (mut supplies, forwarder) = forward(Arc::clone(&clt).into_supply_stream());
+
+        wait_for_subscribers(&server, MockMethod::SubscribeSupply, 1).await;
+        server.publish_supply(api::Supply { id: 1, channel_type: 1, ..Default::default() });
+        assert_eq!(timeout(WAIT, supplies.recv()).await.unwrap().unwrap().unwrap().id, 1);
+
+        server.drop_streams(Status::unavailable("dropped"));
+        assert!(timeout(WAIT, supplies.recv()).await.unwrap().unwrap().is_err());
+
+        wait_for_subscribers(&server, MockMethod::SubscribeSupply, 1).await;
+        assert_eq!(server.requests_of(MockMethod::SubscribeSupply).len(), 2);
+        server.publish_supply(api::Supply { id: 2, channel_type: 1, ..Default::default() });
+        assert_eq!(timeout(WAIT, supplies.recv()).await.unwrap().unwrap().unwrap().id, 2);
+        forwarder.abort();
+    }
+
+    #[tokio::test]
+    async fn demand_stream_borrows_client() {
+        let server = MockSynerexServer::start_in_memory().await;
+        let clt = service_client(&server).await;
+        let mut demands = std::pin::pin!(clt.demand_stream());
+
+        let received = async {
+            wait_for_subscribers(&server, MockMethod::SubscribeDemand, 1).await;
+            server.publish_demand(api::Demand { id: 3, channel_type: 1, ..Default::default() });
+        };
+        let (demand, ()) = tokio::join!(timeout(WAIT, demands.next()), received);
+        assert_eq!(demand.unwrap().unwrap().unwrap().id, 3);
+    }
+
+    #[tokio::test]
+    async fn mbus_stream_ends_when_mbus_is_closed() {
+        let server = MockSynerexServer::start().await.unwrap();
+        let clt = service_client(&server).await;
+        let mbus = clt.create_mbus(api::MbusOpt::default()).await.unwrap();
+        clt.mbus_ids.write().await.push(mbus.mbus_id); // as after select / confirm
+        let (mut msgs, forwarder) = forward(Arc::clone(&clt).into_mbus_stream(mbus.mbus_id));
+
+        wait_for_subscribers(&server, MockMethod::SubscribeMbus, 1).await;
+        server.publish_mbus_msg(api::MbusMsg { msg_id: 1, mbus_id: mbus.mbus_id, ..Default::default() });
+        assert_eq!(timeout(WAIT, msgs.recv()).await.unwrap().unwrap().unwrap().msg_id, 1);
+
+        clt.close_mbus(mbus.mbus_id).await.unwrap();
+        assert!(timeout(WAIT, msgs.recv()).await.unwrap().is_none());
+        timeout(WAIT, forwarder).await.unwrap().unwrap();
+        assert_eq!(server.requests_of(MockMethod::SubscribeMbus).len(), 1);
+    }
+}
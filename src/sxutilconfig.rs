@@ -0,0 +1,530 @@
+use core::time::Duration;
+use std::{collections::BTreeMap, env, fs, path::Path};
+
+use serde::Deserialize;
+
+use crate::{Backoff, ConnectOptions, OverflowPolicy, TlsOptions, ReceiveBufferOptions, ReconnectPolicy, SxutilError, WAIT_TIME, MSG_TIME_OUT, RECONNECT_WAIT};
+
+// environment variables read by SxutilConfig::from_env (values are seconds)
+pub const ENV_WAIT_TIME: &str = "SXUTIL_WAIT_TIME";
+pub const ENV_MSG_TIME_OUT: &str = "SXUTIL_MSG_TIME_OUT";
+pub const ENV_RECONNECT_WAIT: &str = "SXUTIL_RECONNECT_WAIT";
+pub const ENV_KEEPALIVE_INTERVAL: &str = "SXUTIL_KEEPALIVE_INTERVAL";
+pub const ENV_RECONNECT_MAX_WAIT: &str = "SXUTIL_RECONNECT_MAX_WAIT"; // enables exponential backoff
+pub const ENV_RECONNECT_MAX_ATTEMPTS: &str = "SXUTIL_RECONNECT_MAX_ATTEMPTS"; // count
+pub const ENV_RECONNECT_MAX_ELAPSED: &str = "SXUTIL_RECONNECT_MAX_ELAPSED";
+pub const ENV_KEEPALIVE_FAILURE_THRESHOLD: &str = "SXUTIL_KEEPALIVE_FAILURE_THRESHOLD"; // count
+pub const ENV_RECEIVE_BUFFER_CAPACITY: &str = "SXUTIL_RECEIVE_BUFFER_CAPACITY"; // count
+pub const ENV_RECEIVE_BUFFER_POLICY: &str = "SXUTIL_RECEIVE_BUFFER_POLICY"; // block, drop_oldest, drop_newest, keep_latest_per_sender
+pub const ENV_CONNECT_TIMEOUT: &str = "SXUTIL_CONNECT_TIMEOUT";
+pub const ENV_REQUEST_TIMEOUT: &str = "SXUTIL_REQUEST_TIMEOUT";
+pub const ENV_HTTP2_KEEPALIVE_INTERVAL: &str = "SXUTIL_HTTP2_KEEPALIVE_INTERVAL";
+pub const ENV_HTTP2_KEEPALIVE_TIMEOUT: &str = "SXUTIL_HTTP2_KEEPALIVE_TIMEOUT";
+pub const ENV_TCP_NODELAY: &str = "SXUTIL_TCP_NODELAY"; // true / false
+pub const ENV_LAZY_CONNECT: &str = "SXUTIL_LAZY_CONNECT"; // true / false
+pub const ENV_TLS: &str = "SXUTIL_TLS"; // true / false (system root certificates without CA)
+pub const ENV_TLS_CA_CERT: &str = "SXUTIL_TLS_CA_CERT"; // PEM file path
+pub const ENV_TLS_CLIENT_CERT: &str = "SXUTIL_TLS_CLIENT_CERT"; // PEM file path
+pub const ENV_TLS_CLIENT_KEY: &str = "SXUTIL_TLS_CLIENT_KEY"; // PEM file path
+pub const ENV_TLS_DOMAIN_NAME: &str = "SXUTIL_TLS_DOMAIN_NAME";
+pub const ENV_AUTH_TOKEN: &str = "SXUTIL_AUTH_TOKEN"; // bearer token
+pub const ENV_METADATA: &str = "SXUTIL_METADATA"; // key=value,key=value
+
+// default count of consecutive keepalive failures before re-registration
+const KEEPALIVE_FAILURE_THRESHOLD: u32 = 3;
+
+// SxutilConfig is runtime configuration for timeouts and reconnect timing
+#[derive(Debug, Clone, PartialEq)]
+pub struct SxutilConfig {
+    // how long provider stays locked on SERVER_CHANGE while proposals are in flight
+    pub wait_time: Duration,
+    // message timeout for synerex server
+    pub msg_timeout: Duration,
+    // wait before re-connecting to synerex server
+    pub reconnect_wait: Duration,
+    // backoff and retry limits of subscription loops (reconnect_wait is the base wait)
+    pub reconnect_policy: ReconnectPolicy,
+    // keepalive interval, None means using the duration given by node server
+    pub keepalive_interval: Option<Duration>,
+    // consecutive keepalive failures regarded as node server failure (then reconnect and re-register)
+    pub keepalive_failure_threshold: u32,
+    // bounded buffer between subscription and handler, None means handler is awaited for each message
+    pub receive_buffer: Option<ReceiveBufferOptions>,
+    // options of synerex server connections (and node server connection unless node_connect is set)
+    pub connect: ConnectOptions,
+    // options of node server connection, None means using connect
+    pub node_connect: Option<ConnectOptions>,
+}
+
+// values in config file are seconds (fractions allowed)
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SxutilConfigFile {
+    wait_time: Option<f64>,
+    msg_timeout: Option<f64>,
+    reconnect_wait: Option<f64>,
+    reconnect_max_wait: Option<f64>,
+    reconnect_max_attempts: Option<u32>,
+    reconnect_max_elapsed: Option<f64>,
+    keepalive_interval: Option<f64>,
+    keepalive_failure_threshold: Option<u32>,
+    receive_buffer_capacity: Option<usize>,
+    receive_buffer_policy: Option<String>,
+    connect_timeout: Option<f64>,
+    request_timeout: Option<f64>,
+    http2_keepalive_interval: Option<f64>,
+    http2_keepalive_timeout: Option<f64>,
+    tcp_nodelay: Option<bool>,
+    lazy_connect: Option<bool>,
+    tls: Option<bool>,
+    tls_ca_cert: Option<String>,
+    tls_client_cert: Option<String>,
+    tls_client_key: Option<String>,
+    tls_domain_name: Option<String>,
+    auth_token: Option<String>,
+    metadata: Option<BTreeMap<String, String>>,
+}
+
+// ConnectOptions values given by environment variables or config file
+#[derive(Debug, Default)]
+struct ConnectSettings {
+    connect_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+    http2_keepalive_interval: Option<Duration>,
+    http2_keepalive_timeout: Option<Duration>,
+    tcp_nodelay: Option<bool>,
+    lazy_connect: Option<bool>,
+    tls: Option<bool>,
+    tls_ca_cert: Option<String>,
+    tls_client_cert: Option<String>,
+    tls_client_key: Option<String>,
+    tls_domain_name: Option<String>,
+    auth_token: Option<String>,
+    metadata: Vec<(String, String)>,
+}
+
+impl Default for SxutilConfig {
+    fn default() -> Self {
+        SxutilConfig {
+            wait_time: Duration::from_secs(WAIT_TIME),
+            msg_timeout: Duration::from_secs(MSG_TIME_OUT),
+            reconnect_wait: Duration::from_secs(RECONNECT_WAIT),
+            reconnect_policy: ReconnectPolicy::default(),
+            keepalive_interval: None,
+            keepalive_failure_threshold: KEEPALIVE_FAILURE_THRESHOLD,
+            receive_buffer: None,
+            connect: ConnectOptions::default(),
+            node_connect: None,
+        }
+    }
+}
+
+impl SxutilConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_wait_time(mut self, wait_time: Duration) -> Self {
+        self.wait_time = wait_time;
+        self
+    }
+
+    pub fn with_msg_timeout(mut self, msg_timeout: Duration) -> Self {
+        self.msg_timeout = msg_timeout;
+        self
+    }
+
+    pub fn with_reconnect_wait(mut self, reconnect_wait: Duration) -> Self {
+        self.reconnect_wait = reconnect_wait;
+        self
+    }
+
+    pub fn with_reconnect_policy(mut self, reconnect_policy: ReconnectPolicy) -> Self {
+        self.reconnect_policy = reconnect_policy;
+        self
+    }
+
+    pub fn with_keepalive_interval(mut self, keepalive_interval: Duration) -> Self {
+        self.keepalive_interval = Some(keepalive_interval);
+        self
+    }
+
+    pub fn with_keepalive_failure_threshold(mut self, threshold: u32) -> Self {
+        self.keepalive_failure_threshold = threshold;
+        self
+    }
+
+    pub fn with_receive_buffer(mut self, receive_buffer: ReceiveBufferOptions) -> Self {
+        self.receive_buffer = Some(receive_buffer);
+        self
+    }
+
+    pub fn with_connect(mut self, connect: ConnectOptions) -> Self {
+        self.connect = connect;
+        self
+    }
+
+    pub fn with_node_connect(mut self, node_connect: ConnectOptions) -> Self {
+        self.node_connect = Some(node_connect);
+        self
+    }
+
+    // options of node server connection
+    pub fn node_connect_options(&self) -> &ConnectOptions {
+        self.node_connect.as_ref().unwrap_or(&self.connect)
+    }
+
+    // returns keepalive interval, falls back to the duration given by node server
+    pub fn keepalive_interval_or(&self, keepalive_duration: i32) -> Duration {
+        match self.keepalive_interval {
+            Some(interval) => interval,
+            None => Duration::from_secs(keepalive_duration.max(0) as u64),
+        }
+    }
+
+    // FromEnv builds config from defaults overridden by SXUTIL_* environment variables
+    pub fn from_env() -> Result<Self, SxutilError> {
+        Self::default().merge_env()
+    }
+
+    // overrides fields by SXUTIL_* environment variables if they are set
+    pub fn merge_env(mut self) -> Result<Self, SxutilError> {
+        if let Some(secs) = env_secs(ENV_WAIT_TIME)? {
+            self.wait_time = secs;
+        }
+        if let Some(secs) = env_secs(ENV_MSG_TIME_OUT)? {
+            self.msg_timeout = secs;
+        }
+        if let Some(secs) = env_secs(ENV_RECONNECT_WAIT)? {
+            self.reconnect_wait = secs;
+        }
+        self.merge_reconnect_policy(env_secs(ENV_RECONNECT_MAX_WAIT)?, env_count(ENV_RECONNECT_MAX_ATTEMPTS)?, env_secs(ENV_RECONNECT_MAX_ELAPSED)?);
+        if let Some(secs) = env_secs(ENV_KEEPALIVE_INTERVAL)? {
+            self.keepalive_interval = Some(secs);
+        }
+        if let Some(count) = env_count(ENV_KEEPALIVE_FAILURE_THRESHOLD)? {
+            self.keepalive_failure_threshold = count;
+        }
+        self.merge_connect(ConnectSettings {
+            connect_timeout: env_secs(ENV_CONNECT_TIMEOUT)?,
+            request_timeout: env_secs(ENV_REQUEST_TIMEOUT)?,
+            http2_keepalive_interval: env_secs(ENV_HTTP2_KEEPALIVE_INTERVAL)?,
+            http2_keepalive_timeout: env_secs(ENV_HTTP2_KEEPALIVE_TIMEOUT)?,
+            tcp_nodelay: env_bool(ENV_TCP_NODELAY)?,
+            lazy_connect: env_bool(ENV_LAZY_CONNECT)?,
+            tls: env_bool(ENV_TLS)?,
+            tls_ca_cert: env_string(ENV_TLS_CA_CERT)?,
+            tls_client_cert: env_string(ENV_TLS_CLIENT_CERT)?,
+            tls_client_key: env_string(ENV_TLS_CLIENT_KEY)?,
+            tls_domain_name: env_string(ENV_TLS_DOMAIN_NAME)?,
+            auth_token: env_string(ENV_AUTH_TOKEN)?,
+            metadata: env_metadata(ENV_METADATA)?,
+        })?;
+        self.merge_receive_buffer(env_count(ENV_RECEIVE_BUFFER_CAPACITY)?, env_string(ENV_RECEIVE_BUFFER_POLICY)?)
+    }
+
+    // FromFile builds config from TOML (.toml) or YAML (.yaml/.yml) file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, SxutilError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|err| SxutilError::Config(format!("can't read {}: {}", path.display(), err)))?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::from_toml_str(&text),
+            Some("yaml") | Some("yml") => Self::from_yaml_str(&text),
+            _ => Err(SxutilError::Config(format!("unknown config file type: {}", path.display()))),
+        }
+    }
+
+    pub fn from_toml_str(text: &str) -> Result<Self, SxutilError> {
+        let file: SxutilConfigFile = toml::from_str(text)
+            .map_err(|err| SxutilError::Config(format!("toml: {}", err)))?;
+        Self::default().merge_file(file)
+    }
+
+    pub fn from_yaml_str(text: &str) -> Result<Self, SxutilError> {
+        let file: SxutilConfigFile = serde_yaml::from_str(text)
+            .map_err(|err| SxutilError::Config(format!("yaml: {}", err)))?;
+        Self::default().merge_file(file)
+    }
+
+    fn merge_file(mut self, file: SxutilConfigFile) -> Result<Self, SxutilError> {
+        if let Some(secs) = file.wait_time {
+            self.wait_time = secs_to_duration("wait_time", secs)?;
+        }
+        if let Some(secs) = file.msg_timeout {
+            self.msg_timeout = secs_to_duration("msg_timeout", secs)?;
+        }
+        if let Some(secs) = file.reconnect_wait {
+            self.reconnect_wait = secs_to_duration("reconnect_wait", secs)?;
+        }
+        let max_wait = file.reconnect_max_wait.map(|secs| secs_to_duration("reconnect_max_wait", secs)).transpose()?;
+        let max_elapsed = file.reconnect_max_elapsed.map(|secs| secs_to_duration("reconnect_max_elapsed", secs)).transpose()?;
+        self.merge_reconnect_policy(max_wait, file.reconnect_max_attempts, max_elapsed);
+        if let Some(secs) = file.keepalive_interval {
+            self.keepalive_interval = Some(secs_to_duration("keepalive_interval", secs)?);
+        }
+        if let Some(count) = file.keepalive_failure_threshold {
+            self.keepalive_failure_threshold = count;
+        }
+        let secs = |name, secs: Option<f64>| secs.map(|secs| secs_to_duration(name, secs)).transpose();
+        self.merge_connect(ConnectSettings {
+            connect_timeout: secs("connect_timeout", file.connect_timeout)?,
+            request_timeout: secs("request_timeout", file.request_timeout)?,
+            http2_keepalive_interval: secs("http2_keepalive_interval", file.http2_keepalive_interval)?,
+            http2_keepalive_timeout: secs("http2_keepalive_timeout", file.http2_keepalive_timeout)?,
+            tcp_nodelay: file.tcp_nodelay,
+            lazy_connect: file.lazy_connect,
+            tls: file.tls,
+            tls_ca_cert: file.tls_ca_cert,
+            tls_client_cert: file.tls_client_cert,
+            tls_client_key: file.tls_client_key,
+            tls_domain_name: file.tls_domain_name,
+            auth_token: file.auth_token,
+            metadata: file.metadata.unwrap_or_default().into_iter().collect(),
+        })?;
+        self.merge_receive_buffer(file.receive_buffer_capacity, file.receive_buffer_policy)
+    }
+
+    // tls_* settings enable TLS (tls = false disables it)
+    fn merge_connect(&mut self, settings: ConnectSettings) -> Result<(), SxutilError> {
+        let connect = &mut self.connect;
+        if settings.connect_timeout.is_some() {
+            connect.connect_timeout = settings.connect_timeout;
+        }
+        if settings.request_timeout.is_some() {
+            connect.request_timeout = settings.request_timeout;
+        }
+        if settings.http2_keepalive_interval.is_some() {
+            connect.http2_keepalive_interval = settings.http2_keepalive_interval;
+        }
+        if settings.http2_keepalive_timeout.is_some() {
+            connect.http2_keepalive_timeout = settings.http2_keepalive_timeout;
+        }
+        if let Some(tcp_nodelay) = settings.tcp_nodelay {
+            connect.tcp_nodelay = tcp_nodelay;
+        }
+        if let Some(lazy) = settings.lazy_connect {
+            connect.lazy = lazy;
+        }
+        if let Some(token) = settings.auth_token {
+            connect.interceptor = connect.interceptor.clone().with_token(token);
+        }
+        for (key, value) in settings.metadata {
+            connect.interceptor = connect.interceptor.clone().with_metadata(&key, &value)?;
+        }
+
+        let files = TlsOptions::from_pem_files(settings.tls_ca_cert, settings.tls_client_cert, settings.tls_client_key)?;
+        let use_tls = files.ca_cert.is_some() || files.client_identity.is_some() || settings.tls_domain_name.is_some();
+        if settings.tls == Some(false) {
+            connect.tls = None;
+        } else if use_tls || settings.tls == Some(true) {
+            let tls = connect.tls.get_or_insert_with(TlsOptions::default);
+            if files.ca_cert.is_some() {
+                tls.ca_cert = files.ca_cert;
+            }
+            if files.client_identity.is_some() {
+                tls.client_identity = files.client_identity;
+            }
+            if settings.tls_domain_name.is_some() {
+                tls.domain_name = settings.tls_domain_name;
+            }
+        }
+        Ok(())
+    }
+
+    // max_wait switches backoff to exponential with full jitter
+    fn merge_reconnect_policy(&mut self, max_wait: Option<Duration>, max_attempts: Option<u32>, max_elapsed: Option<Duration>) {
+        if let Some(max_wait) = max_wait {
+            self.reconnect_policy.backoff = Backoff::ExponentialJitter { max_wait };
+        }
+        if max_attempts.is_some() {
+            self.reconnect_policy.max_attempts = max_attempts;
+        }
+        if max_elapsed.is_some() {
+            self.reconnect_policy.max_elapsed = max_elapsed;
+        }
+    }
+
+    // capacity enables receive buffer (policy defaults to block)
+    fn merge_receive_buffer(mut self, capacity: Option<usize>, policy: Option<String>) -> Result<Self, SxutilError> {
+        if let Some(capacity) = capacity {
+            let policy = self.receive_buffer.map_or(OverflowPolicy::Block, |buf| buf.policy);
+            self.receive_buffer = Some(ReceiveBufferOptions::new(capacity, policy));
+        }
+        if let Some(policy) = policy {
+            let policy = policy.parse()?;
+            match self.receive_buffer.as_mut() {
+                Some(buf) => buf.policy = policy,
+                None => return Err(SxutilError::Config(String::from("receive buffer policy is set without capacity"))),
+            }
+        }
+        Ok(self)
+    }
+}
+
+fn env_secs(name: &str) -> Result<Option<Duration>, SxutilError> {
+    match env::var(name) {
+        Ok(value) => {
+            let secs: f64 = value.trim().parse()
+                .map_err(|_| SxutilError::Config(format!("{} should be seconds: {}", name, value)))?;
+            Ok(Some(secs_to_duration(name, secs)?))
+        },
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(err) => Err(SxutilError::Config(format!("{}: {}", name, err))),
+    }
+}
+
+fn env_count<T: std::str::FromStr>(name: &str) -> Result<Option<T>, SxutilError> {
+    match env::var(name) {
+        Ok(value) => value.trim().parse().map(Some)
+            .map_err(|_| SxutilError::Config(format!("{} should be a count: {}", name, value))),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(err) => Err(SxutilError::Config(format!("{}: {}", name, err))),
+    }
+}
+
+fn env_bool(name: &str) -> Result<Option<bool>, SxutilError> {
+    match env_string(name)? {
+        Some(value) => value.trim().parse().map(Some)
+            .map_err(|_| SxutilError::Config(format!("{} should be true or false: {}", name, value))),
+        None => Ok(None),
+    }
+}
+
+fn env_metadata(name: &str) -> Result<Vec<(String, String)>, SxutilError> {
+    let Some(value) = env_string(name)? else {
+        return Ok(Vec::new());
+    };
+    value.split(',').map(str::trim).filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) => Ok((key.trim().to_string(), value.trim().to_string())),
+            None => Err(SxutilError::Config(format!("{} should be key=value: {}", name, pair))),
+        })
+        .collect()
+}
+
+fn env_string(name: &str) -> Result<Option<String>, SxutilError> {
+    match env::var(name) {
+        Ok(value) => Ok(Some(value)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(err) => Err(SxutilError::Config(format!("{}: {}", name, err))),
+    }
+}
+
+fn secs_to_duration(name: &str, secs: f64) -> Result<Duration, SxutilError> {
+    Duration::try_from_secs_f64(secs)
+        .map_err(|_| SxutilError::Config(format!("{} should be non-negative seconds: {}", name, secs)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::service::Interceptor;
+
+    #[test]
+    fn toml_and_yaml_set_seconds() {
+        let toml = SxutilConfig::from_toml_str("msg_timeout = 2.5\nreconnect_wait = 1\nkeepalive_interval = 0.5\n").unwrap();
+        assert_eq!(toml.msg_timeout, Duration::from_millis(2500));
+        assert_eq!(toml.reconnect_wait, Duration::from_secs(1));
+        assert_eq!(toml.keepalive_interval, Some(Duration::from_millis(500)));
+        assert_eq!(toml.wait_time, SxutilConfig::default().wait_time);
+
+        let yaml = SxutilConfig::from_yaml_str("msg_timeout: 2.5\nreconnect_wait: 1\nkeepalive_interval: 0.5\n").unwrap();
+        assert_eq!(yaml, toml);
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(matches!(SxutilConfig::from_toml_str("msg_timeuot = 1"), Err(SxutilError::Config(_))));
+        assert!(matches!(SxutilConfig::from_yaml_str("msg_timeuot: 1"), Err(SxutilError::Config(_))));
+    }
+
+    #[test]
+    fn negative_durations_are_rejected() {
+        assert!(matches!(SxutilConfig::from_toml_str("msg_timeout = -1"), Err(SxutilError::Config(_))));
+        assert!(matches!(SxutilConfig::from_yaml_str("reconnect_wait: -0.5"), Err(SxutilError::Config(_))));
+        assert!(matches!(SxutilConfig::from_toml_str("keepalive_interval = -10"), Err(SxutilError::Config(_))));
+    }
+
+    #[test]
+    fn tls_is_inferred_from_https_address() {
+        let config = SxutilConfig::from_toml_str("connect_timeout = 1").unwrap();
+        assert_eq!(config.connect.tls, None);
+        assert_eq!(config.connect.tls_for("https://sx.example.com:10000"), Some(TlsOptions::default()));
+        assert_eq!(config.connect.tls_for("http://sx.example.com:10000"), None);
+        assert_eq!(config.connect.tls_for("sx.example.com:10000"), None);
+
+        let config = SxutilConfig::from_toml_str("tls_domain_name = \"synerex\"").unwrap();
+        let tls = TlsOptions::new().with_domain_name(String::from("synerex"));
+        assert_eq!(config.connect.tls.as_ref(), Some(&tls));
+        assert_eq!(config.connect.tls_for("https://sx.example.com:10000"), Some(tls.clone()));
+        assert_eq!(config.connect.tls_for("sx.example.com:10000"), Some(tls));
+
+        let config = SxutilConfig::from_toml_str("tls_domain_name = \"synerex\"\ntls = false").unwrap();
+        assert_eq!(config.connect.tls, None);
+    }
+
+    #[test]
+    fn invalid_connect_keys_are_config_errors() {
+        let err = SxutilConfig::from_toml_str("tls_ca_cert = \"/nonexistent/ca.pem\"");
+        assert!(matches!(err, Err(SxutilError::Config(_))));
+        let err = SxutilConfig::from_toml_str("tls_client_cert = \"/nonexistent/client.pem\"");
+        assert!(matches!(err, Err(SxutilError::Config(_))));
+        assert!(matches!(SxutilConfig::from_yaml_str("connect_timeout: -0.5"), Err(SxutilError::Config(_))));
+    }
+
+    #[test]
+    fn reconnect_policy_keys() {
+        let toml = SxutilConfig::from_toml_str("reconnect_max_wait = 30\nreconnect_max_attempts = 4\n").unwrap();
+        assert_eq!(toml.reconnect_policy.backoff, Backoff::ExponentialJitter { max_wait: Duration::from_secs(30) });
+        assert_eq!(toml.reconnect_policy.max_attempts, Some(4));
+        assert_eq!(SxutilConfig::from_yaml_str("reconnect_max_wait: 30\nreconnect_max_attempts: 4\n").unwrap(), toml);
+        assert!(matches!(SxutilConfig::from_toml_str("reconnect_max_elapsed = -10"), Err(SxutilError::Config(_))));
+    }
+
+    #[test]
+    fn overflow_policy_needs_capacity() {
+        let err = SxutilConfig::from_toml_str("receive_buffer_policy = \"drop_oldest\"");
+        assert!(matches!(err, Err(SxutilError::Config(_))));
+        assert!(matches!(SxutilConfig::from_toml_str("receive_buffer_capacity = 8\nreceive_buffer_policy = \"bogus\""), Err(SxutilError::Config(_))));
+
+        let config = SxutilConfig::from_toml_str("receive_buffer_capacity = 8\nreceive_buffer_policy = \"drop_oldest\"").unwrap();
+        assert_eq!(config.receive_buffer, Some(ReceiveBufferOptions::new(8, OverflowPolicy::DropOldest)));
+        let config = SxutilConfig::from_toml_str("receive_buffer_capacity = 8").unwrap();
+        assert_eq!(config.receive_buffer, Some(ReceiveBufferOptions::new(8, OverflowPolicy::Block)));
+    }
+
+    // the only test touching SXUTIL_* variables (environment is shared by test threads)
+    #[test]
+    fn merge_env_overrides_and_validates() {
+        env::set_var(ENV_MSG_TIME_OUT, "2.5");
+        env::set_var(ENV_RECONNECT_WAIT, "1");
+        env::set_var(ENV_RECONNECT_MAX_WAIT, "30");
+        env::set_var(ENV_RECEIVE_BUFFER_CAPACITY, "16");
+        env::set_var(ENV_RECEIVE_BUFFER_POLICY, "keep_latest_per_sender");
+        env::set_var(ENV_METADATA, "x-tenant=a, x-zone = b");
+        let config = SxutilConfig::from_env();
+        env::set_var(ENV_MSG_TIME_OUT, "-1");
+        let negative = SxutilConfig::from_env();
+        env::set_var(ENV_MSG_TIME_OUT, "soon");
+        let invalid = SxutilConfig::from_env();
+        env::set_var(ENV_MSG_TIME_OUT, "1");
+        env::set_var(ENV_METADATA, "x-tenant");
+        let bad_metadata = SxutilConfig::from_env();
+        for name in [ENV_MSG_TIME_OUT, ENV_RECONNECT_WAIT, ENV_RECONNECT_MAX_WAIT, ENV_RECEIVE_BUFFER_CAPACITY, ENV_RECEIVE_BUFFER_POLICY, ENV_METADATA] {
+            env::remove_var(name);
+        }
+
+        let config = config.unwrap();
+        assert_eq!(config.msg_timeout, Duration::from_millis(2500));
+        assert_eq!(config.reconnect_wait, Duration::from_secs(1));
+        assert_eq!(config.reconnect_policy.backoff, Backoff::ExponentialJitter { max_wait: Duration::from_secs(30) });
+        assert_eq!(config.receive_buffer, Some(ReceiveBufferOptions::new(16, OverflowPolicy::KeepLatestPerSender)));
+        let req = config.connect.interceptor.clone().call(tonic::Request::new(())).unwrap();
+        assert_eq!(req.metadata().get("x-tenant").unwrap(), "a");
+        assert_eq!(req.metadata().get("x-zone").unwrap(), "b");
+        assert!(matches!(negative, Err(SxutilError::Config(_))));
+        assert!(matches!(invalid, Err(SxutilError::Config(_))));
+        assert!(matches!(bad_metadata, Err(SxutilError::Config(_))));
+    }
+}
@@ -0,0 +1,98 @@
+use std::{error::Error, fmt};
+
+// SxutilError is the error type returned by sxutil functions
+#[derive(Debug)]
+pub enum SxutilError {
+    // no connection to synerex server / node server (not connected yet, or reset for reconnection)
+    NotConnected,
+    // server address is empty or can't be used
+    InvalidAddress(String),
+    // failed to establish connection to server
+    Transport(tonic::transport::Error),
+    // server returned gRPC error status
+    Grpc(Box<tonic::Status>),
+    // no response within message timeout
+    Timeout,
+    // node server refused the request
+    NodeServerRejected(String),
+    // synerex server returned `ok == false`
+    ServerRejected(String),
+    // no mbus is opened with this client
+    MbusNotOpen,
+    // operation is not allowed in current state
+    InvalidState(String),
+    // invalid configuration value or file
+    Config(String),
+}
+
+impl SxutilError {
+    // returns underlying gRPC status if this error came from server
+    pub fn status(&self) -> Option<&tonic::Status> {
+        match self {
+            SxutilError::Grpc(status) => Some(status.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SxutilError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SxutilError::NotConnected => write!(f, "sxutil: not connected to server"),
+            SxutilError::InvalidAddress(addr) => write!(f, "sxutil: invalid server address [{}]", addr),
+            SxutilError::Transport(err) => write!(f, "sxutil: connection error: {}", err),
+            SxutilError::Grpc(status) => write!(f, "sxutil: gRPC error: {}", status),
+            SxutilError::Timeout => write!(f, "sxutil: message timeout"),
+            SxutilError::NodeServerRejected(msg) => write!(f, "sxutil: rejected by node server: {}", msg),
+            SxutilError::ServerRejected(msg) => write!(f, "sxutil: rejected by synerex server: {}", msg),
+            SxutilError::MbusNotOpen => write!(f, "sxutil: no mbus opened"),
+            SxutilError::InvalidState(msg) => write!(f, "sxutil: invalid state: {}", msg),
+            SxutilError::Config(msg) => write!(f, "sxutil: config error: {}", msg),
+        }
+    }
+}
+
+impl Error for SxutilError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SxutilError::Transport(err) => Some(err),
+            SxutilError::Grpc(status) => Some(status.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<tonic::Status> for SxutilError {
+    fn from(status: tonic::Status) -> Self {
+        match status.code() {
+            // deadline sent with request was exceeded
+            tonic::Code::DeadlineExceeded => SxutilError::Timeout,
+            tonic::Code::Cancelled if timeout_expired(&status) => SxutilError::Timeout,
+            _ => SxutilError::Grpc(Box::new(status)),
+        }
+    }
+}
+
+// tonic cancels a call on client side when its deadline (grpc-timeout) expires
+fn timeout_expired(status: &tonic::Status) -> bool {
+    let mut source = status.source();
+    while let Some(err) = source {
+        if err.is::<tonic::transport::TimeoutExpired>() {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
+
+impl From<tonic::transport::Error> for SxutilError {
+    fn from(err: tonic::transport::Error) -> Self {
+        SxutilError::Transport(err)
+    }
+}
+
+impl From<tokio::time::error::Elapsed> for SxutilError {
+    fn from(_: tokio::time::error::Elapsed) -> Self {
+        SxutilError::Timeout
+    }
+}
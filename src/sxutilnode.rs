@@ -0,0 +1,254 @@
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::nodeservinfo::query_node_name;
+use crate::{ConnectionSlot, NodeServInfo, SXServiceClient, SXSynerexClient, SxServerOpt, SxutilConfig, SxutilError, KeepAliveHandle, ReceiveMetrics, KeepAliveHandler};
+
+// SxutilNode is a handle for one node server registration.
+// Each SxutilNode has its own snowflake generator, NodeId, keepalive and NodeState,
+// so one process can register with several node servers.
+#[derive(Debug, Clone)]
+pub struct SxutilNode {
+    ni: Arc<RwLock<NodeServInfo>>,
+}
+
+impl Default for SxutilNode {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SxutilNode {
+    pub fn new() -> SxutilNode {
+        Self::from_node_serv_info(Arc::new(RwLock::new(NodeServInfo::new())))
+    }
+
+    pub fn with_config(config: SxutilConfig) -> SxutilNode {
+        let mut ni = NodeServInfo::new();
+        ni.config = config;
+        Self::from_node_serv_info(Arc::new(RwLock::new(ni)))
+    }
+
+    pub fn from_node_serv_info(ni: Arc<RwLock<NodeServInfo>>) -> SxutilNode {
+        SxutilNode { ni }
+    }
+
+    pub fn node_serv_info(&self) -> &Arc<RwLock<NodeServInfo>> {
+        &self.ni
+    }
+
+    // SetConfig replaces runtime configuration of this node
+    // SXServiceClient created after this call uses new config.
+    pub async fn set_config(&self, config: SxutilConfig) {
+        self.ni.write().await.config = config;
+    }
+
+    pub async fn config(&self) -> SxutilConfig {
+        self.ni.read().await.config.clone()
+    }
+
+    // InitNodeNum for initialize NodeNum again
+    pub async fn init_node_num(&self, n: i32) {
+        self.ni.write().await.init_node_num(n);
+        info!("Successfully Initialize node {}", n);
+    }
+
+    // NodeId returns node id given by node server (-1 before registration)
+    pub async fn node_id(&self) -> i32 {
+        self.ni.read().await.nid.node_id
+    }
+
+    // GetNodeName returns node name from node_id
+    pub async fn get_node_name(&self, n: i32) -> Result<String, SxutilError> {
+        let (nodeclt, msg_timeout) = {
+            let ni = self.ni.read().await;
+            (ni.nodeclt.clone().ok_or(SxutilError::NotConnected)?, ni.config.msg_timeout)
+        };
+        query_node_name(nodeclt, n, msg_timeout).await
+    }
+
+    // SetNodeStatus updates KeepAlive info to NodeServer
+    pub async fn set_node_status(&self, status: i32, arg: String) {
+        self.ni.read().await.set_node_status(status, arg).await;
+    }
+
+    pub async fn msg_count_up(&self) {
+        self.ni.write().await.msg_count_up();
+    }
+
+    // RegisterNode is a function to register Node with node server address
+    // keepalive is started automatically (see KeepAliveHandle)
+    pub async fn register_node(&self, nodesrv: String, nm: String, channels: Vec<u32>, serv: Option<&SxServerOpt>) -> Result<KeepAliveHandle, SxutilError> {
+        self.register_node_with_cmd(nodesrv, nm, channels, serv, None).await
+    }
+
+    // RegisterNodeWithCmd is a function to register Node with node server address and KeepAlive Command Callback
+    // keepalive is started automatically (see KeepAliveHandle)
+    pub async fn register_node_with_cmd(&self, nodesrv: String, nm: String, channels: Vec<u32>, serv: Option<&SxServerOpt>, handler: Option<Arc<dyn KeepAliveHandler>>) -> Result<KeepAliveHandle, SxutilError> {
+        NodeServInfo::register_node_with_cmd(&self.ni, nodesrv, nm, channels, serv, handler).await
+    }
+
+    // StartKeepAliveWithCmd starts another keepalive task for this node
+    // (only needed after the one started by registration is cancelled)
+    pub fn start_keep_alive_with_cmd(&self, handler: Option<Arc<dyn KeepAliveHandler>>) -> KeepAliveHandle {
+        NodeServInfo::start_keep_alive_with_cmd(&self.ni, handler)
+    }
+
+    // UpdateChannels replaces channel types of this node and re-registers if already registered
+    pub async fn update_channels(&self, channels: Vec<u32>) -> Result<(), SxutilError> {
+        NodeServInfo::update_node_info(&self.ni, |nif| nif.channel_types = channels).await
+    }
+
+    // UpdateArea replaces area id of this node and re-registers if already registered
+    pub async fn update_area(&self, area_id: String) -> Result<(), SxutilError> {
+        NodeServInfo::update_node_info(&self.ni, |nif| nif.area_id = area_id).await
+    }
+
+    pub async fn un_register_node(&self) -> Result<(), SxutilError> {
+        NodeServInfo::un_register(&self.ni).await
+    }
+
+    // GenerateIntID for generate uniquie ID
+    pub async fn generate_int_id(&self) -> u64 {
+        self.ni.read().await.generate_int_id()
+    }
+
+    // NewSXServiceClient Creates wrapper structre SXServiceClient from SynerexClient
+    pub async fn new_sx_service_client(&self, clt: SXSynerexClient, mtype: u32, arg_json: String) -> SXServiceClient {
+        let sxclient = Arc::new(ConnectionSlot::new(Some(clt)));
+        let (client_id, config) = {
+            let mut ni = self.ni.write().await;
+            ni.add_sx_client(&sxclient);
+            (ni.generate_int_id(), ni.config.clone())
+        };
+        SXServiceClient {
+            client_id,
+            channel_type: mtype,
+            sxclient,
+            arg_json,
+            mbus_ids: Arc::new(RwLock::from(Vec::new())),
+            ni: Some(Arc::clone(&self.ni)),
+            config,
+            receive_metrics: Arc::new(ReceiveMetrics::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::time::Duration;
+    use crate::brokerstate::node_of;
+    use crate::testing::{MockNodeMethod, MockNodeRequest, MockNodeServer};
+    use crate::KeepAliveStatus;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    fn node() -> SxutilNode {
+        SxutilNode::with_config(SxutilConfig::default().with_keepalive_interval(Duration::from_millis(20)))
+    }
+
+    // node ids of keepalives received by server
+    fn keepalive_node_ids(server: &MockNodeServer) -> Vec<i32> {
+        server.requests_of(MockNodeMethod::KeepAlive).into_iter()
+            .filter_map(|req| match req {
+                MockNodeRequest::KeepAlive(update) => Some(update.node_id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn nodes_register_to_their_own_node_servers() {
+        let (server_a, server_b) = (MockNodeServer::start().await.unwrap(), MockNodeServer::start().await.unwrap());
+        let other = node();
+        let other_keepalive = other.register_node(server_b.address(), String::from("Other"), vec![1], None).await.unwrap();
+        let (node_a, node_b) = (node(), node());
+        let keepalive_a = node_a.register_node(server_a.address(), String::from("NodeA"), vec![1], None).await.unwrap();
+        let keepalive_b = node_b.register_node(server_b.address(), String::from("NodeB"), vec![2], None).await.unwrap();
+
+        let (id_a, id_b) = (node_a.node_id().await, node_b.node_id().await);
+        assert_ne!(id_a, id_b);
+        assert_eq!(server_a.node_by_name("NodeA").unwrap().node_id, id_a);
+        assert_eq!(server_b.node_by_name("NodeB").unwrap().node_id, id_b);
+        assert!(server_a.node_by_name("NodeB").is_none());
+        assert!(server_b.node_by_name("NodeA").is_none());
+        // ids are generated with node id of each registration
+        assert_eq!(node_of(node_a.generate_int_id().await), id_a);
+        assert_eq!(node_of(node_b.generate_int_id().await), id_b);
+
+        server_a.wait_for_keepalives(id_a, 2, WAIT).await;
+        server_b.wait_for_keepalives(id_b, 2, WAIT).await;
+        assert!(keepalive_node_ids(&server_a).iter().all(|&id| id == id_a));
+        let id_other = other.node_id().await;
+        assert!(keepalive_node_ids(&server_b).iter().all(|&id| id == id_b || id == id_other));
+
+        node_a.un_register_node().await.unwrap();
+        assert!(server_a.nodes().is_empty());
+        assert!(server_b.node(id_b).is_some());
+        assert_eq!(keepalive_a.join().await.ok(), Some(()));
+        assert_eq!(keepalive_a.status(), KeepAliveStatus::Unregistered);
+        let keepalives = server_b.node(id_b).unwrap().keepalives;
+        server_b.wait_for_keepalives(id_b, keepalives + 2, WAIT).await;
+        assert_eq!(keepalive_b.status(), KeepAliveStatus::Running);
+
+        keepalive_b.stop().await.unwrap();
+        other_keepalive.stop().await.unwrap();
+        assert!(server_b.nodes().is_empty());
+    }
+
+    #[tokio::test]
+    async fn stalled_registration_times_out_without_holding_node_lock() {
+        let mock = MockNodeServer::start().await.unwrap();
+        let node = SxutilNode::with_config(SxutilConfig::default().with_msg_timeout(Duration::from_millis(300)));
+        mock.set_latency(MockNodeMethod::RegisterNode, Duration::from_secs(60));
+        let registration = tokio::spawn({
+            let node = node.clone();
+            let address = mock.address();
+            async move { node.register_node(address, String::from("Stalled"), vec![1], None).await.map(|_| ()) }
+        });
+        mock.wait_for_requests(MockNodeMethod::RegisterNode, 1, WAIT).await;
+        // node can be used while node server stalls
+        tokio::time::timeout(Duration::from_millis(100), node.config()).await.unwrap();
+
+        let result = tokio::time::timeout(WAIT, registration).await.unwrap().unwrap();
+        assert!(matches!(result, Err(SxutilError::Timeout)), "{:?}", result);
+        assert!(mock.nodes().is_empty());
+    }
+
+    #[tokio::test]
+    async fn update_calls_node_server_without_holding_node_lock() {
+        let mock = MockNodeServer::start().await.unwrap();
+        let node = SxutilNode::with_config(SxutilConfig::default()
+            .with_keepalive_interval(Duration::from_millis(20))
+            .with_msg_timeout(Duration::from_millis(300)));
+        let keepalive = node.register_node(mock.address(), String::from("Update"), vec![1], None).await.unwrap();
+        let node_id = node.node_id().await;
+
+        node.update_channels(vec![1, 2]).await.unwrap();
+        assert_eq!(mock.node(node_id).unwrap().info.channel_types, vec![1, 2]);
+
+        mock.set_latency(MockNodeMethod::RegisterNode, Duration::from_secs(60));
+        let sent = mock.requests_of(MockNodeMethod::RegisterNode).len();
+        let update = tokio::spawn({
+            let node = node.clone();
+            async move { node.update_area(String::from("Stalled")).await }
+        });
+        mock.wait_for_requests(MockNodeMethod::RegisterNode, sent + 1, WAIT).await;
+        assert_eq!(tokio::time::timeout(Duration::from_millis(100), node.node_id()).await.unwrap(), node_id);
+        let result = tokio::time::timeout(WAIT, update).await.unwrap().unwrap();
+        assert!(matches!(result, Err(SxutilError::Timeout)), "{:?}", result);
+
+        mock.clear_faults();
+        mock.set_latency(MockNodeMethod::UnRegisterNode, Duration::from_secs(60));
+        let unregister = tokio::spawn({
+            let node = node.clone();
+            async move { node.un_register_node().await }
+        });
+        mock.wait_for_requests(MockNodeMethod::UnRegisterNode, 1, WAIT).await;
+        assert_eq!(tokio::time::timeout(Duration::from_millis(100), node.node_id()).await.unwrap(), node_id);
+        let result = tokio::time::timeout(WAIT, unregister).await.unwrap().unwrap();
+        assert!(matches!(result, Err(SxutilError::Timeout)), "{:?}", result);
+        assert_eq!(tokio::time::timeout(WAIT, keepalive.join()).await.unwrap().ok(), Some(()));
+    }
+}
@@ -0,0 +1,497 @@
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+
+use tokio::net::TcpListener;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+use synerex_api::api::{self, synerex_server::{Synerex, SynerexServer}};
+use synerex_nodeapi::nodeapi;
+
+use crate::brokerstate::{node_of, BrokerState};
+use crate::serving::{confirmed, ok, rejected, tcp_incoming, SubscriptionStream};
+use crate::{BrokerOptions, KeepAliveHandle, KeepAliveHandler, SxServerOpt, SxutilError, SxutilNode};
+
+// BrokerStats is a snapshot of SynerexBroker
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BrokerStats {
+    pub demand_subscribers: usize,
+    pub supply_subscribers: usize,
+    pub mbus_subscribers: usize,
+    pub gateways: usize,
+    // open mbuses
+    pub mbuses: usize,
+    // messages put to subscription streams
+    pub delivered: u64,
+    // messages dropped for full subscription streams
+    pub dropped: u64,
+}
+
+struct BrokerShared {
+    state: Mutex<BrokerState>,
+    node: SxutilNode,
+    opts: BrokerOptions,
+    // messages not yet added to msg_count of node
+    msg_count: AtomicU64,
+    reporting: AtomicBool,
+}
+
+impl BrokerShared {
+    fn lock(&self) -> MutexGuard<'_, BrokerState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// SynerexBroker is a Synerex server implemented on sxutil types (replacement of Go version's synerex-server).
+// Demands / supplies are delivered to subscribers of the same channel_type (also to the sender),
+// proposals to the sender of the target when it is known, selections to the sender of the target
+// with a private mbus between both. Ids of mbuses are generated by its SxutilNode,
+// which can be registered to node server as SERVER node.
+#[derive(Clone)]
+pub struct SynerexBroker {
+    shared: Arc<BrokerShared>,
+}
+
+impl Default for SynerexBroker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SynerexBroker {
+    pub fn new() -> Self {
+        Self::with_options(BrokerOptions::default())
+    }
+
+    pub fn with_options(opts: BrokerOptions) -> Self {
+        Self::with_node(SxutilNode::new(), opts)
+    }
+
+    // WithNode uses node (registered with register_node) for ids and node server
+    pub fn with_node(node: SxutilNode, opts: BrokerOptions) -> Self {
+        let shared = BrokerShared {
+            state: Mutex::new(BrokerState::new(&opts)),
+            node,
+            opts,
+            msg_count: AtomicU64::new(0),
+            reporting: AtomicBool::new(false),
+        };
+        SynerexBroker { shared: Arc::new(shared) }
+    }
+
+    pub fn node(&self) -> &SxutilNode {
+        &self.shared.node
+    }
+
+    pub fn options(&self) -> &BrokerOptions {
+        &self.shared.opts
+    }
+
+    // Service returns Synerex service to be added to tonic Server with other services
+    pub fn service(&self) -> SynerexServer<SynerexBroker> {
+        SynerexServer::new(self.clone())
+    }
+
+    // Serve serves on addr until error
+    pub async fn serve(&self, addr: SocketAddr) -> Result<(), SxutilError> {
+        self.serve_with_shutdown(addr, futures::future::pending()).await
+    }
+
+    // ServeWithShutdown serves on addr until signal, open streams are ended on shutdown
+    pub async fn serve_with_shutdown<F: Future<Output = ()> + Send>(&self, addr: SocketAddr, signal: F) -> Result<(), SxutilError> {
+        info!("SynerexBroker: serving on {}", addr);
+        Server::builder()
+            .add_service(self.service())
+            .serve_with_shutdown(addr, self.closing(signal))
+            .await?;
+        Ok(())
+    }
+
+    // ServeWithListener serves on bound listener (port 0 for tests) until signal
+    pub async fn serve_with_listener<F: Future<Output = ()> + Send>(&self, listener: TcpListener, signal: F) -> Result<(), SxutilError> {
+        info!("SynerexBroker: serving on {:?}", listener.local_addr());
+        Server::builder()
+            .add_service(self.service())
+            .serve_with_incoming_shutdown(tcp_incoming(listener), self.closing(signal))
+            .await?;
+        Ok(())
+    }
+
+    // streams are ended before graceful shutdown (which waits for them)
+    fn closing<F: Future<Output = ()>>(&self, signal: F) -> impl Future<Output = ()> {
+        let shared = Arc::clone(&self.shared);
+        async move {
+            signal.await;
+            let closed = shared.lock().close_clients(|_| true);
+            info!("SynerexBroker: shutdown, closed {} streams", closed);
+        }
+    }
+
+    // RegisterNode registers this broker to node server as SERVER node.
+    // server_info is the address given to providers ("host:port").
+    pub async fn register_node(&self, nodesrv: String, nm: String, server_info: String) -> Result<KeepAliveHandle, SxutilError> {
+        let serv = SxServerOpt {
+            node_type: nodeapi::NodeType::Server,
+            server_info,
+            cluster_id: 0,
+            area_id: String::from("Default"),
+            gw_info: String::new(),
+        };
+        self.register_node_with_opt(nodesrv, nm, &serv).await
+    }
+
+    // RegisterNodeWithOpt registers with cluster / area of serv (node_type must be SERVER).
+    // channels of disconnected providers (PROV_DISCONN) are closed by keepalive.
+    pub async fn register_node_with_opt(&self, nodesrv: String, nm: String, serv: &SxServerOpt) -> Result<KeepAliveHandle, SxutilError> {
+        if serv.node_type != nodeapi::NodeType::Server {
+            return Err(SxutilError::InvalidState(format!("broker should be SERVER node, not {:?}", serv.node_type)));
+        }
+        let handler: Arc<dyn KeepAliveHandler> = Arc::new(BrokerKeepAlive { shared: Arc::downgrade(&self.shared) });
+        let keepalive = self.shared.node.register_node_with_cmd(nodesrv, nm, Vec::new(), Some(serv), Some(handler)).await?;
+        let node_id = self.shared.node.node_id().await;
+        self.shared.lock().synerex_id = Some(node_id as u64);
+        info!("SynerexBroker: registered as node {} [{}]", node_id, serv.server_info);
+        self.start_report();
+        Ok(keepalive)
+    }
+
+    // message count is added to node periodically (not to lock node on each message)
+    fn start_report(&self) {
+        if self.shared.reporting.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let shared = Arc::downgrade(&self.shared);
+        let interval = self.shared.opts.report_interval;
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                let Some(shared) = shared.upgrade() else {
+                    break;
+                };
+                let count = shared.msg_count.swap(0, Ordering::Relaxed);
+                if count > 0 {
+                    shared.node.node_serv_info().write().await.msg_count += count;
+                }
+            }
+        });
+    }
+
+    // CloseAllChannels ends all streams of client, returns the number of ended streams
+    pub fn close_all_channels(&self, client_id: u64) -> usize {
+        self.shared.lock().close_clients(|cid| cid == client_id)
+    }
+
+    // CloseProvider ends all streams of clients whose id is generated by node (provider node id)
+    pub fn close_provider(&self, node_id: i32) -> usize {
+        self.shared.lock().close_clients(|cid| node_of(cid) == node_id)
+    }
+
+    pub fn stats(&self) -> BrokerStats {
+        let mut state = self.shared.lock();
+        let (demand_subscribers, supply_subscribers, mbus_subscribers, gateways, mbuses) = state.counts();
+        BrokerStats {
+            demand_subscribers,
+            supply_subscribers,
+            mbus_subscribers,
+            gateways,
+            mbuses,
+            delivered: state.delivered,
+            dropped: state.dropped,
+        }
+    }
+
+    fn count_msg(&self) {
+        self.shared.msg_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    async fn generate_id(&self) -> u64 {
+        self.shared.node.generate_int_id().await
+    }
+}
+
+impl std::fmt::Debug for SynerexBroker {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SynerexBroker")
+            .field("stats", &self.stats())
+            .field("options", &self.shared.opts)
+            .finish()
+    }
+}
+
+// closes channels of provider on PROV_DISCONN from node server
+struct BrokerKeepAlive {
+    shared: Weak<BrokerShared>,
+}
+
+#[tonic::async_trait]
+impl KeepAliveHandler for BrokerKeepAlive {
+    async fn on_provider_disconnect(&self, provider_id: Option<i32>, err: String) {
+        match (provider_id, self.shared.upgrade()) {
+            (Some(node_id), Some(shared)) => {
+                let closed = shared.lock().close_clients(|cid| node_of(cid) == node_id);
+                info!("SynerexBroker: provider {} disconnected, closed {} streams", node_id, closed);
+            },
+            (None, _) => warn!("SynerexBroker: unknown provider of PROV_DISCONN [{}]", err),
+            (_, None) => {},
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Synerex for SynerexBroker {
+    type SubscribeDemandStream = SubscriptionStream<api::Demand>;
+    type SubscribeSupplyStream = SubscriptionStream<api::Supply>;
+    type SubscribeMbusStream = SubscriptionStream<api::MbusMsg>;
+    type SubscribeGatewayStream = SubscriptionStream<api::GatewayMsg>;
+
+    async fn notify_demand(&self, request: Request<api::Demand>) -> Result<Response<api::Response>, Status> {
+        self.count_msg();
+        self.shared.lock().send_demand(request.get_ref(), false);
+        Ok(ok())
+    }
+
+    async fn notify_supply(&self, request: Request<api::Supply>) -> Result<Response<api::Response>, Status> {
+        self.count_msg();
+        self.shared.lock().send_supply(request.get_ref(), false);
+        Ok(ok())
+    }
+
+    async fn propose_demand(&self, request: Request<api::Demand>) -> Result<Response<api::Response>, Status> {
+        self.count_msg();
+        self.shared.lock().send_demand(request.get_ref(), true);
+        Ok(ok())
+    }
+
+    async fn propose_supply(&self, request: Request<api::Supply>) -> Result<Response<api::Response>, Status> {
+        self.count_msg();
+        self.shared.lock().send_supply(request.get_ref(), true);
+        Ok(ok())
+    }
+
+    async fn select_supply(&self, request: Request<api::Target>) -> Result<Response<api::ConfirmResponse>, Status> {
+        self.count_msg();
+        let tg = request.into_inner();
+        let mbus_id = self.generate_id().await;
+        let dm = api::Demand { id: tg.id, sender_id: tg.sender_id, target_id: tg.target_id, channel_type: tg.channel_type, ..Default::default() };
+        let selected = self.shared.lock().select_supply(dm, mbus_id);
+        selected.map(confirmed).ok_or_else(|| Status::not_found(format!("no such supply {}", tg.target_id)))
+    }
+
+    async fn select_modified_supply(&self, request: Request<api::Supply>) -> Result<Response<api::ConfirmResponse>, Status> {
+        self.count_msg();
+        let sp = request.into_inner();
+        let mbus_id = self.generate_id().await;
+        let dm = api::Demand {
+            id: sp.id,
+            sender_id: sp.sender_id,
+            target_id: sp.target_id,
+            channel_type: sp.channel_type,
+            demand_name: sp.supply_name,
+            ts: sp.ts,
+            arg_json: sp.arg_json,
+            mbus_id,
+            cdata: sp.cdata,
+        };
+        let selected = self.shared.lock().select_supply(dm, mbus_id);
+        selected.map(confirmed).ok_or_else(|| Status::not_found(format!("no such supply {}", sp.target_id)))
+    }
+
+    async fn select_demand(&self, request: Request<api::Target>) -> Result<Response<api::ConfirmResponse>, Status> {
+        self.count_msg();
+        let tg = request.into_inner();
+        let mbus_id = self.generate_id().await;
+        let sp = api::Supply { id: tg.id, sender_id: tg.sender_id, target_id: tg.target_id, channel_type: tg.channel_type, ..Default::default() };
+        let selected = self.shared.lock().select_demand(sp, mbus_id);
+        selected.map(confirmed).ok_or_else(|| Status::not_found(format!("no such demand {}", tg.target_id)))
+    }
+
+    async fn confirm(&self, request: Request<api::Target>) -> Result<Response<api::Response>, Status> {
+        let tg = request.into_inner();
+        if !self.shared.lock().confirm(&tg) {
+            debug!("SynerexBroker: confirm without open mbus {:?}", tg);
+        }
+        Ok(ok())
+    }
+
+    async fn subscribe_demand(&self, request: Request<api::Channel>) -> Result<Response<Self::SubscribeDemandStream>, Status> {
+        let ch = request.into_inner();
+        debug!("SynerexBroker: subscribe demand {} of {}", ch.channel_type, ch.client_id);
+        Ok(Response::new(self.shared.lock().subscribe_demand(&ch)))
+    }
+
+    async fn subscribe_supply(&self, request: Request<api::Channel>) -> Result<Response<Self::SubscribeSupplyStream>, Status> {
+        let ch = request.into_inner();
+        debug!("SynerexBroker: subscribe supply {} of {}", ch.channel_type, ch.client_id);
+        Ok(Response::new(self.shared.lock().subscribe_supply(&ch)))
+    }
+
+    async fn create_mbus(&self, request: Request<api::MbusOpt>) -> Result<Response<api::Mbus>, Status> {
+        let opt = request.into_inner();
+        let mbus_id = self.generate_id().await;
+        self.shared.lock().create_mbus(mbus_id, opt);
+        Ok(Response::new(api::Mbus { client_id: 0, mbus_id, arg_json: String::new() }))
+    }
+
+    async fn close_mbus(&self, request: Request<api::Mbus>) -> Result<Response<api::Response>, Status> {
+        let mb = request.into_inner();
+        if self.shared.lock().close_mbus(mb.mbus_id) {
+            Ok(ok())
+        } else {
+            Ok(rejected(format!("no such mbus {}", mb.mbus_id)))
+        }
+    }
+
+    async fn subscribe_mbus(&self, request: Request<api::Mbus>) -> Result<Response<Self::SubscribeMbusStream>, Status> {
+        self.shared.lock().subscribe_mbus(request.get_ref()).map(Response::new).map_err(|status| *status)
+    }
+
+    async fn send_mbus_msg(&self, request: Request<api::MbusMsg>) -> Result<Response<api::Response>, Status> {
+        self.count_msg();
+        match self.shared.lock().send_mbus_msg(request.get_ref()) {
+            Ok(()) => Ok(ok()),
+            Err(err) => Ok(rejected(err)),
+        }
+    }
+
+    async fn get_mbus_state(&self, request: Request<api::Mbus>) -> Result<Response<api::MbusState>, Status> {
+        Ok(Response::new(self.shared.lock().mbus_state(request.get_ref().mbus_id)))
+    }
+
+    async fn subscribe_gateway(&self, request: Request<api::GatewayInfo>) -> Result<Response<Self::SubscribeGatewayStream>, Status> {
+        let gi = request.into_inner();
+        info!("SynerexBroker: gateway {} subscribed for {:?}", gi.client_id, gi.channels);
+        Ok(Response::new(self.shared.lock().subscribe_gateway(gi)))
+    }
+
+    async fn forward_to_gateway(&self, request: Request<api::GatewayMsg>) -> Result<Response<api::Response>, Status> {
+        self.count_msg();
+        self.shared.lock().deliver_from_gateway(request.into_inner());
+        Ok(ok())
+    }
+
+    async fn close_demand_channel(&self, request: Request<api::Channel>) -> Result<Response<api::Response>, Status> {
+        self.shared.lock().close_demand_channel(request.get_ref());
+        Ok(ok())
+    }
+
+    async fn close_supply_channel(&self, request: Request<api::Channel>) -> Result<Response<api::Response>, Status> {
+        self.shared.lock().close_supply_channel(request.get_ref());
+        Ok(ok())
+    }
+
+    async fn close_all_channels(&self, request: Request<api::ProviderId>) -> Result<Response<api::Response>, Status> {
+        let client_id = request.get_ref().client_id;
+        self.shared.lock().close_clients(|cid| cid == client_id);
+        Ok(ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::time::Duration;
+    use futures::{Stream, StreamExt};
+    use tokio::sync::mpsc;
+    use tokio_util::sync::CancellationToken;
+    use crate::{grpc_connect_server, DemandOpts, SupplyOpts};
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    async fn start_broker(broker: &SynerexBroker, shutdown: &CancellationToken) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (broker, shutdown) = (broker.clone(), shutdown.clone());
+        tokio::spawn(async move { broker.serve_with_listener(listener, shutdown.cancelled_owned()).await });
+        addr.to_string()
+    }
+
+    async fn wait_for<F: Fn(BrokerStats) -> bool>(broker: &SynerexBroker, cond: F) {
+        tokio::time::timeout(WAIT, async {
+            while !cond(broker.stats()) {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        }).await.unwrap_or_else(|_| panic!("SynerexBroker: {:?}", broker.stats()));
+    }
+
+    // stream is read on a task (subscription is opened before messages are sent)
+    fn forward<T: Send + 'static, S>(stream: S) -> mpsc::UnboundedReceiver<T>
+    where
+        S: Stream<Item = Result<T, SxutilError>> + Send + 'static,
+    {
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut stream = Box::pin(stream);
+            while let Some(Ok(msg)) = stream.next().await {
+                if tx.send(msg).is_err() {
+                    break;
+                }
+            }
+        });
+        rx
+    }
+
+    async fn next<T>(rx: &mut mpsc::UnboundedReceiver<T>) -> T {
+        tokio::time::timeout(WAIT, rx.recv()).await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn demand_proposal_selection_and_mbus() {
+        let (broker, shutdown) = (SynerexBroker::new(), CancellationToken::new());
+        let addr = start_broker(&broker, &shutdown).await;
+        // clients of one node (separate nodes would generate the same ids)
+        let node = SxutilNode::new();
+        let mut clients = Vec::new();
+        for _ in 0..2 {
+            let clt = grpc_connect_server(addr.clone()).await.unwrap();
+            clients.push(Arc::new(node.new_sx_service_client(clt, 1, String::new()).await));
+        }
+        let (demander, supplier) = (clients.remove(0), clients.remove(0));
+        let mut supplies = forward(Arc::clone(&demander).into_supply_stream());
+        let mut demands = forward(Arc::clone(&supplier).into_demand_stream());
+        wait_for(&broker, |stats| stats.demand_subscribers == 1 && stats.supply_subscribers == 1).await;
+
+        let dmo = DemandOpts { id: 0, target: 0, name: String::from("ride"), json: String::new(), cdata: api::Content::default() };
+        let demand_id = demander.notify_demand(dmo).await.unwrap();
+        let dm = next(&mut demands).await;
+        assert_eq!((dm.id, dm.sender_id), (demand_id, demander.client_id));
+
+        let spo = SupplyOpts { id: 0, target: demand_id, name: String::from("taxi"), json: String::new(), cdata: api::Content::default() };
+        let supply_id = supplier.propose_supply(&spo).await.unwrap();
+        let sp = next(&mut supplies).await;
+        assert_eq!((sp.id, sp.target_id), (supply_id, demand_id));
+
+        let mbus_id = demander.select_supply(sp).await.unwrap();
+        let selection = next(&mut demands).await;
+        assert_eq!((selection.target_id, selection.mbus_id), (supply_id, mbus_id));
+        supplier.confirm(mbus_id, supply_id).await.unwrap();
+
+        let mut msgs = forward(Arc::clone(&supplier).into_mbus_stream(mbus_id));
+        wait_for(&broker, |stats| stats.mbus_subscribers == 1).await;
+        let msg = api::MbusMsg { msg_info: String::from("pickup"), ..Default::default() };
+        demander.send_mbus_msg(mbus_id, msg).await.unwrap();
+        let msg = next(&mut msgs).await;
+        assert_eq!((msg.sender_id, msg.msg_info.as_str()), (demander.client_id, "pickup"));
+
+        shutdown.cancel();
+        // streams are ended on shutdown
+        wait_for(&broker, |stats| stats.demand_subscribers + stats.supply_subscribers + stats.mbus_subscribers == 0).await;
+    }
+
+    #[tokio::test]
+    async fn provider_disconnect_closes_channels_of_provider() {
+        let broker = SynerexBroker::new();
+        let handler = BrokerKeepAlive { shared: Arc::downgrade(&broker.shared) };
+        let _streams = {
+            let mut state = broker.shared.lock();
+            [12 << 12 | 1, 12 << 12 | 2, 13 << 12 | 1].map(|client_id| {
+                state.subscribe_demand(&api::Channel { client_id, channel_type: 1, ..Default::default() })
+            })
+        };
+        handler.on_provider_disconnect(None, String::from("unknown")).await;
+        assert_eq!(broker.stats().demand_subscribers, 3);
+        handler.on_provider_disconnect(Some(12), String::from("12")).await;
+        assert_eq!(broker.stats().demand_subscribers, 1);
+    }
+}
@@ -0,0 +1,7 @@
+// in-process servers to test providers without Go Synerex servers
+
+mod mockserve;
+mod mocknodeserver;
+pub use mocknodeserver::{MockNode, MockNodeMethod, MockNodeRequest, MockNodeServer};
+mod mocksynerexserver;
+pub use mocksynerexserver::{MockMethod, MockRequest, MockSynerexServer};
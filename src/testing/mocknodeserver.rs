@@ -0,0 +1,634 @@
+use core::time::Duration;
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use tokio::net::TcpListener;
+use tokio::sync::Notify;
+use tonic::transport::Server;
+use tonic::metadata::MetadataMap;
+use tonic::{Request, Response, Status};
+
+use synerex_nodeapi::nodeapi::{self, node_server::{Node, NodeServer}};
+
+use crate::serving::tcp_incoming;
+use super::mockserve::{inject, Faults, Running};
+
+// node ids given by node server (servers and gateways: 0-9, providers: 10-1023)
+const SERVER_NODE_IDS: (i32, i32) = (0, 9);
+const PROVIDER_NODE_IDS: (i32, i32) = (10, 1023);
+// keepalive_duration given to nodes (seconds)
+const KEEPALIVE_DURATION: i32 = 10;
+
+// MockNodeMethod is a method of Node service
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MockNodeMethod {
+    RegisterNode,
+    QueryNode,
+    KeepAlive,
+    UnRegisterNode,
+}
+
+// MockNodeRequest is a request received by MockNodeServer
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockNodeRequest {
+    RegisterNode(nodeapi::NodeInfo),
+    QueryNode(nodeapi::NodeId),
+    KeepAlive(nodeapi::NodeUpdate),
+    UnRegisterNode(nodeapi::NodeId),
+}
+
+impl MockNodeRequest {
+    pub fn method(&self) -> MockNodeMethod {
+        match self {
+            MockNodeRequest::RegisterNode(_) => MockNodeMethod::RegisterNode,
+            MockNodeRequest::QueryNode(_) => MockNodeMethod::QueryNode,
+            MockNodeRequest::KeepAlive(_) => MockNodeMethod::KeepAlive,
+            MockNodeRequest::UnRegisterNode(_) => MockNodeMethod::UnRegisterNode,
+        }
+    }
+}
+
+// MockNode is a node registered to MockNodeServer
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockNode {
+    pub node_id: i32,
+    pub secret: u64,
+    pub info: nodeapi::NodeInfo,
+    // keepalives received since registration
+    pub keepalives: u32,
+    pub last_update: Option<nodeapi::NodeUpdate>,
+}
+
+// command returned with keepalive response
+#[derive(Debug, Clone)]
+struct ScriptedCommand {
+    command: nodeapi::KeepAliveCommand,
+    err: String,
+}
+
+struct NodeState {
+    requests: Vec<MockNodeRequest>,
+    // metadata (headers) of requests
+    metadata: Vec<(MockNodeMethod, MetadataMap)>,
+    faults: Faults<MockNodeMethod>,
+    nodes: BTreeMap<i32, MockNode>,
+    // scripted commands for each node (None: for any node)
+    commands: BTreeMap<Option<i32>, VecDeque<ScriptedCommand>>,
+    // server info given to providers (None: server_info of a registered SERVER node)
+    server_info: Option<String>,
+    keepalive_duration: i32,
+    // restarts of node server (registrations are lost on each restart)
+    restarts: u32,
+}
+
+impl NodeState {
+    fn assign_node_id(&self, info: &nodeapi::NodeInfo) -> Option<i32> {
+        let (first, last) = match info.node_type() {
+            nodeapi::NodeType::Provider => PROVIDER_NODE_IDS,
+            nodeapi::NodeType::Server | nodeapi::NodeType::Gateway => SERVER_NODE_IDS,
+        };
+        let reusable = match self.nodes.get(&info.with_node_id) {
+            Some(node) => node.info.node_name == info.node_name, // re-registration of the same node
+            None => true,
+        };
+        if info.with_node_id >= first && info.with_node_id <= last && reusable {
+            return Some(info.with_node_id);
+        }
+        (first..=last).find(|node_id| !self.nodes.contains_key(node_id))
+    }
+
+    fn server_info(&self) -> String {
+        match &self.server_info {
+            Some(server_info) => server_info.clone(),
+            None => self.nodes.values()
+                .find(|node| node.info.node_type() == nodeapi::NodeType::Server)
+                .map(|node| node.info.server_info.clone())
+                .unwrap_or_default(),
+        }
+    }
+
+    fn registered(&self, node_id: i32, secret: u64) -> bool {
+        self.nodes.get(&node_id).is_some_and(|node| node.secret == secret)
+    }
+
+    fn next_command(&mut self, node_id: i32) -> Option<ScriptedCommand> {
+        for key in [Some(node_id), None] {
+            if let Some(command) = self.commands.get_mut(&key).and_then(VecDeque::pop_front) {
+                return Some(command);
+            }
+        }
+        None
+    }
+}
+
+struct Shared {
+    state: Mutex<NodeState>,
+    // notified on every request and registration change
+    received: Notify,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, NodeState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// Node service of MockNodeServer
+struct MockNodeService {
+    shared: Arc<Shared>,
+}
+
+impl MockNodeService {
+    // records request, then applies injected latency and failure
+    async fn intercept(&self, metadata: MetadataMap, request: MockNodeRequest) -> Result<(), Status> {
+        let injected = {
+            let mut state = self.shared.lock();
+            let injected = state.faults.next(request.method());
+            state.metadata.push((request.method(), metadata));
+            state.requests.push(request);
+            injected
+        };
+        self.shared.received.notify_waiters();
+        inject(injected).await
+    }
+}
+
+fn node_response(ok: bool, command: nodeapi::KeepAliveCommand, err: &str) -> Response<nodeapi::Response> {
+    Response::new(nodeapi::Response { ok, command: command as i32, err: err.to_string() })
+}
+
+#[tonic::async_trait]
+impl Node for MockNodeService {
+    async fn register_node(&self, request: Request<nodeapi::NodeInfo>) -> Result<Response<nodeapi::NodeId>, Status> {
+        let (metadata, _, info) = request.into_parts();
+        self.intercept(metadata, MockNodeRequest::RegisterNode(info.clone())).await?;
+        let mut state = self.shared.lock();
+        let node_id = state.assign_node_id(&info)
+            .ok_or_else(|| Status::resource_exhausted("MockNodeServer: no node id available"))?;
+        let secret = rand::random::<u64>() | 1; // secret 0 means unregistered
+        state.nodes.insert(node_id, MockNode { node_id, secret, info, keepalives: 0, last_update: None });
+        self.shared.received.notify_waiters();
+        Ok(Response::new(nodeapi::NodeId {
+            node_id,
+            secret,
+            server_info: state.server_info(),
+            keepalive_duration: state.keepalive_duration,
+        }))
+    }
+
+    async fn query_node(&self, request: Request<nodeapi::NodeId>) -> Result<Response<nodeapi::NodeInfo>, Status> {
+        let (metadata, _, nid) = request.into_parts();
+        self.intercept(metadata, MockNodeRequest::QueryNode(nid.clone())).await?;
+        match self.shared.lock().nodes.get(&nid.node_id) {
+            Some(node) => Ok(Response::new(node.info.clone())),
+            None => Err(Status::not_found(format!("MockNodeServer: no node {}", nid.node_id))),
+        }
+    }
+
+    // unknown node (e.g. after restart) is asked to RECONNECT like Go nodeserv
+    async fn keep_alive(&self, request: Request<nodeapi::NodeUpdate>) -> Result<Response<nodeapi::Response>, Status> {
+        let (metadata, _, nupd) = request.into_parts();
+        self.intercept(metadata, MockNodeRequest::KeepAlive(nupd.clone())).await?;
+        let mut state = self.shared.lock();
+        if !state.registered(nupd.node_id, nupd.secret) {
+            return Ok(node_response(false, nodeapi::KeepAliveCommand::Reconnect, "MockNodeServer: unknown node"));
+        }
+        let node_id = nupd.node_id;
+        if let Some(node) = state.nodes.get_mut(&node_id) {
+            node.keepalives += 1;
+            node.last_update = Some(nupd);
+        }
+        self.shared.received.notify_waiters();
+        Ok(match state.next_command(node_id) {
+            Some(scripted) => node_response(true, scripted.command, &scripted.err),
+            None => node_response(true, nodeapi::KeepAliveCommand::None, ""),
+        })
+    }
+
+    async fn un_register_node(&self, request: Request<nodeapi::NodeId>) -> Result<Response<nodeapi::Response>, Status> {
+        let (metadata, _, nid) = request.into_parts();
+        self.intercept(metadata, MockNodeRequest::UnRegisterNode(nid.clone())).await?;
+        let mut state = self.shared.lock();
+        if !state.registered(nid.node_id, nid.secret) {
+            return Ok(node_response(false, nodeapi::KeepAliveCommand::None, "MockNodeServer: unknown node"));
+        }
+        state.nodes.remove(&nid.node_id);
+        self.shared.received.notify_waiters();
+        Ok(node_response(true, nodeapi::KeepAliveCommand::None, ""))
+    }
+}
+
+// MockNodeServer is an in-process node server for tests of node registration and keepalive.
+// It assigns node ids and secrets, counts keepalives and returns scripted keepalive commands
+// (RECONNECT / SERVER_CHANGE / PROV_DISCONN) in order. restart() forgets registrations like a
+// restarted node server, so keepalives of known nodes get RECONNECT.
+pub struct MockNodeServer {
+    shared: Arc<Shared>,
+    addr: SocketAddr,
+    running: Mutex<Option<Running>>,
+}
+
+impl MockNodeServer {
+    // Start binds a free local port
+    pub async fn start() -> io::Result<Self> {
+        Self::start_on(SocketAddr::from(([127, 0, 0, 1], 0))).await
+    }
+
+    // StartOn binds addr (port 0 picks a free port)
+    pub async fn start_on(addr: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let state = NodeState {
+            requests: Vec::new(),
+            metadata: Vec::new(),
+            faults: Faults::new(),
+            nodes: BTreeMap::new(),
+            commands: BTreeMap::new(),
+            server_info: None,
+            keepalive_duration: KEEPALIVE_DURATION,
+            restarts: 0,
+        };
+        let server = MockNodeServer {
+            shared: Arc::new(Shared { state: Mutex::new(state), received: Notify::new() }),
+            addr: listener.local_addr()?,
+            running: Mutex::new(None),
+        };
+        server.serve(listener);
+        Ok(server)
+    }
+
+    fn serve(&self, listener: TcpListener) {
+        let service = NodeServer::new(MockNodeService { shared: Arc::clone(&self.shared) });
+        let running = Running::spawn("MockNodeServer", |signal| {
+            Server::builder().add_service(service).serve_with_incoming_shutdown(tcp_incoming(listener), signal)
+        });
+        *self.running.lock().unwrap_or_else(PoisonError::into_inner) = Some(running);
+    }
+
+    // Address to register ("127.0.0.1:port")
+    pub fn address(&self) -> String {
+        self.addr.to_string()
+    }
+
+    // SetServerInfo sets server info given to nodes on registration (e.g. MockSynerexServer address)
+    pub fn set_server_info(&self, server_info: String) {
+        self.shared.lock().server_info = Some(server_info);
+    }
+
+    // SetKeepaliveDuration sets keepalive_duration (seconds) given on registration
+    pub fn set_keepalive_duration(&self, seconds: i32) {
+        self.shared.lock().keepalive_duration = seconds;
+    }
+
+    // Shutdown stops server, registrations are kept (like network failure)
+    pub async fn shutdown(&self) {
+        let running = self.running.lock().unwrap_or_else(PoisonError::into_inner).take();
+        if let Some(running) = running {
+            running.stop().await;
+        }
+    }
+
+    // Restart simulates node server restart: registrations and scripted commands are lost
+    // and server is started again on the same address
+    pub async fn restart(&self) -> io::Result<()> {
+        self.shutdown().await;
+        {
+            let mut state = self.shared.lock();
+            state.nodes.clear();
+            state.commands.clear();
+            state.restarts += 1;
+        }
+        self.serve(TcpListener::bind(self.addr).await?);
+        Ok(())
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.lock().unwrap_or_else(PoisonError::into_inner).as_ref()
+            .is_some_and(|running| !running.is_finished())
+    }
+
+    pub fn restarts(&self) -> u32 {
+        self.shared.lock().restarts
+    }
+
+    // ScriptCommand returns command with err on the next keepalive of node_id (None: any node).
+    // Commands are returned in order, one per keepalive.
+    pub fn script_command(&self, node_id: Option<i32>, command: nodeapi::KeepAliveCommand, err: String) {
+        self.shared.lock().commands.entry(node_id).or_default().push_back(ScriptedCommand { command, err });
+    }
+
+    // SendReconnect asks node to register again
+    pub fn send_reconnect(&self, node_id: i32) {
+        self.script_command(Some(node_id), nodeapi::KeepAliveCommand::Reconnect, String::new());
+    }
+
+    // SendServerChange moves nodes to server_info: it is given on re-registration after SERVER_CHANGE
+    pub fn send_server_change(&self, node_id: i32, server_info: String) {
+        self.set_server_info(server_info);
+        self.script_command(Some(node_id), nodeapi::KeepAliveCommand::ServerChange, String::new());
+    }
+
+    // SendProviderDisconnect tells SERVER node that provider_id is disconnected
+    pub fn send_provider_disconnect(&self, node_id: i32, provider_id: i32) {
+        self.script_command(Some(node_id), nodeapi::KeepAliveCommand::ProviderDisconnect, format!("provider disconnected: {}", provider_id));
+    }
+
+    // PendingCommands returns the number of scripted commands not yet returned
+    pub fn pending_commands(&self) -> usize {
+        self.shared.lock().commands.values().map(VecDeque::len).sum()
+    }
+
+    // Fail makes every call of method return status
+    pub fn fail(&self, method: MockNodeMethod, status: Status) {
+        self.shared.lock().faults.fail(method, status, None);
+    }
+
+    // FailTimes makes next `times` calls of method return status
+    pub fn fail_times(&self, method: MockNodeMethod, status: Status, times: u32) {
+        self.shared.lock().faults.fail(method, status, Some(times));
+    }
+
+    // SetLatency delays every call of method (before failure or response)
+    pub fn set_latency(&self, method: MockNodeMethod, latency: Duration) {
+        self.shared.lock().faults.set_latency(method, latency);
+    }
+
+    // ClearFaults removes injected failures and latency
+    pub fn clear_faults(&self) {
+        self.shared.lock().faults.clear();
+    }
+
+    // Nodes returns registered nodes ordered by node id
+    pub fn nodes(&self) -> Vec<MockNode> {
+        self.shared.lock().nodes.values().cloned().collect()
+    }
+
+    pub fn node(&self, node_id: i32) -> Option<MockNode> {
+        self.shared.lock().nodes.get(&node_id).cloned()
+    }
+
+    // NodeByName returns registered node with node_name
+    pub fn node_by_name(&self, node_name: &str) -> Option<MockNode> {
+        self.shared.lock().nodes.values().find(|node| node.info.node_name == node_name).cloned()
+    }
+
+    // Keepalives returns keepalives of node since its registration (0 if not registered)
+    pub fn keepalives(&self, node_id: i32) -> u32 {
+        self.node(node_id).map_or(0, |node| node.keepalives)
+    }
+
+    // Requests returns all received requests in arrival order (including failed ones)
+    pub fn requests(&self) -> Vec<MockNodeRequest> {
+        self.shared.lock().requests.clone()
+    }
+
+    pub fn requests_of(&self, method: MockNodeMethod) -> Vec<MockNodeRequest> {
+        self.shared.lock().requests.iter().filter(|req| req.method() == method).cloned().collect()
+    }
+
+    // MetadataOf returns metadata (e.g. authorization) of requests of method in arrival order
+    pub fn metadata_of(&self, method: MockNodeMethod) -> Vec<MetadataMap> {
+        self.shared.lock().metadata.iter().filter(|(m, _)| *m == method).map(|(_, metadata)| metadata.clone()).collect()
+    }
+
+    pub fn clear_requests(&self) {
+        let mut state = self.shared.lock();
+        state.requests.clear();
+        state.metadata.clear();
+    }
+
+    // AssertReceived panics unless method was called `count` times
+    pub fn assert_received(&self, method: MockNodeMethod, count: usize) {
+        let received = self.requests_of(method).len();
+        assert_eq!(received, count, "MockNodeServer: {:?} received {} times, expected {}", method, received, count);
+    }
+
+    // WaitForRequests waits until method is called `count` times and returns the requests
+    // (panics when not reached within wait)
+    pub async fn wait_for_requests(&self, method: MockNodeMethod, count: usize, wait: Duration) -> Vec<MockNodeRequest> {
+        let requests = self.wait_until(wait, || {
+            let requests = self.requests_of(method);
+            (requests.len() >= count).then_some(requests)
+        }).await;
+        requests.unwrap_or_else(|| panic!("MockNodeServer: {:?} not received {} times within {:?}", method, count, wait))
+    }
+
+    // WaitForKeepalives waits until node sends `count` keepalives (panics when not reached within wait)
+    pub async fn wait_for_keepalives(&self, node_id: i32, count: u32, wait: Duration) -> MockNode {
+        let node = self.wait_until(wait, || self.node(node_id).filter(|node| node.keepalives >= count)).await;
+        node.unwrap_or_else(|| panic!("MockNodeServer: node {} didn't send {} keepalives within {:?}", node_id, count, wait))
+    }
+
+    // WaitForNode waits until node with node_name is registered (panics when not registered within wait)
+    pub async fn wait_for_node(&self, node_name: &str, wait: Duration) -> MockNode {
+        let node = self.wait_until(wait, || self.node_by_name(node_name)).await;
+        node.unwrap_or_else(|| panic!("MockNodeServer: node {} not registered within {:?}", node_name, wait))
+    }
+
+    // checks `ready` on every request until it returns Some or wait expires
+    async fn wait_until<T, F: Fn() -> Option<T>>(&self, wait: Duration, ready: F) -> Option<T> {
+        let deadline = tokio::time::Instant::now() + wait;
+        loop {
+            let received = self.shared.received.notified();
+            tokio::pin!(received);
+            received.as_mut().enable();
+            if let Some(value) = ready() {
+                return Some(value);
+            }
+            if tokio::time::timeout_at(deadline, received).await.is_err() {
+                return ready();
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for MockNodeServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("MockNodeServer")
+            .field("addr", &self.addr)
+            .field("running", &self.is_running())
+            .finish()
+    }
+}
+
+impl Drop for MockNodeServer {
+    fn drop(&mut self) {
+        if let Some(running) = self.running.get_mut().unwrap_or_else(PoisonError::into_inner).take() {
+            running.cancel();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use tokio::sync::mpsc;
+    use crate::testing::{MockMethod, MockSynerexServer};
+    use crate::{grpc_connect_server, KeepAliveEvent, KeepAliveHandle, KeepAliveHandler, KeepAliveStatus, SxutilConfig, SxutilNode};
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    // what KeepAliveHandler is called with
+    #[derive(Debug, Clone, PartialEq)]
+    enum Called {
+        Reconnect,
+        ServerChange(String),
+        Event(KeepAliveEvent),
+    }
+
+    struct Recorder(mpsc::UnboundedSender<Called>);
+
+    #[tonic::async_trait]
+    impl KeepAliveHandler for Recorder {
+        async fn on_reconnect(&self) {
+            let _ = self.0.send(Called::Reconnect);
+        }
+
+        async fn on_server_change(&self, server_info: String) {
+            let _ = self.0.send(Called::ServerChange(server_info));
+        }
+
+        async fn on_event(&self, event: KeepAliveEvent) {
+            let _ = self.0.send(Called::Event(event));
+        }
+    }
+
+    fn config() -> SxutilConfig {
+        SxutilConfig::default()
+            .with_keepalive_interval(Duration::from_millis(20))
+            .with_keepalive_failure_threshold(2)
+            .with_reconnect_wait(Duration::from_millis(10))
+            .with_msg_timeout(Duration::from_secs(1))
+    }
+
+    // registers a provider with Recorder, returns node id after its first keepalive
+    async fn register(mock: &MockNodeServer, node: &SxutilNode) -> (KeepAliveHandle, mpsc::UnboundedReceiver<Called>, i32) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let handle = node.register_node_with_cmd(mock.address(), String::from("MockProvider"), vec![1], None, Some(Arc::new(Recorder(tx)))).await.unwrap();
+        let node_id = node.node_id().await;
+        mock.wait_for_keepalives(node_id, 1, WAIT).await;
+        (handle, rx, node_id)
+    }
+
+    // waits until handler is called with `expected` (other calls are skipped)
+    async fn expect_called(calls: &mut mpsc::UnboundedReceiver<Called>, expected: Called) {
+        let called = tokio::time::timeout(WAIT, async {
+            while let Some(called) = calls.recv().await {
+                if called == expected {
+                    return true;
+                }
+            }
+            false
+        }).await;
+        assert_eq!(called, Ok(true), "{:?} not called", expected);
+    }
+
+    fn with_node_ids(mock: &MockNodeServer) -> Vec<i32> {
+        mock.requests_of(MockNodeMethod::RegisterNode).into_iter()
+            .filter_map(|req| match req {
+                MockNodeRequest::RegisterNode(info) => Some(info.with_node_id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn reconnect_registers_again_with_node_id() {
+        let mock = MockNodeServer::start().await.unwrap();
+        let node = SxutilNode::with_config(config());
+        let (handle, mut calls, node_id) = register(&mock, &node).await;
+
+        mock.send_reconnect(node_id);
+        expect_called(&mut calls, Called::Reconnect).await;
+        assert_eq!(with_node_ids(&mock), vec![-1, node_id]);
+        assert_eq!(node.node_id().await, node_id);
+        assert_eq!(mock.pending_commands(), 0);
+        mock.wait_for_keepalives(node_id, 2, WAIT).await; // with the new secret
+
+        handle.stop().await.unwrap();
+        assert_eq!(handle.status(), KeepAliveStatus::Stopped);
+        assert!(mock.nodes().is_empty());
+    }
+
+    #[tokio::test]
+    async fn server_change_moves_connection_and_subscriptions() {
+        let (old_server, new_server) = (MockSynerexServer::start().await.unwrap(), MockSynerexServer::start().await.unwrap());
+        let mock = MockNodeServer::start().await.unwrap();
+        mock.set_server_info(old_server.address());
+        let node = SxutilNode::with_config(config());
+        let (handle, mut calls, node_id) = register(&mock, &node).await;
+
+        let server_info = handle.server_info().await;
+        assert_eq!(server_info, old_server.address());
+        let clt = Arc::new(node.new_sx_service_client(grpc_connect_server(server_info).await.unwrap(), 1, String::new()).await);
+        let mut supplies = Box::pin(Arc::clone(&clt).into_supply_stream());
+        let subscriber = tokio::spawn(async move { supplies.next().await });
+        old_server.wait_for_requests(MockMethod::SubscribeSupply, 1, WAIT).await;
+
+        mock.send_server_change(node_id, new_server.address());
+        expect_called(&mut calls, Called::ServerChange(new_server.address())).await;
+        assert_eq!(clt.server_address().await, Some(new_server.address()));
+        assert_eq!(with_node_ids(&mock), vec![-1, node_id]);
+        mock.assert_received(MockNodeMethod::UnRegisterNode, 1);
+
+        // subscription is opened again on the new server
+        new_server.wait_for_requests(MockMethod::SubscribeSupply, 1, WAIT).await;
+        tokio::time::timeout(WAIT, async {
+            while new_server.publish_supply(api_supply(7)) == 0 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        }).await.unwrap();
+        let sp = tokio::time::timeout(WAIT, subscriber).await.unwrap().unwrap();
+        assert_eq!(sp.unwrap().unwrap().id, 7);
+        handle.stop().await.unwrap();
+    }
+
+    fn api_supply(id: u64) -> synerex_api::api::Supply {
+        synerex_api::api::Supply { id, channel_type: 1, ..Default::default() }
+    }
+
+    #[tokio::test]
+    async fn server_change_fails_when_registration_is_lost() {
+        let mock = MockNodeServer::start().await.unwrap();
+        let node = SxutilNode::with_config(config());
+        let (mut handle, _calls, node_id) = register(&mock, &node).await;
+
+        mock.fail(MockNodeMethod::RegisterNode, Status::unavailable("injected"));
+        mock.send_server_change(node_id, String::from("127.0.0.1:1"));
+        let state = tokio::time::timeout(WAIT, async {
+            loop {
+                let state = handle.changed().await.unwrap();
+                if state.status != KeepAliveStatus::Running {
+                    return state;
+                }
+            }
+        }).await.unwrap();
+        assert_eq!(state.status, KeepAliveStatus::Failed);
+        assert!(state.last_error.is_some());
+        assert_eq!(with_node_ids(&mock), vec![-1, node_id, node_id]); // tried keepalive_failure_threshold times
+        assert!(handle.join().await.is_err());
+        handle.stop().await.unwrap(); // already unregistered
+        mock.assert_received(MockNodeMethod::UnRegisterNode, 1);
+    }
+
+    #[tokio::test]
+    async fn restart_is_recovered_by_reregistration() {
+        let mock = MockNodeServer::start().await.unwrap();
+        let node = SxutilNode::with_config(config());
+        let (handle, mut calls, node_id) = register(&mock, &node).await;
+
+        mock.shutdown().await;
+        expect_called(&mut calls, Called::Event(KeepAliveEvent::NodeServerLost { failures: 2 })).await;
+        assert!(handle.state().consecutive_failures >= 2);
+
+        mock.restart().await.unwrap();
+        assert_eq!(mock.restarts(), 1);
+        expect_called(&mut calls, Called::Event(KeepAliveEvent::Reregistered { node_id })).await;
+        assert_eq!(node.node_id().await, node_id);
+        mock.wait_for_keepalives(node_id, 1, WAIT).await;
+        assert_eq!(handle.status(), KeepAliveStatus::Running);
+        handle.stop().await.unwrap();
+    }
+}
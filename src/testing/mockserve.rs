@@ -0,0 +1,112 @@
+use core::time::Duration;
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+
+use tokio::task::JoinHandle;
+use tokio_util::sync::{CancellationToken, WaitForCancellationFutureOwned};
+use tonic::Status;
+
+// wait for graceful shutdown of server before aborting it
+const SHUTDOWN_WAIT: u64 = 5;
+
+// injected failure (remaining None: fails every time)
+struct Fault {
+    status: Status,
+    remaining: Option<u32>,
+}
+
+// Faults holds failures and latency injected per method of mock server
+pub(super) struct Faults<M> {
+    faults: HashMap<M, Fault>,
+    latency: HashMap<M, Duration>,
+}
+
+impl<M: Copy + Eq + Hash> Faults<M> {
+    pub(super) fn new() -> Self {
+        Faults { faults: HashMap::new(), latency: HashMap::new() }
+    }
+
+    pub(super) fn fail(&mut self, method: M, status: Status, times: Option<u32>) {
+        if times != Some(0) {
+            self.faults.insert(method, Fault { status, remaining: times });
+        }
+    }
+
+    pub(super) fn set_latency(&mut self, method: M, latency: Duration) {
+        self.latency.insert(method, latency);
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.faults.clear();
+        self.latency.clear();
+    }
+
+    // latency and failure for a call of method (counts down failures given times)
+    pub(super) fn next(&mut self, method: M) -> (Option<Duration>, Option<Status>) {
+        let latency = self.latency.get(&method).copied();
+        let Some(fault) = self.faults.get_mut(&method) else {
+            return (latency, None);
+        };
+        let status = fault.status.clone();
+        if let Some(remaining) = fault.remaining.as_mut() {
+            *remaining -= 1;
+            if *remaining == 0 {
+                self.faults.remove(&method);
+            }
+        }
+        (latency, Some(status))
+    }
+}
+
+// applies latency and failure taken by Faults::next (call without holding lock)
+pub(super) async fn inject((latency, fault): (Option<Duration>, Option<Status>)) -> Result<(), Status> {
+    if let Some(latency) = latency {
+        tokio::time::sleep(latency).await;
+    }
+    match fault {
+        Some(status) => Err(status),
+        None => Ok(()),
+    }
+}
+
+// Running is a spawned server task
+pub(super) struct Running {
+    shutdown: CancellationToken,
+    join: JoinHandle<()>,
+}
+
+impl Running {
+    // spawns serve with shutdown signal
+    pub(super) fn spawn<F, Fut>(name: &'static str, serve: F) -> Self
+    where
+        F: FnOnce(WaitForCancellationFutureOwned) -> Fut,
+        Fut: Future<Output = Result<(), tonic::transport::Error>> + Send + 'static,
+    {
+        let shutdown = CancellationToken::new();
+        let served = serve(shutdown.clone().cancelled_owned());
+        let join = tokio::spawn(async move {
+            if let Err(err) = served.await {
+                error!("{}: {}", name, err);
+            }
+        });
+        Running { shutdown, join }
+    }
+
+    pub(super) fn is_finished(&self) -> bool {
+        self.join.is_finished()
+    }
+
+    // signals shutdown without waiting (for Drop)
+    pub(super) fn cancel(&self) {
+        self.shutdown.cancel();
+    }
+
+    // graceful shutdown (streams should be ended before), aborted after SHUTDOWN_WAIT
+    pub(super) async fn stop(mut self) {
+        self.shutdown.cancel();
+        if tokio::time::timeout(Duration::from_secs(SHUTDOWN_WAIT), &mut self.join).await.is_err() {
+            self.join.abort();
+        }
+    }
+}
@@ -0,0 +1,871 @@
+use core::time::Duration;
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use futures::{stream, Stream};
+use tokio::io::DuplexStream;
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, Notify};
+use tonic::service::interceptor::InterceptedService;
+use tonic::transport::{Server, Uri};
+use tonic::metadata::MetadataMap;
+use tonic::{Request, Response, Status};
+
+use synerex_api::api::{self, synerex_server::{Synerex, SynerexServer}};
+
+use crate::{ConnectOptions, SXSynerexClient, SxutilError, grpc_connect_server_with};
+use crate::serving::{confirmed, ok, rejected, tcp_incoming, Subscribers, SubscriptionStream};
+use super::mockserve::{inject, Faults, Running};
+
+// address of in-memory MockSynerexServer (can't be dialed by reconnection)
+const IN_MEMORY_ADDRESS: &str = "in-memory.mock";
+const IN_MEMORY_BUFFER: usize = 64 * 1024;
+
+// messages kept for each stream not read by its client (published messages are dropped beyond it)
+const STREAM_BUFFER: usize = 4096;
+
+// MockMethod is a method of Synerex service
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MockMethod {
+    NotifyDemand,
+    NotifySupply,
+    ProposeDemand,
+    ProposeSupply,
+    SelectSupply,
+    SelectModifiedSupply,
+    SelectDemand,
+    Confirm,
+    SubscribeDemand,
+    SubscribeSupply,
+    CreateMbus,
+    CloseMbus,
+    SubscribeMbus,
+    SendMbusMsg,
+    GetMbusState,
+    SubscribeGateway,
+    ForwardToGateway,
+    CloseDemandChannel,
+    CloseSupplyChannel,
+    CloseAllChannels,
+}
+
+// MockRequest is a request received by MockSynerexServer
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockRequest {
+    NotifyDemand(api::Demand),
+    NotifySupply(api::Supply),
+    ProposeDemand(api::Demand),
+    ProposeSupply(api::Supply),
+    SelectSupply(api::Target),
+    SelectModifiedSupply(api::Supply),
+    SelectDemand(api::Target),
+    Confirm(api::Target),
+    SubscribeDemand(api::Channel),
+    SubscribeSupply(api::Channel),
+    CreateMbus(api::MbusOpt),
+    CloseMbus(api::Mbus),
+    SubscribeMbus(api::Mbus),
+    SendMbusMsg(api::MbusMsg),
+    GetMbusState(api::Mbus),
+    SubscribeGateway(api::GatewayInfo),
+    ForwardToGateway(api::GatewayMsg),
+    CloseDemandChannel(api::Channel),
+    CloseSupplyChannel(api::Channel),
+    CloseAllChannels(api::ProviderId),
+}
+
+impl MockRequest {
+    pub fn method(&self) -> MockMethod {
+        match self {
+            MockRequest::NotifyDemand(_) => MockMethod::NotifyDemand,
+            MockRequest::NotifySupply(_) => MockMethod::NotifySupply,
+            MockRequest::ProposeDemand(_) => MockMethod::ProposeDemand,
+            MockRequest::ProposeSupply(_) => MockMethod::ProposeSupply,
+            MockRequest::SelectSupply(_) => MockMethod::SelectSupply,
+            MockRequest::SelectModifiedSupply(_) => MockMethod::SelectModifiedSupply,
+            MockRequest::SelectDemand(_) => MockMethod::SelectDemand,
+            MockRequest::Confirm(_) => MockMethod::Confirm,
+            MockRequest::SubscribeDemand(_) => MockMethod::SubscribeDemand,
+            MockRequest::SubscribeSupply(_) => MockMethod::SubscribeSupply,
+            MockRequest::CreateMbus(_) => MockMethod::CreateMbus,
+            MockRequest::CloseMbus(_) => MockMethod::CloseMbus,
+            MockRequest::SubscribeMbus(_) => MockMethod::SubscribeMbus,
+            MockRequest::SendMbusMsg(_) => MockMethod::SendMbusMsg,
+            MockRequest::GetMbusState(_) => MockMethod::GetMbusState,
+            MockRequest::SubscribeGateway(_) => MockMethod::SubscribeGateway,
+            MockRequest::ForwardToGateway(_) => MockMethod::ForwardToGateway,
+            MockRequest::CloseDemandChannel(_) => MockMethod::CloseDemandChannel,
+            MockRequest::CloseSupplyChannel(_) => MockMethod::CloseSupplyChannel,
+            MockRequest::CloseAllChannels(_) => MockMethod::CloseAllChannels,
+        }
+    }
+}
+
+struct MockMbus {
+    opt: api::MbusOpt,
+    closed: bool,
+}
+
+struct MockState {
+    requests: Vec<MockRequest>,
+    // metadata (headers) of requests
+    metadata: Vec<(MockMethod, MetadataMap)>,
+    faults: Faults<MockMethod>,
+    demands: Subscribers<api::Demand>,
+    supplies: Subscribers<api::Supply>,
+    mbus_msgs: Subscribers<api::MbusMsg>,
+    gateways: Subscribers<api::GatewayMsg>,
+    mbuses: HashMap<u64, MockMbus>,
+    next_id: u64,
+    // sender of in-memory connections (None: in-memory server is down)
+    incoming: Option<mpsc::UnboundedSender<DuplexStream>>,
+}
+
+impl MockState {
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn create_mbus(&mut self, opt: api::MbusOpt) -> u64 {
+        let mbus_id = self.next_id();
+        self.mbuses.insert(mbus_id, MockMbus { opt, closed: false });
+        mbus_id
+    }
+
+    // sender of demand / supply with id (from received requests)
+    fn sender_of(&self, id: u64) -> Option<u64> {
+        self.requests.iter().rev().find_map(|req| match req {
+            MockRequest::NotifyDemand(dm) | MockRequest::ProposeDemand(dm) if dm.id == id => Some(dm.sender_id),
+            MockRequest::NotifySupply(sp) | MockRequest::ProposeSupply(sp) if sp.id == id => Some(sp.sender_id),
+            _ => None,
+        })
+    }
+
+    // private mbus between selecting client and the owner of target
+    fn select_mbus(&mut self, sender_id: u64, owner: Option<u64>) -> u64 {
+        let mut subscribers = vec![sender_id];
+        subscribers.extend(owner);
+        self.create_mbus(api::MbusOpt { mbus_type: api::mbus_opt::MbusType::Private as i32, subscribers })
+    }
+
+    fn drop_streams(&mut self, method: Option<MockMethod>, status: &Status) {
+        let all = method.is_none();
+        if all || method == Some(MockMethod::SubscribeDemand) {
+            self.demands.close_with(|_, _| true, Some(status));
+        }
+        if all || method == Some(MockMethod::SubscribeSupply) {
+            self.supplies.close_with(|_, _| true, Some(status));
+        }
+        if all || method == Some(MockMethod::SubscribeMbus) {
+            self.mbus_msgs.close_with(|_, _| true, Some(status));
+        }
+        if all || method == Some(MockMethod::SubscribeGateway) {
+            self.gateways.close_with(|_, _| true, Some(status));
+        }
+    }
+}
+
+struct Shared {
+    state: Mutex<MockState>,
+    // notified on every request
+    received: Notify,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// Synerex service of MockSynerexServer
+struct MockService {
+    shared: Arc<Shared>,
+}
+
+// receivers of message (only target if given)
+fn to(target: Option<u64>) -> impl Fn(u64) -> bool {
+    move |client_id| target.is_none() || target == Some(client_id)
+}
+
+impl MockService {
+    // records request, then applies injected latency and failure
+    async fn intercept(&self, metadata: MetadataMap, request: MockRequest) -> Result<(), Status> {
+        let injected = {
+            let mut state = self.shared.lock();
+            let injected = state.faults.next(request.method());
+            state.metadata.push((request.method(), metadata));
+            state.requests.push(request);
+            injected
+        };
+        self.shared.received.notify_waiters();
+        inject(injected).await
+    }
+}
+
+#[tonic::async_trait]
+impl Synerex for MockService {
+    type SubscribeDemandStream = SubscriptionStream<api::Demand>;
+    type SubscribeSupplyStream = SubscriptionStream<api::Supply>;
+    type SubscribeMbusStream = SubscriptionStream<api::MbusMsg>;
+    type SubscribeGatewayStream = SubscriptionStream<api::GatewayMsg>;
+
+    async fn notify_demand(&self, request: Request<api::Demand>) -> Result<Response<api::Response>, Status> {
+        let (metadata, _, dm) = request.into_parts();
+        self.intercept(metadata, MockRequest::NotifyDemand(dm.clone())).await?;
+        self.shared.lock().demands.send(dm.channel_type as u64, to(None), &dm);
+        Ok(ok())
+    }
+
+    async fn notify_supply(&self, request: Request<api::Supply>) -> Result<Response<api::Response>, Status> {
+        let (metadata, _, sp) = request.into_parts();
+        self.intercept(metadata, MockRequest::NotifySupply(sp.clone())).await?;
+        self.shared.lock().supplies.send(sp.channel_type as u64, to(None), &sp);
+        Ok(ok())
+    }
+
+    async fn propose_demand(&self, request: Request<api::Demand>) -> Result<Response<api::Response>, Status> {
+        let (metadata, _, dm) = request.into_parts();
+        self.intercept(metadata, MockRequest::ProposeDemand(dm.clone())).await?;
+        let mut state = self.shared.lock();
+        let target = state.sender_of(dm.target_id);
+        state.demands.send(dm.channel_type as u64, to(target), &dm);
+        Ok(ok())
+    }
+
+    async fn propose_supply(&self, request: Request<api::Supply>) -> Result<Response<api::Response>, Status> {
+        let (metadata, _, sp) = request.into_parts();
+        self.intercept(metadata, MockRequest::ProposeSupply(sp.clone())).await?;
+        let mut state = self.shared.lock();
+        let target = state.sender_of(sp.target_id);
+        state.supplies.send(sp.channel_type as u64, to(target), &sp);
+        Ok(ok())
+    }
+
+    // selection is delivered to the supplier as Demand targeting the supply
+    async fn select_supply(&self, request: Request<api::Target>) -> Result<Response<api::ConfirmResponse>, Status> {
+        let (metadata, _, tg) = request.into_parts();
+        self.intercept(metadata, MockRequest::SelectSupply(tg.clone())).await?;
+        let mut state = self.shared.lock();
+        let owner = state.sender_of(tg.target_id);
+        let mbus_id = state.select_mbus(tg.sender_id, owner);
+        let dm = api::Demand { id: tg.id, sender_id: tg.sender_id, target_id: tg.target_id, channel_type: tg.channel_type, mbus_id, ..Default::default() };
+        state.demands.send(tg.channel_type as u64, to(owner), &dm);
+        Ok(confirmed(mbus_id))
+    }
+
+    async fn select_modified_supply(&self, request: Request<api::Supply>) -> Result<Response<api::ConfirmResponse>, Status> {
+        let (metadata, _, sp) = request.into_parts();
+        self.intercept(metadata, MockRequest::SelectModifiedSupply(sp.clone())).await?;
+        let mut state = self.shared.lock();
+        let owner = state.sender_of(sp.target_id);
+        let mbus_id = state.select_mbus(sp.sender_id, owner);
+        let dm = api::Demand {
+            id: sp.id,
+            sender_id: sp.sender_id,
+            target_id: sp.target_id,
+            channel_type: sp.channel_type,
+            demand_name: sp.supply_name,
+            ts: sp.ts,
+            arg_json: sp.arg_json,
+            mbus_id,
+            cdata: sp.cdata,
+        };
+        state.demands.send(dm.channel_type as u64, to(owner), &dm);
+        Ok(confirmed(mbus_id))
+    }
+
+    // selection is delivered to the demander as Supply targeting the demand
+    async fn select_demand(&self, request: Request<api::Target>) -> Result<Response<api::ConfirmResponse>, Status> {
+        let (metadata, _, tg) = request.into_parts();
+        self.intercept(metadata, MockRequest::SelectDemand(tg.clone())).await?;
+        let mut state = self.shared.lock();
+        let owner = state.sender_of(tg.target_id);
+        let mbus_id = state.select_mbus(tg.sender_id, owner);
+        let sp = api::Supply { id: tg.id, sender_id: tg.sender_id, target_id: tg.target_id, channel_type: tg.channel_type, mbus_id, ..Default::default() };
+        state.supplies.send(tg.channel_type as u64, to(owner), &sp);
+        Ok(confirmed(mbus_id))
+    }
+
+    async fn confirm(&self, request: Request<api::Target>) -> Result<Response<api::Response>, Status> {
+        let (metadata, _, tg) = request.into_parts();
+        self.intercept(metadata, MockRequest::Confirm(tg)).await?;
+        Ok(ok())
+    }
+
+    async fn subscribe_demand(&self, request: Request<api::Channel>) -> Result<Response<Self::SubscribeDemandStream>, Status> {
+        let (metadata, _, ch) = request.into_parts();
+        self.intercept(metadata, MockRequest::SubscribeDemand(ch.clone())).await?;
+        Ok(Response::new(self.shared.lock().demands.subscribe(ch.client_id, ch.channel_type as u64)))
+    }
+
+    async fn subscribe_supply(&self, request: Request<api::Channel>) -> Result<Response<Self::SubscribeSupplyStream>, Status> {
+        let (metadata, _, ch) = request.into_parts();
+        self.intercept(metadata, MockRequest::SubscribeSupply(ch.clone())).await?;
+        Ok(Response::new(self.shared.lock().supplies.subscribe(ch.client_id, ch.channel_type as u64)))
+    }
+
+    async fn create_mbus(&self, request: Request<api::MbusOpt>) -> Result<Response<api::Mbus>, Status> {
+        let (metadata, _, opt) = request.into_parts();
+        self.intercept(metadata, MockRequest::CreateMbus(opt.clone())).await?;
+        let mbus_id = self.shared.lock().create_mbus(opt);
+        Ok(Response::new(api::Mbus { client_id: 0, mbus_id, arg_json: String::new() }))
+    }
+
+    // closing mbus ends its subscriptions normally
+    async fn close_mbus(&self, request: Request<api::Mbus>) -> Result<Response<api::Response>, Status> {
+        let (metadata, _, mb) = request.into_parts();
+        self.intercept(metadata, MockRequest::CloseMbus(mb.clone())).await?;
+        let mut state = self.shared.lock();
+        match state.mbuses.get_mut(&mb.mbus_id) {
+            Some(mbus) if !mbus.closed => mbus.closed = true,
+            _ => return Ok(rejected("no such mbus")),
+        }
+        state.mbus_msgs.close(|_, key| key == mb.mbus_id);
+        Ok(ok())
+    }
+
+    async fn subscribe_mbus(&self, request: Request<api::Mbus>) -> Result<Response<Self::SubscribeMbusStream>, Status> {
+        let (metadata, _, mb) = request.into_parts();
+        self.intercept(metadata, MockRequest::SubscribeMbus(mb.clone())).await?;
+        let mut state = self.shared.lock();
+        let private = match state.mbuses.get(&mb.mbus_id) {
+            Some(mbus) if !mbus.closed => {
+                mbus.opt.mbus_type == api::mbus_opt::MbusType::Private as i32 && !mbus.opt.subscribers.contains(&mb.client_id)
+            },
+            _ => return Err(Status::not_found(format!("no such mbus {}", mb.mbus_id))),
+        };
+        if private {
+            return Err(Status::permission_denied(format!("mbus {} is private", mb.mbus_id)));
+        }
+        Ok(Response::new(state.mbus_msgs.subscribe(mb.client_id, mb.mbus_id)))
+    }
+
+    async fn send_mbus_msg(&self, request: Request<api::MbusMsg>) -> Result<Response<api::Response>, Status> {
+        let (metadata, _, msg) = request.into_parts();
+        self.intercept(metadata, MockRequest::SendMbusMsg(msg.clone())).await?;
+        let mut state = self.shared.lock();
+        match state.mbuses.get(&msg.mbus_id) {
+            Some(mbus) if !mbus.closed => {},
+            _ => return Ok(rejected("no such mbus")),
+        }
+        let target = if msg.target_id == 0 { None } else { Some(msg.target_id) };
+        state.mbus_msgs.send(msg.mbus_id, to(target), &msg);
+        Ok(ok())
+    }
+
+    async fn get_mbus_state(&self, request: Request<api::Mbus>) -> Result<Response<api::MbusState>, Status> {
+        let (metadata, _, mb) = request.into_parts();
+        self.intercept(metadata, MockRequest::GetMbusState(mb.clone())).await?;
+        let mut state = self.shared.lock();
+        let subscribers = state.mbus_msgs.clients(mb.mbus_id);
+        let status = match state.mbuses.get(&mb.mbus_id) {
+            None => api::mbus_state::MbusStatus::Invalid,
+            Some(mbus) if mbus.closed => api::mbus_state::MbusStatus::Closed,
+            Some(_) if subscribers.is_empty() => api::mbus_state::MbusStatus::Intialized,
+            Some(_) => api::mbus_state::MbusStatus::Subscribers,
+        };
+        Ok(Response::new(api::MbusState { mbus_id: mb.mbus_id, status: status as i32, subscribers }))
+    }
+
+    async fn subscribe_gateway(&self, request: Request<api::GatewayInfo>) -> Result<Response<Self::SubscribeGatewayStream>, Status> {
+        let (metadata, _, gi) = request.into_parts();
+        self.intercept(metadata, MockRequest::SubscribeGateway(gi.clone())).await?;
+        Ok(Response::new(self.shared.lock().gateways.subscribe(gi.client_id, 0)))
+    }
+
+    async fn forward_to_gateway(&self, request: Request<api::GatewayMsg>) -> Result<Response<api::Response>, Status> {
+        let (metadata, _, msg) = request.into_parts();
+        self.intercept(metadata, MockRequest::ForwardToGateway(msg.clone())).await?;
+        self.shared.lock().gateways.send(0, to(None), &msg);
+        Ok(ok())
+    }
+
+    async fn close_demand_channel(&self, request: Request<api::Channel>) -> Result<Response<api::Response>, Status> {
+        let (metadata, _, ch) = request.into_parts();
+        self.intercept(metadata, MockRequest::CloseDemandChannel(ch.clone())).await?;
+        self.shared.lock().demands.close(|cid, key| cid == ch.client_id && key == ch.channel_type as u64);
+        Ok(ok())
+    }
+
+    async fn close_supply_channel(&self, request: Request<api::Channel>) -> Result<Response<api::Response>, Status> {
+        let (metadata, _, ch) = request.into_parts();
+        self.intercept(metadata, MockRequest::CloseSupplyChannel(ch.clone())).await?;
+        self.shared.lock().supplies.close(|cid, key| cid == ch.client_id && key == ch.channel_type as u64);
+        Ok(ok())
+    }
+
+    async fn close_all_channels(&self, request: Request<api::ProviderId>) -> Result<Response<api::Response>, Status> {
+        let (metadata, _, pid) = request.into_parts();
+        self.intercept(metadata, MockRequest::CloseAllChannels(pid.clone())).await?;
+        let mut state = self.shared.lock();
+        state.demands.close(|cid, _| cid == pid.client_id);
+        state.supplies.close(|cid, _| cid == pid.client_id);
+        state.mbus_msgs.close(|cid, _| cid == pid.client_id);
+        Ok(ok())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Transport {
+    Tcp(SocketAddr),
+    InMemory,
+}
+
+// MockSynerexServer is an in-process Synerex server for tests of providers.
+// Demands / supplies are delivered to subscribers of the same channel_type (also to the sender),
+// proposals and selections to the sender of the target when it is known.
+// Failures, latency and stream drops can be injected per method, and received requests are recorded.
+pub struct MockSynerexServer {
+    shared: Arc<Shared>,
+    transport: Transport,
+    running: Mutex<Option<Running>>,
+}
+
+impl MockSynerexServer {
+    // Start binds a free local port
+    pub async fn start() -> io::Result<Self> {
+        Self::start_on(SocketAddr::from(([127, 0, 0, 1], 0))).await
+    }
+
+    // StartOn binds addr (port 0 picks a free port)
+    pub async fn start_on(addr: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let server = Self::new(Transport::Tcp(listener.local_addr()?));
+        server.serve_tcp(listener);
+        Ok(server)
+    }
+
+    // StartInMemory serves on in-memory duplex streams (no port is used).
+    // Clients from connect() reconnect their channel after restart(),
+    // but SXServiceClient can't redial it (use start() to test reconnection).
+    pub async fn start_in_memory() -> Self {
+        let server = Self::new(Transport::InMemory);
+        server.serve_in_memory();
+        server
+    }
+
+    fn new(transport: Transport) -> Self {
+        let state = MockState {
+            requests: Vec::new(),
+            metadata: Vec::new(),
+            faults: Faults::new(),
+            demands: Subscribers::new(STREAM_BUFFER),
+            supplies: Subscribers::new(STREAM_BUFFER),
+            mbus_msgs: Subscribers::new(STREAM_BUFFER),
+            gateways: Subscribers::new(STREAM_BUFFER),
+            mbuses: HashMap::new(),
+            next_id: 0,
+            incoming: None,
+        };
+        MockSynerexServer {
+            shared: Arc::new(Shared { state: Mutex::new(state), received: Notify::new() }),
+            transport,
+            running: Mutex::new(None),
+        }
+    }
+
+    fn serve_tcp(&self, listener: TcpListener) {
+        self.serve(tcp_incoming(listener));
+    }
+
+    fn serve_in_memory(&self) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.shared.lock().incoming = Some(tx);
+        let incoming = stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|conn| (Ok::<_, io::Error>(conn), rx))
+        });
+        self.serve(incoming);
+    }
+
+    fn serve<I, IO>(&self, incoming: I)
+    where
+        I: Stream<Item = io::Result<IO>> + Send + 'static,
+        IO: tokio::io::AsyncRead + tokio::io::AsyncWrite + tonic::transport::server::Connected + Unpin + Send + 'static,
+        IO::ConnectInfo: Clone + Send + Sync + 'static,
+    {
+        let service = SynerexServer::new(MockService { shared: Arc::clone(&self.shared) });
+        let running = Running::spawn("MockSynerexServer", |signal| {
+            Server::builder().add_service(service).serve_with_incoming_shutdown(incoming, signal)
+        });
+        *self.running.lock().unwrap_or_else(PoisonError::into_inner) = Some(running);
+    }
+
+    // Address to connect ("127.0.0.1:port" or in-memory address)
+    pub fn address(&self) -> String {
+        match self.transport {
+            Transport::Tcp(addr) => addr.to_string(),
+            Transport::InMemory => IN_MEMORY_ADDRESS.to_string(),
+        }
+    }
+
+    pub async fn connect(&self) -> Result<SXSynerexClient, SxutilError> {
+        self.connect_with(&ConnectOptions::default()).await
+    }
+
+    // ConnectWith connects with options (interceptor is applied also in memory)
+    pub async fn connect_with(&self, opts: &ConnectOptions) -> Result<SXSynerexClient, SxutilError> {
+        if let Transport::Tcp(addr) = self.transport {
+            return grpc_connect_server_with(addr.to_string(), opts).await;
+        }
+        let shared = Arc::clone(&self.shared);
+        let connector = tower::service_fn(move |_: Uri| {
+            let incoming = shared.lock().incoming.clone();
+            async move {
+                let refused = || io::Error::new(io::ErrorKind::ConnectionRefused, "MockSynerexServer is down");
+                let (client, server) = tokio::io::duplex(IN_MEMORY_BUFFER);
+                incoming.ok_or_else(refused)?.send(server).map_err(|_| refused())?;
+                Ok::<_, io::Error>(client)
+            }
+        });
+        let channel = opts.endpoint(IN_MEMORY_ADDRESS)?.connect_with_connector(connector).await?;
+        let client = api::synerex_client::SynerexClient::new(InterceptedService::new(channel, opts.interceptor.clone()));
+        let mut clt = SXSynerexClient::new(IN_MEMORY_ADDRESS.to_string(), client);
+        clt.connect = Arc::new(opts.clone());
+        Ok(clt)
+    }
+
+    // Shutdown stops server (open streams end with UNAVAILABLE), recorded requests are kept
+    pub async fn shutdown(&self) {
+        let running = {
+            let mut state = self.shared.lock();
+            state.incoming = None;
+            state.drop_streams(None, &Status::unavailable("MockSynerexServer shutdown"));
+            self.running.lock().unwrap_or_else(PoisonError::into_inner).take()
+        };
+        if let Some(running) = running {
+            running.stop().await;
+        }
+    }
+
+    // Restart serves again on the same address (after shutdown, or stops the running server first)
+    pub async fn restart(&self) -> io::Result<()> {
+        self.shutdown().await;
+        match self.transport {
+            Transport::Tcp(addr) => self.serve_tcp(TcpListener::bind(addr).await?),
+            Transport::InMemory => self.serve_in_memory(),
+        }
+        Ok(())
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.lock().unwrap_or_else(PoisonError::into_inner).as_ref()
+            .is_some_and(|running| !running.is_finished())
+    }
+
+    // Fail makes every call of method return status
+    pub fn fail(&self, method: MockMethod, status: Status) {
+        self.shared.lock().faults.fail(method, status, None);
+    }
+
+    // FailTimes makes next `times` calls of method return status
+    pub fn fail_times(&self, method: MockMethod, status: Status, times: u32) {
+        self.shared.lock().faults.fail(method, status, Some(times));
+    }
+
+    // SetLatency delays every call of method (before failure or response)
+    pub fn set_latency(&self, method: MockMethod, latency: Duration) {
+        self.shared.lock().faults.set_latency(method, latency);
+    }
+
+    // ClearFaults removes injected failures and latency
+    pub fn clear_faults(&self) {
+        self.shared.lock().faults.clear();
+    }
+
+    // DropStreams ends all open subscription streams with status
+    pub fn drop_streams(&self, status: Status) {
+        self.shared.lock().drop_streams(None, &status);
+    }
+
+    // DropStreamsOf ends open streams of subscribe method with status
+    pub fn drop_streams_of(&self, method: MockMethod, status: Status) {
+        self.shared.lock().drop_streams(Some(method), &status);
+    }
+
+    // PublishDemand sends dm to demand subscribers of its channel_type, returns the number of receivers
+    pub fn publish_demand(&self, dm: api::Demand) -> usize {
+        self.shared.lock().demands.send(dm.channel_type as u64, to(None), &dm).delivered
+    }
+
+    // PublishSupply sends sp to supply subscribers of its channel_type, returns the number of receivers
+    pub fn publish_supply(&self, sp: api::Supply) -> usize {
+        self.shared.lock().supplies.send(sp.channel_type as u64, to(None), &sp).delivered
+    }
+
+    // PublishMbusMsg sends msg to subscribers of its mbus, returns the number of receivers
+    pub fn publish_mbus_msg(&self, msg: api::MbusMsg) -> usize {
+        let target = if msg.target_id == 0 { None } else { Some(msg.target_id) };
+        self.shared.lock().mbus_msgs.send(msg.mbus_id, to(target), &msg).delivered
+    }
+
+    // Subscribers returns the number of open streams of subscribe method
+    pub fn subscribers(&self, method: MockMethod) -> usize {
+        let mut state = self.shared.lock();
+        match method {
+            MockMethod::SubscribeDemand => state.demands.len(),
+            MockMethod::SubscribeSupply => state.supplies.len(),
+            MockMethod::SubscribeMbus => state.mbus_msgs.len(),
+            MockMethod::SubscribeGateway => state.gateways.len(),
+            _ => 0,
+        }
+    }
+
+    // Requests returns all received requests in arrival order (including failed ones)
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.shared.lock().requests.clone()
+    }
+
+    pub fn requests_of(&self, method: MockMethod) -> Vec<MockRequest> {
+        self.shared.lock().requests.iter().filter(|req| req.method() == method).cloned().collect()
+    }
+
+    // MetadataOf returns metadata (e.g. authorization) of requests of method in arrival order
+    pub fn metadata_of(&self, method: MockMethod) -> Vec<MetadataMap> {
+        self.shared.lock().metadata.iter().filter(|(m, _)| *m == method).map(|(_, metadata)| metadata.clone()).collect()
+    }
+
+    pub fn clear_requests(&self) {
+        let mut state = self.shared.lock();
+        state.requests.clear();
+        state.metadata.clear();
+    }
+
+    pub fn notified_demands(&self) -> Vec<api::Demand> {
+        self.filter_requests(|req| match req {
+            MockRequest::NotifyDemand(dm) => Some(dm.clone()),
+            _ => None,
+        })
+    }
+
+    pub fn notified_supplies(&self) -> Vec<api::Supply> {
+        self.filter_requests(|req| match req {
+            MockRequest::NotifySupply(sp) => Some(sp.clone()),
+            _ => None,
+        })
+    }
+
+    pub fn proposed_demands(&self) -> Vec<api::Demand> {
+        self.filter_requests(|req| match req {
+            MockRequest::ProposeDemand(dm) => Some(dm.clone()),
+            _ => None,
+        })
+    }
+
+    pub fn proposed_supplies(&self) -> Vec<api::Supply> {
+        self.filter_requests(|req| match req {
+            MockRequest::ProposeSupply(sp) => Some(sp.clone()),
+            _ => None,
+        })
+    }
+
+    pub fn mbus_messages(&self) -> Vec<api::MbusMsg> {
+        self.filter_requests(|req| match req {
+            MockRequest::SendMbusMsg(msg) => Some(msg.clone()),
+            _ => None,
+        })
+    }
+
+    fn filter_requests<T, F: Fn(&MockRequest) -> Option<T>>(&self, f: F) -> Vec<T> {
+        self.shared.lock().requests.iter().filter_map(f).collect()
+    }
+
+    // AssertReceived panics unless method was called `count` times
+    pub fn assert_received(&self, method: MockMethod, count: usize) {
+        let received = self.requests_of(method).len();
+        assert_eq!(received, count, "MockSynerexServer: {:?} received {} times, expected {}", method, received, count);
+    }
+
+    // WaitForRequests waits until method is called `count` times and returns the requests
+    // (panics when not reached within wait)
+    pub async fn wait_for_requests(&self, method: MockMethod, count: usize, wait: Duration) -> Vec<MockRequest> {
+        let deadline = tokio::time::Instant::now() + wait;
+        loop {
+            let received = self.shared.received.notified();
+            tokio::pin!(received);
+            received.as_mut().enable();
+            let requests = self.requests_of(method);
+            if requests.len() >= count {
+                return requests;
+            }
+            if tokio::time::timeout_at(deadline, received).await.is_err() {
+                panic!("MockSynerexServer: {:?} received {} times within {:?}, expected {}", method, requests.len(), wait, count);
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for MockSynerexServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("MockSynerexServer")
+            .field("transport", &self.transport)
+            .field("running", &self.is_running())
+            .finish()
+    }
+}
+
+impl Drop for MockSynerexServer {
+    fn drop(&mut self) {
+        if let Some(running) = self.running.get_mut().unwrap_or_else(PoisonError::into_inner).take() {
+            self.shared.lock().drop_streams(None, &Status::unavailable("MockSynerexServer dropped"));
+            running.cancel();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use crate::{DemandOpts, SXServiceClient, SupplyOpts, SxutilConfig, SxutilNode};
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    fn node() -> SxutilNode {
+        SxutilNode::with_config(SxutilConfig::default().with_reconnect_wait(Duration::from_millis(10)))
+    }
+
+    async fn service_client(server: &MockSynerexServer, channel_type: u32) -> SXServiceClient {
+        node().new_sx_service_client(server.connect().await.unwrap(), channel_type, String::new()).await
+    }
+
+    fn supply_opts(target: u64) -> SupplyOpts {
+        SupplyOpts { id: 0, target, name: String::from("mock"), json: String::new(), cdata: api::Content { entity: Vec::new() } }
+    }
+
+    fn demand_opts() -> DemandOpts {
+        DemandOpts { id: 0, target: 0, name: String::from("mock"), json: String::new(), cdata: api::Content { entity: Vec::new() } }
+    }
+
+    // raw supply subscription (registered when this returns)
+    async fn subscribe_supply(clt: &SXServiceClient) -> tonic::Streaming<api::Supply> {
+        let mut client = clt.sxclient.load().unwrap().client.clone();
+        client.subscribe_supply(clt.get_channel()).await.unwrap().into_inner()
+    }
+
+    async fn next_supply(stream: &mut tonic::Streaming<api::Supply>) -> api::Supply {
+        tokio::time::timeout(WAIT, stream.message()).await.unwrap().unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn notify_and_propose_reach_subscribers() {
+        let server = MockSynerexServer::start_in_memory().await;
+        let node = node(); // client ids from one snowflake generator
+        let mut clients = Vec::new();
+        for channel_type in [1, 1, 2] {
+            clients.push(node.new_sx_service_client(server.connect().await.unwrap(), channel_type, String::new()).await);
+        }
+        let (a, b, other) = (&clients[0], &clients[1], &clients[2]);
+        let (mut a_supplies, mut b_supplies) = (subscribe_supply(a).await, subscribe_supply(b).await);
+        let mut other_supplies = subscribe_supply(other).await;
+        assert_eq!(server.subscribers(MockMethod::SubscribeSupply), 3);
+
+        let id = a.notify_supply(supply_opts(0)).await.unwrap();
+        assert_eq!(next_supply(&mut a_supplies).await.id, id);
+        assert_eq!(next_supply(&mut b_supplies).await.id, id);
+        assert_eq!(server.notified_supplies().iter().map(|sp| sp.id).collect::<Vec<_>>(), vec![id]);
+
+        // proposal goes only to the sender of its target
+        let dm = a.notify_demand(demand_opts()).await.unwrap();
+        let proposed = b.propose_supply(&supply_opts(dm)).await.unwrap();
+        assert_eq!(next_supply(&mut a_supplies).await.id, proposed);
+        assert_eq!(server.publish_supply(api::Supply { id: 99, channel_type: 1, ..Default::default() }), 2);
+        assert_eq!(next_supply(&mut b_supplies).await.id, 99); // b didn't receive the proposal
+
+        // other channel type receives nothing but its own
+        assert_eq!(server.publish_supply(api::Supply { id: 100, channel_type: 2, ..Default::default() }), 1);
+        assert_eq!(next_supply(&mut other_supplies).await.id, 100);
+    }
+
+    #[tokio::test]
+    async fn fail_times_fails_next_calls() {
+        let server = MockSynerexServer::start_in_memory().await;
+        let clt = service_client(&server, 1).await;
+        server.fail_times(MockMethod::NotifySupply, Status::unavailable("injected"), 2);
+
+        for _ in 0..2 {
+            match clt.notify_supply(supply_opts(0)).await {
+                Err(SxutilError::Grpc(status)) => assert_eq!(status.code(), tonic::Code::Unavailable),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert!(clt.notify_supply(supply_opts(0)).await.is_ok());
+        assert_eq!(server.requests_of(MockMethod::NotifySupply).len(), 3); // failed calls are recorded
+        assert_eq!(server.notified_supplies().len(), 3);
+
+        server.fail(MockMethod::NotifyDemand, Status::internal("always"));
+        assert!(clt.notify_demand(demand_opts()).await.is_err());
+        assert!(clt.notify_demand(demand_opts()).await.is_err());
+        server.clear_faults();
+        assert!(clt.notify_demand(demand_opts()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn latency_exceeds_msg_timeout() {
+        let server = MockSynerexServer::start_in_memory().await;
+        let clt = service_client(&server, 1).await;
+        server.set_latency(MockMethod::NotifySupply, Duration::from_millis(300));
+
+        let timeout = clt.notify_supply_with_timeout(supply_opts(0), Duration::from_millis(50)).await;
+        assert!(matches!(timeout, Err(SxutilError::Timeout)), "{:?}", timeout);
+        assert!(clt.notify_supply_with_timeout(supply_opts(0), WAIT).await.is_ok());
+        assert!(clt.notify_demand_with_timeout(demand_opts(), Duration::from_millis(50)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn dropped_stream_is_subscribed_again() {
+        let server = MockSynerexServer::start().await.unwrap();
+        let clt = service_client(&server, 1).await;
+        let mut supplies = std::pin::pin!(clt.supply_stream());
+
+        let received = async {
+            server.wait_for_requests(MockMethod::SubscribeSupply, 1, WAIT).await;
+            server.publish_supply(api::Supply { id: 1, channel_type: 1, ..Default::default() });
+        };
+        let (sp, ()) = tokio::join!(tokio::time::timeout(WAIT, supplies.next()), received);
+        assert_eq!(sp.unwrap().unwrap().unwrap().id, 1);
+
+        server.drop_streams_of(MockMethod::SubscribeSupply, Status::unavailable("dropped"));
+        match tokio::time::timeout(WAIT, supplies.next()).await.unwrap() {
+            Some(Err(SxutilError::Grpc(status))) => assert_eq!(status.message(), "dropped"),
+            other => panic!("unexpected {:?}", other),
+        }
+        let received = async {
+            server.wait_for_requests(MockMethod::SubscribeSupply, 2, WAIT).await;
+            while server.subscribers(MockMethod::SubscribeSupply) == 0 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+            server.publish_supply(api::Supply { id: 2, channel_type: 1, ..Default::default() });
+        };
+        let (sp, ()) = tokio::join!(tokio::time::timeout(WAIT, supplies.next()), received);
+        assert_eq!(sp.unwrap().unwrap().unwrap().id, 2);
+    }
+
+    #[tokio::test]
+    async fn in_memory_transport_survives_restart() {
+        let server = MockSynerexServer::start_in_memory().await;
+        assert_eq!(server.address(), IN_MEMORY_ADDRESS);
+        let clt = service_client(&server, 1).await;
+        assert!(clt.notify_supply(supply_opts(0)).await.is_ok());
+
+        server.shutdown().await;
+        assert!(!server.is_running());
+        assert!(server.connect().await.is_err());
+        assert!(clt.notify_supply(supply_opts(0)).await.is_err());
+
+        server.restart().await.unwrap();
+        assert!(server.is_running());
+        let mut sent = false;
+        for _ in 0..50 { // channel connects again on next calls
+            if clt.notify_supply(supply_opts(0)).await.is_ok() {
+                sent = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(sent);
+        assert!(server.connect().await.is_ok());
+    }
+}
@@ -0,0 +1,26 @@
+#!/bin/sh
+# generates PEM fixtures of TLS tests (CA, server certificate for localhost / 127.0.0.1 and client certificate)
+set -e
+cd "$(dirname "$0")"
+OPENSSL=${OPENSSL:-openssl}
+DAYS=36500
+tmp=$(mktemp -d)
+trap 'rm -rf "$tmp"' EXIT
+
+$OPENSSL req -x509 -newkey ec -pkeyopt ec_paramgen_curve:prime256v1 -nodes -keyout "$tmp/ca.key" -out ca.pem -days $DAYS \
+  -subj "/CN=sxutil test CA" -addext "basicConstraints=critical,CA:TRUE" -addext "keyUsage=critical,keyCertSign,cRLSign"
+
+cert() { # name subject extensions
+  $OPENSSL genpkey -algorithm ec -pkeyopt ec_paramgen_curve:prime256v1 -out "$1.key"
+  $OPENSSL req -new -key "$1.key" -subj "$2" -out "$tmp/$1.csr"
+  printf '%s\n' "$3" > "$tmp/$1.ext"
+  $OPENSSL x509 -req -in "$tmp/$1.csr" -CA ca.pem -CAkey "$tmp/ca.key" -CAcreateserial -CAserial "$tmp/ca.srl" \
+    -days $DAYS -extfile "$tmp/$1.ext" -out "$1.pem"
+}
+cert server "/CN=localhost" "basicConstraints=CA:FALSE
+keyUsage=critical,digitalSignature
+extendedKeyUsage=serverAuth
+subjectAltName=DNS:localhost,IP:127.0.0.1"
+cert client "/CN=sxutil test client" "basicConstraints=CA:FALSE
+keyUsage=critical,digitalSignature
+extendedKeyUsage=clientAuth"